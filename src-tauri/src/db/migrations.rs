@@ -1,9 +1,14 @@
 use crate::error::SunderError;
 use rusqlite::Connection;
 
+/// A migration step that depends on what's in the database.
+type MigrationFn = fn(&Connection) -> Result<(), SunderError>;
+
 struct Migration {
     version: u32,
     sql: &'static str,
+    /// Run after `sql`.
+    run: Option<MigrationFn>,
 }
 
 const MIGRATIONS: &[Migration] = &[
@@ -23,6 +28,7 @@ const MIGRATIONS: &[Migration] = &[
             CREATE INDEX IF NOT EXISTS idx_notes_updated_at ON notes(updated_at DESC);
             CREATE INDEX IF NOT EXISTS idx_notes_file_path ON notes(file_path);
         ",
+        run: None,
     },
     Migration {
         version: 2,
@@ -48,6 +54,7 @@ const MIGRATIONS: &[Migration] = &[
                 INSERT INTO notes_fts(rowid, title, content) VALUES (new.rowid, new.title, new.content);
             END;
         ",
+        run: None,
     },
     Migration {
         version: 3,
@@ -59,6 +66,7 @@ const MIGRATIONS: &[Migration] = &[
                 updated_at TEXT NOT NULL
             );
         ",
+        run: None,
     },
     Migration {
         version: 4,
@@ -68,6 +76,7 @@ const MIGRATIONS: &[Migration] = &[
                 embedding float[384]
             );
         ",
+        run: None,
     },
     Migration {
         version: 5,
@@ -93,6 +102,299 @@ const MIGRATIONS: &[Migration] = &[
             INSERT OR IGNORE INTO settings (key, value) VALUES ('debounce_ms', '300');
             INSERT OR IGNORE INTO settings (key, value) VALUES ('theme', 'dark');
         ",
+        run: None,
+    },
+    Migration {
+        version: 6,
+        sql: "
+            ALTER TABLE notes ADD COLUMN file_hash TEXT;
+        ",
+        run: None,
+    },
+    Migration {
+        version: 7,
+        sql: "
+            CREATE TABLE IF NOT EXISTS deleted_notes (
+                id TEXT PRIMARY KEY,
+                title TEXT NOT NULL,
+                content TEXT NOT NULL,
+                file_path TEXT,
+                trash_path TEXT,
+                word_count INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                deleted_at TEXT NOT NULL,
+                embedding BLOB,
+                model_version TEXT
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_deleted_notes_deleted_at ON deleted_notes(deleted_at);
+
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('trash_retention_days', '30');
+        ",
+        run: None,
+    },
+    Migration {
+        version: 8,
+        sql: "
+            CREATE TABLE IF NOT EXISTS note_revisions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                note_id TEXT NOT NULL,
+                title TEXT NOT NULL,
+                content TEXT NOT NULL,
+                source TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_note_revisions_note ON note_revisions(note_id, id DESC);
+
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('revision_max_per_note', '50');
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('revision_max_age_days', '90');
+        ",
+        run: None,
+    },
+    Migration {
+        version: 9,
+        sql: "
+            ALTER TABLE notes ADD COLUMN metadata TEXT;
+            ALTER TABLE deleted_notes ADD COLUMN metadata TEXT;
+        ",
+        run: None,
+    },
+    Migration {
+        version: 10,
+        sql: "
+            CREATE TABLE IF NOT EXISTS tags (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE
+            );
+
+            CREATE TABLE IF NOT EXISTS note_tags (
+                note_id TEXT NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+                tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+                source TEXT NOT NULL,
+                PRIMARY KEY (note_id, tag_id)
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_note_tags_tag ON note_tags(tag_id);
+        ",
+        run: None,
+    },
+    Migration {
+        version: 11,
+        sql: "
+            CREATE TABLE IF NOT EXISTS note_aliases (
+                note_id TEXT NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+                alias TEXT NOT NULL COLLATE NOCASE,
+                PRIMARY KEY (note_id, alias)
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_note_aliases_alias ON note_aliases(alias);
+
+            CREATE TABLE IF NOT EXISTS note_links (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                source_id TEXT NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+                target_id TEXT REFERENCES notes(id) ON DELETE SET NULL,
+                target_text TEXT NOT NULL,
+                heading TEXT,
+                alias TEXT,
+                start_offset INTEGER NOT NULL,
+                end_offset INTEGER NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_note_links_source ON note_links(source_id);
+            CREATE INDEX IF NOT EXISTS idx_note_links_target ON note_links(target_id);
+            CREATE INDEX IF NOT EXISTS idx_note_links_unresolved
+                ON note_links(target_text COLLATE NOCASE) WHERE target_id IS NULL;
+            CREATE INDEX IF NOT EXISTS idx_notes_title_nocase ON notes(title COLLATE NOCASE);
+        ",
+        run: None,
+    },
+    Migration {
+        version: 12,
+        sql: "
+            -- Describes the vec_embeddings table created in v4; the embedding
+            -- service recreates it when a model with another dimension is loaded
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('embedding_model', 'minilm-v2-q8');
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('embedding_dimension', '384');
+        ",
+        run: None,
+    },
+    Migration {
+        version: 13,
+        sql: "
+            -- Passage embeddings; vec_chunks is created by the embedding service
+            -- at the loaded model's dimension
+            CREATE TABLE IF NOT EXISTS note_chunks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                note_id TEXT NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+                chunk_index INTEGER NOT NULL,
+                start_offset INTEGER NOT NULL,
+                end_offset INTEGER NOT NULL,
+                vector BLOB NOT NULL,
+                model_version TEXT NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_note_chunks_note ON note_chunks(note_id);
+        ",
+        run: None,
+    },
+    Migration {
+        version: 14,
+        sql: "
+            -- Chunks from before v14 were fixed token windows; the markdown
+            -- default makes them stale so they get re-embedded
+            ALTER TABLE note_chunks ADD COLUMN chunking TEXT NOT NULL DEFAULT 'window';
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('chunking_policy', 'markdown');
+        ",
+        run: None,
+    },
+    Migration {
+        version: 15,
+        sql: "
+            -- Plain text indexed by notes_fts instead of the markdown, when the
+            -- fts_plain_text setting is on
+            ALTER TABLE notes ADD COLUMN fts_content TEXT;
+
+            DROP TRIGGER IF EXISTS notes_ai;
+            DROP TRIGGER IF EXISTS notes_ad;
+            DROP TRIGGER IF EXISTS notes_au;
+
+            CREATE TRIGGER notes_ai AFTER INSERT ON notes BEGIN
+                INSERT INTO notes_fts(rowid, title, content)
+                    VALUES (new.rowid, new.title, COALESCE(new.fts_content, new.content));
+            END;
+
+            CREATE TRIGGER notes_ad AFTER DELETE ON notes BEGIN
+                INSERT INTO notes_fts(notes_fts, rowid, title, content)
+                    VALUES('delete', old.rowid, old.title, COALESCE(old.fts_content, old.content));
+            END;
+
+            CREATE TRIGGER notes_au AFTER UPDATE ON notes BEGIN
+                INSERT INTO notes_fts(notes_fts, rowid, title, content)
+                    VALUES('delete', old.rowid, old.title, COALESCE(old.fts_content, old.content));
+                INSERT INTO notes_fts(rowid, title, content)
+                    VALUES (new.rowid, new.title, COALESCE(new.fts_content, new.content));
+            END;
+
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('plain_text_code_blocks', 'drop');
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('plain_text_links', 'text');
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('fts_plain_text', 'false');
+        ",
+        run: None,
+    },
+    Migration {
+        version: 16,
+        sql: "
+            -- Bulk indexing sessions; read when the embedding service starts
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('embedding_sessions', '1');
+        ",
+        run: None,
+    },
+    Migration {
+        version: 17,
+        sql: "
+            -- Notes waiting to be embedded, one row per note; version is bumped
+            -- when a waiting note is queued again
+            CREATE TABLE IF NOT EXISTS index_jobs (
+                note_id TEXT PRIMARY KEY REFERENCES notes(id) ON DELETE CASCADE,
+                priority INTEGER NOT NULL,
+                version INTEGER NOT NULL DEFAULT 0,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                enqueued_at TEXT NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_index_jobs_order ON index_jobs(priority, enqueued_at);
+        ",
+        run: None,
+    },
+    Migration {
+        version: 18,
+        sql: "
+            -- Hash of what each embedding was made from (model, chunking and
+            -- content), to skip unchanged notes and reuse identical ones
+            ALTER TABLE embeddings ADD COLUMN content_hash TEXT;
+            CREATE INDEX IF NOT EXISTS idx_embeddings_content_hash ON embeddings(content_hash);
+
+            -- Jobs that re-embed even when the hash matches
+            ALTER TABLE index_jobs ADD COLUMN force INTEGER NOT NULL DEFAULT 0;
+        ",
+        run: None,
+    },
+    Migration {
+        version: 19,
+        sql: "
+            -- notes_fts reads back the text it indexed, so snippet() and
+            -- highlight() agree with the index when fts_plain_text is on
+            CREATE VIEW IF NOT EXISTS notes_fts_source AS
+                SELECT rowid AS rowid, title, COALESCE(fts_content, content) AS content FROM notes;
+
+            DROP TABLE IF EXISTS notes_fts;
+
+            CREATE VIRTUAL TABLE notes_fts USING fts5(
+                title,
+                content,
+                content=notes_fts_source,
+                content_rowid=rowid,
+                tokenize='unicode61'
+            );
+
+            INSERT INTO notes_fts(notes_fts) VALUES('rebuild');
+        ",
+        run: None,
+    },
+    Migration {
+        version: 20,
+        sql: "
+            -- How hybrid search fuses its rankings
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('search_fusion', 'rrf');
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('search_rrf_k', '60');
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('search_fulltext_weight', '1');
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('search_semantic_weight', '1');
+        ",
+        run: None,
+    },
+    Migration {
+        version: 21,
+        sql: "
+            -- Semantic matches less similar than this are left out of search
+            INSERT OR IGNORE INTO settings (key, value) VALUES ('search_min_similarity', '0.2');
+        ",
+        run: None,
+    },
+    Migration {
+        version: 22,
+        sql: "
+            -- Passage chunks and embedding hash of trashed notes, put back on restore
+            CREATE TABLE IF NOT EXISTS deleted_note_chunks (
+                note_id TEXT NOT NULL REFERENCES deleted_notes(id) ON DELETE CASCADE,
+                chunk_index INTEGER NOT NULL,
+                start_offset INTEGER NOT NULL,
+                end_offset INTEGER NOT NULL,
+                vector BLOB NOT NULL,
+                model_version TEXT NOT NULL,
+                chunking TEXT NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_deleted_note_chunks_note ON deleted_note_chunks(note_id);
+
+            ALTER TABLE deleted_notes ADD COLUMN content_hash TEXT;
+        ",
+        run: None,
+    },
+    Migration {
+        version: 23,
+        // vec_chunks was only created once a model loaded, so deleting a note
+        // failed on a database that had never had one
+        sql: "",
+        run: Some(create_chunk_vector_table),
+    },
+    Migration {
+        version: 24,
+        // Vectors are unit length and ranked by cosine distance; the tables
+        // from v4 and v23 left sqlite-vec comparing them by L2
+        sql: "",
+        run: Some(recreate_cosine_vector_tables),
     },
 ];
 
@@ -118,6 +420,9 @@ pub fn run_all(conn: &Connection) -> Result<u32, SunderError> {
 
         if !already_applied {
             conn.execute_batch(migration.sql)?;
+            if let Some(run) = migration.run {
+                run(conn)?;
+            }
             conn.execute(
                 "INSERT INTO migrations (version, applied_at) VALUES (?1, datetime('now'))",
                 [migration.version],
@@ -133,3 +438,57 @@ pub fn run_all(conn: &Connection) -> Result<u32, SunderError> {
 
     Ok(applied)
 }
+
+/// The dimension of the vectors in the vector tables, as recorded in v12 and
+/// kept up to date by the embedding service.
+fn recorded_dimension(conn: &Connection) -> Result<usize, SunderError> {
+    let value: String = conn.query_row(
+        "SELECT value FROM settings WHERE key = 'embedding_dimension'",
+        [],
+        |row| row.get(0),
+    )?;
+    value
+        .parse()
+        .map_err(|_| SunderError::DatabaseError(format!("Invalid embedding_dimension: {value}")))
+}
+
+fn create_chunk_vector_table(conn: &Connection) -> Result<(), SunderError> {
+    conn.execute_batch(&format!(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS vec_chunks USING vec0(
+             chunk_id INTEGER PRIMARY KEY,
+             embedding float[{}] distance_metric=cosine
+         );",
+        recorded_dimension(conn)?
+    ))?;
+    Ok(())
+}
+
+/// Recreate both vector tables comparing by cosine distance and refill them
+/// with the stored vectors of the recorded model.
+fn recreate_cosine_vector_tables(conn: &Connection) -> Result<(), SunderError> {
+    let dimension = recorded_dimension(conn)?;
+    conn.execute_batch(&format!(
+        "DROP TABLE IF EXISTS vec_embeddings;
+         CREATE VIRTUAL TABLE vec_embeddings USING vec0(
+             note_id TEXT PRIMARY KEY,
+             embedding float[{dimension}] distance_metric=cosine
+         );
+         DROP TABLE IF EXISTS vec_chunks;
+         CREATE VIRTUAL TABLE vec_chunks USING vec0(
+             chunk_id INTEGER PRIMARY KEY,
+             embedding float[{dimension}] distance_metric=cosine
+         );
+
+         INSERT INTO vec_embeddings (note_id, embedding)
+         SELECT note_id, vector FROM embeddings
+         WHERE model_version = (SELECT value FROM settings WHERE key = 'embedding_model')
+           AND length(vector) = {bytes};
+
+         INSERT INTO vec_chunks (chunk_id, embedding)
+         SELECT id, vector FROM note_chunks
+         WHERE model_version = (SELECT value FROM settings WHERE key = 'embedding_model')
+           AND length(vector) = {bytes};",
+        bytes = dimension * 4
+    ))?;
+    Ok(())
+}
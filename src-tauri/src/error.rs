@@ -9,10 +9,12 @@ pub enum SunderError {
     ValidationError(String),
     DatabaseError(String),
     EmbeddingError(String),
+    EmbeddingUnavailable(String),
     IoError(String),
     Internal(String),
     ContentTooLarge(String),
     EmptyQuery,
+    InvalidQuery(String),
     ContentTooShort(String),
     AlreadyRunning,
     DirectoryNotFound(String),
@@ -29,10 +31,14 @@ impl fmt::Display for SunderError {
             SunderError::ValidationError(msg) => write!(f, "Validation error: {msg}"),
             SunderError::DatabaseError(msg) => write!(f, "Database error: {msg}"),
             SunderError::EmbeddingError(msg) => write!(f, "Embedding error: {msg}"),
+            SunderError::EmbeddingUnavailable(msg) => {
+                write!(f, "Embedding model unavailable: {msg}")
+            }
             SunderError::IoError(msg) => write!(f, "IO error: {msg}"),
             SunderError::Internal(msg) => write!(f, "Internal error: {msg}"),
             SunderError::ContentTooLarge(msg) => write!(f, "Content too large: {msg}"),
             SunderError::EmptyQuery => write!(f, "Query cannot be empty"),
+            SunderError::InvalidQuery(msg) => write!(f, "Invalid query: {msg}"),
             SunderError::ContentTooShort(msg) => write!(f, "Content too short: {msg}"),
             SunderError::AlreadyRunning => write!(f, "Operation already running"),
             SunderError::DirectoryNotFound(msg) => write!(f, "Directory not found: {msg}"),
@@ -4,13 +4,24 @@ pub mod services;
 
 use db::DatabaseManager;
 use error::SunderError;
-use services::embedding::EmbeddingService;
+use services::embedding::{EmbeddingSlot, EmbeddingStatus};
 use services::file_watcher::FileWatcherService;
+use services::folders::{FolderNode, FolderService};
 use services::graph::{GraphData, GraphService};
+use services::index_queue::{IndexQueueService, JobPriority, QueueStatus};
 use services::link::{LatentLink, LinkService};
+use services::mention::{MentionService, UnlinkedMention};
 use services::note::{Note, NoteList, NoteService};
-use services::search::{SearchMode, SearchResult, SearchService};
+use services::passage::ScoreAggregation;
+use services::search::{
+    PageRequest, PageStart, SearchMode, SearchOptions, SearchPage, SearchService,
+};
+use services::revision::{RevisionDiff, RevisionService, RevisionSummary};
 use services::settings::{Settings, SettingsPatch, SettingsService};
+use services::tags::{TagInfo, TagService};
+use services::trash::{TrashService, TrashedNote};
+use services::wikilink::{NoteLink, WikilinkService};
+use std::path::Path;
 use std::sync::Arc;
 use tauri::Emitter;
 use tauri::Manager;
@@ -21,7 +32,14 @@ use tauri::State;
 pub struct AppState {
     pub note_service: NoteService,
     pub settings_service: SettingsService,
-    pub embedding_service: Arc<EmbeddingService>,
+    pub trash_service: TrashService,
+    pub revision_service: RevisionService,
+    pub tag_service: TagService,
+    pub folder_service: FolderService,
+    pub wikilink_service: WikilinkService,
+    pub mention_service: MentionService,
+    pub embedding: Arc<EmbeddingSlot>,
+    pub index_queue: Arc<IndexQueueService>,
     pub search_service: SearchService,
     pub link_service: LinkService,
     pub graph_service: GraphService,
@@ -29,6 +47,28 @@ pub struct AppState {
     pub db: Arc<DatabaseManager>,
 }
 
+/// Queue notes whose vectors are missing, from another model or chunked with
+/// other settings to be re-embedded.
+fn queue_stale(queue: &IndexQueueService) {
+    match queue.enqueue_stale() {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Queued {count} stale note(s) for re-embedding"),
+        Err(e) => tracing::error!("Failed to queue stale notes: {e}"),
+    }
+}
+
+/// Queue an edited note to have its embedding and graph cache entries rebuilt
+/// in the background, ahead of any bulk indexing.
+fn index_in_background(state: &AppState, note: &Note) {
+    if note.content.split_whitespace().count() < 3 {
+        return;
+    }
+
+    if let Err(e) = state.index_queue.enqueue(&note.id, JobPriority::Interactive) {
+        tracing::error!("Failed to queue note {} for indexing: {}", note.id, e);
+    }
+}
+
 // --- Tauri IPC Commands ---
 
 #[tauri::command]
@@ -39,25 +79,7 @@ fn create_note(
     file_path: Option<String>,
 ) -> Result<Note, SunderError> {
     let note = state.note_service.create_note(title, content, file_path)?;
-
-    // Index embedding + rebuild graph cache in background
-    if note.content.split_whitespace().count() >= 3 {
-        let emb = Arc::clone(&state.embedding_service);
-        let db = Arc::clone(&state.db);
-        let note_id = note.id.clone();
-        let note_content = note.content.clone();
-        std::thread::spawn(move || {
-            if let Err(e) = emb.index_note(&note_id, &note_content) {
-                tracing::error!("Failed to index note {}: {}", note_id, e);
-                return;
-            }
-            let graph_svc = GraphService::new(Arc::clone(&db), Arc::clone(&emb));
-            if let Err(e) = graph_svc.rebuild_cache_for_note(&note_id) {
-                tracing::error!("Failed to rebuild graph cache for {}: {}", note_id, e);
-            }
-        });
-    }
-
+    index_in_background(&state, &note);
     Ok(note)
 }
 
@@ -73,50 +95,177 @@ fn update_note(
     title: Option<String>,
     content: Option<String>,
 ) -> Result<Note, SunderError> {
+    // A title change leaves the embedding as it is
+    let content_changed = content.is_some();
     let note = state.note_service.update_note(&id, title, content)?;
-
-    // Re-index embedding + rebuild graph cache in background
-    if note.content.split_whitespace().count() >= 3 {
-        let emb = Arc::clone(&state.embedding_service);
-        let db = Arc::clone(&state.db);
-        let note_id = note.id.clone();
-        let note_content = note.content.clone();
-        std::thread::spawn(move || {
-            if let Err(e) = emb.index_note(&note_id, &note_content) {
-                tracing::error!("Failed to re-index note {}: {}", note_id, e);
-                return;
-            }
-            let graph_svc = GraphService::new(Arc::clone(&db), Arc::clone(&emb));
-            if let Err(e) = graph_svc.rebuild_cache_for_note(&note_id) {
-                tracing::error!("Failed to rebuild graph cache for {}: {}", note_id, e);
-            }
-        });
+    if content_changed {
+        index_in_background(&state, &note);
     }
+    Ok(note)
+}
 
+#[tauri::command]
+fn move_note(
+    state: State<'_, AppState>,
+    id: String,
+    new_title: Option<String>,
+    new_path: Option<String>,
+) -> Result<Note, SunderError> {
+    // Same id, so the embedding and graph cache stay valid for notes whose
+    // links didn't have to be rewritten
+    let (note, rewritten) = state.note_service.move_note(&id, new_title, new_path)?;
+    let (own, linking): (Vec<String>, Vec<String>) = rewritten
+        .into_iter()
+        .partition(|rewritten_id| *rewritten_id == note.id);
+    if !own.is_empty() {
+        index_in_background(&state, &note);
+    }
+    if let Err(e) = state.index_queue.enqueue_many(&linking, JobPriority::Bulk) {
+        tracing::error!("Failed to queue relinked notes for indexing: {e}");
+    }
     Ok(note)
 }
 
 #[tauri::command]
 fn delete_note(state: State<'_, AppState>, id: String) -> Result<(), SunderError> {
-    // Remove embedding first
-    let _ = state.embedding_service.remove_embedding(&id);
+    // Moves the note, its file and its embedding to the trash
     state.note_service.delete_note(&id)
 }
 
+#[tauri::command]
+fn list_trash(state: State<'_, AppState>) -> Result<Vec<TrashedNote>, SunderError> {
+    state.trash_service.list_trash()
+}
+
+#[tauri::command]
+fn restore_note(state: State<'_, AppState>, id: String) -> Result<Note, SunderError> {
+    let note = state.trash_service.restore_note(&id)?;
+    // The queue skips re-embedding when the restored embedding still matches,
+    // and rebuilds the similarity cache entries dropped on delete either way
+    index_in_background(&state, &note);
+    Ok(note)
+}
+
+#[tauri::command]
+fn empty_trash(state: State<'_, AppState>) -> Result<u32, SunderError> {
+    state.trash_service.empty_trash()
+}
+
+#[tauri::command]
+fn list_revisions(
+    state: State<'_, AppState>,
+    note_id: String,
+) -> Result<Vec<RevisionSummary>, SunderError> {
+    state.revision_service.list_revisions(&note_id)
+}
+
+#[tauri::command]
+fn diff_revisions(
+    state: State<'_, AppState>,
+    note_id: String,
+    from_revision_id: i64,
+    to_revision_id: Option<i64>,
+) -> Result<RevisionDiff, SunderError> {
+    state
+        .revision_service
+        .diff_revisions(&note_id, from_revision_id, to_revision_id)
+}
+
+#[tauri::command]
+fn restore_revision(
+    state: State<'_, AppState>,
+    note_id: String,
+    revision_id: i64,
+) -> Result<Note, SunderError> {
+    let note = state
+        .revision_service
+        .restore_revision(&note_id, revision_id)?;
+    index_in_background(&state, &note);
+    Ok(note)
+}
+
 #[tauri::command]
 fn list_notes(
     state: State<'_, AppState>,
     offset: Option<u32>,
     limit: Option<u32>,
     sort_by: Option<String>,
+    tag: Option<String>,
+    folder: Option<String>,
+    recursive: Option<bool>,
 ) -> Result<NoteList, SunderError> {
     state.note_service.list_notes(
         offset.unwrap_or(0),
         limit.unwrap_or(50),
         &sort_by.unwrap_or_else(|| "updated_at".to_string()),
+        tag.as_deref(),
+        folder.as_deref(),
+        recursive.unwrap_or(false),
     )
 }
 
+#[tauri::command]
+fn get_note_tree(
+    state: State<'_, AppState>,
+    folder: Option<String>,
+    depth: Option<u32>,
+) -> Result<FolderNode, SunderError> {
+    state
+        .folder_service
+        .get_note_tree(folder.as_deref(), depth.unwrap_or(1))
+}
+
+#[tauri::command]
+fn list_tags(state: State<'_, AppState>) -> Result<Vec<TagInfo>, SunderError> {
+    state.tag_service.list_tags()
+}
+
+#[tauri::command]
+fn rename_tag(state: State<'_, AppState>, from: String, to: String) -> Result<u32, SunderError> {
+    let changed = state.tag_service.rename_tag(&from, &to)?;
+    // The rewritten notes' embeddings are out of date
+    if let Err(e) = state.index_queue.enqueue_many(&changed, JobPriority::Bulk) {
+        tracing::error!("Failed to queue renamed-tag notes for indexing: {e}");
+    }
+    Ok(changed.len() as u32)
+}
+
+#[tauri::command]
+fn get_backlinks(state: State<'_, AppState>, note_id: String) -> Result<Vec<NoteLink>, SunderError> {
+    state.wikilink_service.get_backlinks(&note_id)
+}
+
+#[tauri::command]
+fn get_outgoing_links(
+    state: State<'_, AppState>,
+    note_id: String,
+) -> Result<Vec<NoteLink>, SunderError> {
+    state.wikilink_service.get_outgoing_links(&note_id)
+}
+
+#[tauri::command]
+fn get_unlinked_mentions(
+    state: State<'_, AppState>,
+    note_id: String,
+) -> Result<Vec<UnlinkedMention>, SunderError> {
+    state.mention_service.get_unlinked_mentions(&note_id)
+}
+
+#[tauri::command]
+fn link_mention(
+    state: State<'_, AppState>,
+    source_id: String,
+    target_id: String,
+    start: usize,
+    end: usize,
+) -> Result<Note, SunderError> {
+    let note = state
+        .mention_service
+        .link_mention(&source_id, &target_id, start, end)?;
+    index_in_background(&state, &note);
+    Ok(note)
+}
+
 #[tauri::command]
 fn get_settings(state: State<'_, AppState>) -> Result<Settings, SunderError> {
     state.settings_service.get_settings()
@@ -127,49 +276,105 @@ fn update_settings(
     state: State<'_, AppState>,
     settings: SettingsPatch,
 ) -> Result<(), SunderError> {
-    state.settings_service.update_settings(settings)
+    let reembed = settings.affects_embeddings();
+    let refresh_fts = settings.affects_fts();
+    state.settings_service.update_settings(settings)?;
+    if refresh_fts {
+        let count = state.note_service.refresh_fts_content()?;
+        tracing::info!("Reindexed full-text content of {count} note(s)");
+    }
+    // Notes chunked with the old settings are now stale
+    if reembed {
+        queue_stale(&state.index_queue);
+    }
+    Ok(())
 }
 
 #[tauri::command]
 fn reindex_all(
     state: State<'_, AppState>,
-    app_handle: tauri::AppHandle,
+    force: Option<bool>,
 ) -> Result<serde_json::Value, SunderError> {
-    let emb = Arc::clone(&state.embedding_service);
-    let handle = app_handle.clone();
-
-    std::thread::spawn(move || {
-        let result = emb.reindex_all(|processed, total, title| {
-            let _ = handle.emit(
-                "indexing-progress",
-                serde_json::json!({
-                    "processed": processed,
-                    "total": total,
-                    "current_note_title": title,
-                }),
-            );
-        });
-        match result {
-            Ok(count) => tracing::info!("Reindex complete: {count} notes indexed"),
-            Err(e) => tracing::error!("Reindex failed: {e}"),
-        }
-    });
+    // Without force, notes whose content hash is unchanged are skipped
+    let count = state.index_queue.enqueue_all(force.unwrap_or(false))?;
+    tracing::info!("Queued {count} note(s) for reindexing");
 
     // Return immediately — progress is reported via events
     Ok(serde_json::json!({ "queued": true }))
 }
 
 #[tauri::command]
+fn pause_reindex(state: State<'_, AppState>) -> Result<(), SunderError> {
+    state.index_queue.pause()
+}
+
+#[tauri::command]
+fn resume_reindex(state: State<'_, AppState>) -> Result<(), SunderError> {
+    state.index_queue.resume()
+}
+
+/// Drop queued bulk indexing. Returns the number of notes dropped.
+#[tauri::command]
+fn cancel_reindex(state: State<'_, AppState>) -> Result<u32, SunderError> {
+    state.index_queue.cancel()
+}
+
+#[tauri::command]
+fn get_index_queue(state: State<'_, AppState>) -> Result<QueueStatus, SunderError> {
+    state.index_queue.status()
+}
+
+#[tauri::command]
+fn get_embedding_status(state: State<'_, AppState>) -> EmbeddingStatus {
+    state.embedding.status()
+}
+
+/// Load the embedding model in `directory` in place of the current one (or
+/// none), and use it on later launches too. Notes are re-embedded with it in
+/// the background.
+#[tauri::command]
+fn load_embedding_model(
+    state: State<'_, AppState>,
+    directory: String,
+    app_handle: tauri::AppHandle,
+) -> Result<EmbeddingStatus, SunderError> {
+    let loaded = state
+        .embedding
+        .replace(Path::new(&directory), Arc::clone(&state.db));
+    let _ = app_handle.emit("embedding-status", state.embedding.status());
+    let status = loaded?;
+
+    state.settings_service.set_model_directory(Some(&directory))?;
+    queue_stale(&state.index_queue);
+    Ok(status)
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
 fn search_notes(
     state: State<'_, AppState>,
     query: String,
     mode: Option<SearchMode>,
     limit: Option<u32>,
-) -> Result<Vec<SearchResult>, SunderError> {
+    page: Option<PageStart>,
+    tag: Option<String>,
+    aggregation: Option<ScoreAggregation>,
+    min_similarity: Option<f64>,
+    explain: Option<bool>,
+) -> Result<SearchPage, SunderError> {
     state.search_service.search(
         &query,
         &mode.unwrap_or(SearchMode::Hybrid),
-        limit.unwrap_or(20),
+        &PageRequest {
+            limit: limit.unwrap_or(20),
+            start: page.unwrap_or(PageStart::Offset(0)),
+        },
+        tag.as_deref(),
+        &SearchOptions {
+            aggregation: aggregation.unwrap_or_default(),
+            min_similarity,
+            explain: explain.unwrap_or(false),
+        },
     )
 }
 
@@ -180,12 +385,14 @@ fn get_latent_links(
     exclude_note_id: Option<String>,
     threshold: Option<f64>,
     limit: Option<u32>,
+    aggregation: Option<ScoreAggregation>,
 ) -> Result<Vec<LatentLink>, SunderError> {
     state.link_service.compute_latent_links(
         &content,
         exclude_note_id.as_deref(),
         threshold.unwrap_or(0.3),
         limit.unwrap_or(5),
+        aggregation.unwrap_or_default(),
     )
 }
 
@@ -214,12 +421,21 @@ fn set_watch_directory(
 ) -> Result<(), SunderError> {
     state
         .file_watcher_service
-        .start_watching(&directory, app_handle)
+        .start_watching(&directory, app_handle)?;
+
+    // Remember the vault so new notes get files and watching resumes on launch
+    if let Some(dir) = state.file_watcher_service.watch_dir() {
+        state
+            .settings_service
+            .set_watch_directory(Some(&dir.to_string_lossy()))?;
+    }
+    Ok(())
 }
 
 #[tauri::command]
 fn stop_watching(state: State<'_, AppState>) -> Result<(), SunderError> {
-    state.file_watcher_service.stop_watching()
+    state.file_watcher_service.stop_watching()?;
+    state.settings_service.set_watch_directory(None)
 }
 
 #[tauri::command]
@@ -269,25 +485,56 @@ pub fn run() {
 
             let note_service = NoteService::new(Arc::clone(&db));
             let settings_service = SettingsService::new(Arc::clone(&db));
+            let trash_service = TrashService::new(Arc::clone(&db));
+            let revision_service = RevisionService::new(Arc::clone(&db));
+            let tag_service = TagService::new(Arc::clone(&db));
+            let folder_service = FolderService::new(Arc::clone(&db));
+            let wikilink_service = WikilinkService::new(Arc::clone(&db));
+            let mention_service = MentionService::new(Arc::clone(&db));
+
+            if let Err(e) = trash_service.purge_expired() {
+                tracing::warn!("Failed to purge expired trash: {e}");
+            }
+            if let Err(e) = note_service.backfill_derived() {
+                tracing::warn!("Failed to rebuild derived note data: {e}");
+            }
 
-            let embedding_service = Arc::new(
-                EmbeddingService::new(&resource_dir, Arc::clone(&db))
-                    .expect("Failed to initialize embedding service"),
-            );
-
-            let search_service =
-                SearchService::new(Arc::clone(&db), Arc::clone(&embedding_service));
-            let link_service =
-                LinkService::new(Arc::clone(&db), Arc::clone(&embedding_service));
-            let graph_service =
-                GraphService::new(Arc::clone(&db), Arc::clone(&embedding_service));
+            let settings = settings_service.get_settings()?;
+
+            // A model the user loaded replaces the bundled one. Without a
+            // model the app runs with full-text search only
+            let model_dir = settings
+                .model_directory
+                .map_or(resource_dir, Into::into);
+            let embedding = Arc::new(EmbeddingSlot::load(&model_dir, Arc::clone(&db)));
+
+            // Jobs left from the last session are still queued; add notes whose
+            // vectors are missing or from another model
+            let index_queue = Arc::new(IndexQueueService::new(
+                Arc::clone(&db),
+                Arc::clone(&embedding),
+            ));
+            queue_stale(&index_queue);
+
+            let search_service = SearchService::new(Arc::clone(&db), Arc::clone(&embedding));
+            let link_service = LinkService::new(Arc::clone(&db), Arc::clone(&embedding));
+            let graph_service = GraphService::new(Arc::clone(&db), Arc::clone(&embedding));
             let file_watcher_service =
-                FileWatcherService::new(Arc::clone(&db), Arc::clone(&embedding_service));
+                FileWatcherService::new(Arc::clone(&db), Arc::clone(&index_queue));
+
+            let watch_directory = settings.watch_directory;
 
             app.manage(AppState {
                 note_service,
                 settings_service,
-                embedding_service,
+                trash_service,
+                revision_service,
+                tag_service,
+                folder_service,
+                wikilink_service,
+                mention_service,
+                embedding,
+                index_queue: Arc::clone(&index_queue),
                 search_service,
                 link_service,
                 graph_service,
@@ -295,6 +542,39 @@ pub fn run() {
                 db,
             });
 
+            // Report queue depth as it changes, and progress while embedding
+            let handle = app.handle().clone();
+            index_queue.start(move |status| {
+                let _ = handle.emit("index-queue", status);
+                if let Some(title) = &status.current_note_title {
+                    let _ = handle.emit(
+                        "indexing-progress",
+                        serde_json::json!({
+                            "processed": status.processed,
+                            "total": status.processed + status.pending,
+                            "current_note_title": title,
+                            "notes_per_second": status.notes_per_second,
+                            "eta_seconds": status.eta_seconds,
+                        }),
+                    );
+                }
+            })
+            .expect("Failed to start index queue");
+
+            // Resume watching the vault from the last session
+            if let Some(directory) = watch_directory {
+                let handle = app.handle().clone();
+                std::thread::spawn(move || {
+                    let state = handle.state::<AppState>();
+                    if let Err(e) = state
+                        .file_watcher_service
+                        .start_watching(&directory, handle.clone())
+                    {
+                        tracing::warn!("Failed to resume watching {directory}: {e}");
+                    }
+                });
+            }
+
             tracing::info!("Sunder initialized successfully");
             Ok(())
         })
@@ -302,11 +582,31 @@ pub fn run() {
             create_note,
             get_note,
             update_note,
+            move_note,
             delete_note,
+            list_trash,
+            restore_note,
+            empty_trash,
+            list_revisions,
+            diff_revisions,
+            restore_revision,
             list_notes,
+            get_note_tree,
+            list_tags,
+            rename_tag,
+            get_backlinks,
+            get_outgoing_links,
+            get_unlinked_mentions,
+            link_mention,
             get_settings,
             update_settings,
             reindex_all,
+            pause_reindex,
+            resume_reindex,
+            cancel_reindex,
+            get_index_queue,
+            get_embedding_status,
+            load_embedding_model,
             search_notes,
             get_latent_links,
             get_graph_data,
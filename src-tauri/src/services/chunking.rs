@@ -0,0 +1,302 @@
+use crate::error::SunderError;
+use crate::services::embedding_provider::{
+    ChunkEmbedding, EmbeddingProvider, InputKind, WindowInput,
+};
+use crate::services::plaintext::{self, PlainTextOptions};
+use crate::services::settings::setting;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// How note content is split into passages for embedding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkingPolicy {
+    /// Split on headings, paragraphs, list items and fenced blocks, merging
+    /// small pieces up to the token budget.
+    #[default]
+    Markdown,
+    /// Fixed token windows with a quarter window of overlap.
+    Window,
+}
+
+impl ChunkingPolicy {
+    /// Name stored in settings and with each chunk.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChunkingPolicy::Markdown => "markdown",
+            ChunkingPolicy::Window => "window",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "markdown" => Some(ChunkingPolicy::Markdown),
+            "window" => Some(ChunkingPolicy::Window),
+            _ => None,
+        }
+    }
+}
+
+/// Identifies how chunks were made, stored with each one so that notes are
+/// re-embedded when the policy or plain text options change.
+pub fn chunking_key(policy: ChunkingPolicy, options: &PlainTextOptions) -> String {
+    format!("{};{}", policy.as_str(), options.key())
+}
+
+/// Split each note's content into passages as `policy` says and embed the
+/// plain text of each one, in passages of about `manifest().chunk_tokens`
+/// tokens. All the notes' passages are embedded as one batch. Chunk offsets are
+/// character offsets in the note's content; every note gets at least one chunk.
+pub fn embed_chunks(
+    provider: &dyn EmbeddingProvider,
+    contents: &[&str],
+    policy: ChunkingPolicy,
+    options: &PlainTextOptions,
+) -> Result<Vec<Vec<ChunkEmbedding>>, SunderError> {
+    let manifest = provider.manifest();
+    let size = manifest.chunk_tokens.min(manifest.max_tokens);
+    let overlap = size / 4;
+
+    // Each section to embed: which note it's from, where it is, its text and context
+    let mut sections = Vec::new();
+    for (note, content) in contents.iter().enumerate() {
+        let chunks = match policy {
+            ChunkingPolicy::Window => vec![TextChunk {
+                range: 0..content.len(),
+                heading_path: Vec::new(),
+            }],
+            // Leave room for the special tokens the tokenizer adds
+            ChunkingPolicy::Markdown => markdown_chunks(content, size.saturating_sub(2), |s| {
+                provider.count_tokens(&plaintext::to_plain_text(s, options))
+            })?,
+        };
+        for chunk in chunks {
+            let plain = plaintext::render(&content[chunk.range.clone()], options);
+            if !plain.text.trim().is_empty() {
+                sections.push((note, chunk.range.start, plain, chunk.context()));
+            }
+        }
+    }
+
+    let inputs: Vec<WindowInput<'_>> = sections
+        .iter()
+        .map(|(_, _, plain, context)| WindowInput {
+            text: &plain.text,
+            context,
+        })
+        .collect();
+    // A section over the budget is windowed, with its context in the first window
+    let embedded = provider.embed_windows(&inputs, InputKind::Passage, size, overlap)?;
+
+    let mut chunks: Vec<Vec<ChunkEmbedding>> = contents.iter().map(|_| Vec::new()).collect();
+    for ((note, section_start, plain, _), windows) in sections.iter().zip(embedded) {
+        let content = contents[*note];
+        // Window offsets are characters in the plain text; chunk offsets are
+        // characters in the markdown
+        let byte = |offset: usize| {
+            plain
+                .text
+                .char_indices()
+                .nth(offset)
+                .map_or(plain.text.len(), |(i, _)| i)
+        };
+        let chars = |source: usize| content[..section_start + source].chars().count();
+        for window in windows {
+            chunks[*note].push(ChunkEmbedding {
+                start: chars(plain.source_offset(byte(window.start))),
+                end: chars(plain.source_end(byte(window.end))),
+                vector: window.vector,
+            });
+        }
+    }
+
+    // Notes of nothing but code or markup: embed the markdown itself
+    let bare: Vec<usize> = (0..contents.len()).filter(|&i| chunks[i].is_empty()).collect();
+    if !bare.is_empty() {
+        let inputs: Vec<WindowInput<'_>> = bare
+            .iter()
+            .map(|&i| WindowInput {
+                text: contents[i],
+                context: "",
+            })
+            .collect();
+        let embedded = provider.embed_windows(&inputs, InputKind::Passage, size, overlap)?;
+        for (i, windows) in bare.into_iter().zip(embedded) {
+            chunks[i] = windows;
+        }
+    }
+    Ok(chunks)
+}
+
+/// The `chunking_policy` setting, or the default if unset or unrecognized.
+pub fn chunking_policy(conn: &Connection) -> Result<ChunkingPolicy, SunderError> {
+    Ok(setting(conn, "chunking_policy")?
+        .as_deref()
+        .and_then(ChunkingPolicy::parse)
+        .unwrap_or_default())
+}
+
+/// A passage of a markdown document.
+#[derive(Debug, Clone)]
+pub struct TextChunk {
+    /// Byte range in the document.
+    pub range: Range<usize>,
+    /// Headings the passage sits under, outermost first.
+    pub heading_path: Vec<String>,
+}
+
+impl TextChunk {
+    /// Heading path to embed ahead of the passage text, e.g. "Setup > Linux\n\n".
+    pub fn context(&self) -> String {
+        if self.heading_path.is_empty() {
+            String::new()
+        } else {
+            format!("{}\n\n", self.heading_path.join(" > "))
+        }
+    }
+}
+
+/// Split a markdown document into passages of at most `budget` tokens (as
+/// measured by `count_tokens`, heading context included). Paragraphs, list
+/// items and fenced blocks are never cut; consecutive ones are merged while
+/// they fit. A single block over the budget becomes a passage of its own, which
+/// the caller has to window. Returns no passages for a blank document.
+pub fn markdown_chunks<F>(
+    content: &str,
+    budget: usize,
+    mut count_tokens: F,
+) -> Result<Vec<TextChunk>, SunderError>
+where
+    F: FnMut(&str) -> Result<usize, SunderError>,
+{
+    let mut chunks = Vec::new();
+    let mut open: Option<(TextChunk, usize)> = None;
+
+    for block in blocks(content) {
+        let tokens = count_tokens(&content[block.range.clone()])?;
+        if let Some((chunk, used)) = &mut open {
+            // A merged chunk keeps only the headings both parts share, so its
+            // context never grows and `used` stays an upper bound
+            if *used + tokens <= budget {
+                let shared = chunk
+                    .heading_path
+                    .iter()
+                    .zip(&block.heading_path)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                chunk.heading_path.truncate(shared);
+                chunk.range.end = block.range.end;
+                *used += tokens;
+                continue;
+            }
+        }
+        chunks.extend(open.take().map(|(chunk, _)| chunk));
+
+        let context = count_tokens(&block.context())?;
+        open = Some((block, context + tokens));
+    }
+    chunks.extend(open.map(|(chunk, _)| chunk));
+    Ok(chunks)
+}
+
+/// The document's paragraphs, list items and fenced blocks, each with the
+/// headings above it. Heading lines are kept with the block that follows them
+/// (and then left out of its path), so a chunk never ends on a bare heading.
+fn blocks(content: &str) -> Vec<TextChunk> {
+    let mut blocks = Vec::new();
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut pending_heading: Option<TextChunk> = None;
+    let mut current: Option<TextChunk> = None;
+    let mut fence: Option<(TextChunk, &str)> = None;
+    let mut line_start = 0;
+
+    // A block starting at `offset`, or at the headings just before it
+    let start_block =
+        |pending: &mut Option<TextChunk>, headings: &[(usize, String)], offset: usize| {
+            pending.take().unwrap_or_else(|| TextChunk {
+                range: offset..offset,
+                heading_path: headings.iter().map(|(_, title)| title.clone()).collect(),
+            })
+        };
+
+    for line in content.split_inclusive('\n') {
+        let offset = line_start;
+        line_start += line.len();
+        let text = line.trim_end();
+        let end = offset + text.len();
+        let trimmed = text.trim_start();
+
+        if let Some((block, marker)) = &mut fence {
+            if trimmed.starts_with(*marker) {
+                block.range.end = end;
+                blocks.extend(fence.take().map(|(block, _)| block));
+            }
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+            blocks.extend(current.take());
+            fence = Some((start_block(&mut pending_heading, &headings, offset), marker));
+            continue;
+        }
+
+        if trimmed.is_empty() {
+            blocks.extend(current.take());
+            continue;
+        }
+
+        if let Some((level, title)) = heading(trimmed) {
+            blocks.extend(current.take());
+            headings.retain(|(l, _)| *l < level);
+            let mut block = start_block(&mut pending_heading, &headings, offset);
+            block.range.end = end;
+            block.heading_path.truncate(headings.len());
+            pending_heading = Some(block);
+            headings.push((level, title.to_string()));
+            continue;
+        }
+
+        match &mut current {
+            Some(block) if !is_list_item(trimmed) => block.range.end = end,
+            _ => {
+                blocks.extend(current.take());
+                let mut block = start_block(&mut pending_heading, &headings, offset);
+                block.range.end = end;
+                current = Some(block);
+            }
+        }
+    }
+
+    // An unclosed fence runs to the end of the document
+    if let Some((mut block, _)) = fence {
+        block.range.end = content.trim_end().len();
+        blocks.push(block);
+    }
+    blocks.extend(current);
+    blocks.extend(pending_heading);
+    blocks
+}
+
+/// Level and text of an ATX heading line (`## Title`).
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.bytes().take_while(|&b| b == b'#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+/// Whether a line opens a list item (`- `, `* `, `+ `, `1. ` or `1) `).
+fn is_list_item(line: &str) -> bool {
+    let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+    let rest = match digits {
+        0 => line.strip_prefix(['-', '*', '+']),
+        1..=9 => line[digits..].strip_prefix(['.', ')']),
+        _ => None,
+    };
+    rest.is_some_and(|r| r.is_empty() || r.starts_with([' ', '\t']))
+}
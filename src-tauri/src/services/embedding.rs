@@ -1,263 +1,610 @@
 use crate::db::DatabaseManager;
 use crate::error::SunderError;
-use ort::session::Session;
-use ort::value::Tensor;
+use crate::services::chunking::{self, ChunkingPolicy};
+use crate::services::plaintext::{self, PlainTextOptions};
+use crate::services::embedding_provider::{
+    self, EmbeddingProvider, InputKind, ModelManifest, OnnxProvider,
+};
+use crate::services::settings::setting;
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use tokenizers::Tokenizer;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
 
-const EMBEDDING_DIM: usize = 384;
-const MAX_TOKENS: usize = 512;
-const OVERLAP_TOKENS: usize = 256;
+/// Notes embedded together by `index_bulk`.
+pub const NOTES_PER_BATCH: usize = 32;
+/// Upper bound on the `embedding_sessions` setting.
+pub const MAX_EMBEDDING_SESSIONS: usize = 16;
 
 pub struct EmbeddingService {
-    session: Mutex<Session>,
-    tokenizer: Tokenizer,
+    provider: Box<dyn EmbeddingProvider>,
     db: Arc<DatabaseManager>,
-    reindexing: AtomicBool,
 }
 
 impl EmbeddingService {
+    /// Load the ONNX model described by the resource directory's `model.json`
+    /// (or the bundled default model), with as many bulk indexing sessions as
+    /// the `embedding_sessions` setting asks for.
     pub fn new(resource_dir: &Path, db: Arc<DatabaseManager>) -> Result<Self, SunderError> {
-        let model_path = resource_dir.join("model_quantized.onnx");
-        let tokenizer_path = resource_dir.join("tokenizer.json");
-
-        if !model_path.exists() {
-            return Err(SunderError::EmbeddingError(format!(
-                "ONNX model not found: {}",
-                model_path.display()
-            )));
-        }
-        if !tokenizer_path.exists() {
-            return Err(SunderError::EmbeddingError(format!(
-                "Tokenizer not found: {}",
-                tokenizer_path.display()
-            )));
-        }
-
-        let session = Session::builder()
-            .map_err(|e| SunderError::EmbeddingError(format!("Session builder: {e}")))?
-            .with_intra_threads(2)
-            .map_err(|e| SunderError::EmbeddingError(format!("Set threads: {e}")))?
-            .commit_from_file(&model_path)
-            .map_err(|e| SunderError::EmbeddingError(format!("Load ONNX: {e}")))?;
+        let manifest = ModelManifest::load(resource_dir)?;
+        let sessions = {
+            let conn = db.get_read_conn()?;
+            embedding_sessions(&conn)?
+        };
+        let provider = OnnxProvider::new(resource_dir, manifest, sessions)?;
+        Self::with_provider(Box::new(provider), db)
+    }
 
-        let tokenizer = Tokenizer::from_file(&tokenizer_path)
-            .map_err(|e| SunderError::EmbeddingError(format!("Load tokenizer: {e}")))?;
+    /// Use the given provider. Vectors from any other model are taken out of
+    /// `vec_embeddings` (recreating it if the dimension changed) until the notes
+    /// are re-embedded (see `stale_note_ids`).
+    pub fn with_provider(
+        provider: Box<dyn EmbeddingProvider>,
+        db: Arc<DatabaseManager>,
+    ) -> Result<Self, SunderError> {
+        {
+            let mut conn = db.get_write_conn()?;
+            prepare_vector_table(&mut conn, provider.manifest(), provider.model_version())?;
+        }
 
         Ok(Self {
-            session: Mutex::new(session),
-            tokenizer,
+            provider,
             db,
-            reindexing: AtomicBool::new(false),
         })
     }
 
-    /// Embed a text string into a 384-dimensional unit vector.
-    pub fn embed_text(&self, text: &str) -> Result<Vec<f32>, SunderError> {
-        let encoding = self
-            .tokenizer
-            .encode(text, true)
-            .map_err(|e| SunderError::EmbeddingError(format!("Tokenization failed: {e}")))?;
+    pub fn manifest(&self) -> &ModelManifest {
+        self.provider.manifest()
+    }
 
-        let token_count = encoding.get_ids().len();
+    /// Version of the loaded model, as stored in `embeddings.model_version`.
+    pub fn model_version(&self) -> &str {
+        self.provider.model_version()
+    }
 
-        if token_count <= MAX_TOKENS {
-            return self.embed_tokens(encoding.get_ids(), encoding.get_attention_mask());
-        }
+    /// Number of `index_bulk` batches that run at once.
+    pub fn parallelism(&self) -> usize {
+        self.provider.parallelism().max(1)
+    }
 
-        // Chunk long texts with overlap
-        let ids = encoding.get_ids();
-        let mask = encoding.get_attention_mask();
-        let mut chunk_embeddings: Vec<Vec<f32>> = Vec::new();
+    /// Embed note content (or other markdown compared against notes) into a
+    /// unit vector, after reducing it to plain text.
+    pub fn embed_text(&self, text: &str) -> Result<Vec<f32>, SunderError> {
+        let (_, options) = self.chunking()?;
+        self.provider
+            .embed(&plaintext::to_plain_text(text, &options), InputKind::Passage)
+    }
 
-        let mut start = 0;
-        while start < ids.len() {
-            let end = (start + MAX_TOKENS).min(ids.len());
-            let chunk_ids = &ids[start..end];
-            let chunk_mask = &mask[start..end];
+    /// Embed a search query, using the model's query prefix.
+    pub fn embed_query(&self, query: &str) -> Result<Vec<f32>, SunderError> {
+        self.provider.embed(query, InputKind::Query)
+    }
 
-            let emb = self.embed_tokens(chunk_ids, chunk_mask)?;
-            chunk_embeddings.push(emb);
+    /// Embed a note passage by passage, split by the `chunking_policy` setting
+    /// and reduced to plain text by the `plain_text_*` settings.
+    /// Each chunk goes into `note_chunks` and `vec_chunks`; their average is the
+    /// note's embedding in `embeddings` and `vec_embeddings`. Skipped when the
+    /// note's content hash shows its embedding is up to date.
+    pub fn index_note(&self, note_id: &str, content: &str) -> Result<(), SunderError> {
+        self.index_notes(&[(note_id, content)], false)
+    }
 
-            if end >= ids.len() {
-                break;
+    /// `index_note` for several notes, embedded as one batch and written in one
+    /// transaction. Notes with the same content as one already embedded (or as
+    /// another in the batch) get a copy of its vectors instead of being embedded
+    /// again. `force` re-embeds every note regardless.
+    pub fn index_notes(&self, notes: &[(&str, &str)], force: bool) -> Result<(), SunderError> {
+        let (policy, options) = self.chunking()?;
+        let chunking_key = chunking::chunking_key(policy, &options);
+        let model_version = self.model_version();
+        let hashes: Vec<String> = notes
+            .iter()
+            .map(|(_, content)| content_hash(model_version, &chunking_key, content))
+            .collect();
+
+        let mut plans = Vec::with_capacity(notes.len());
+        // Contents to embed, and which of them each hash went to
+        let mut contents: Vec<&str> = Vec::new();
+        let mut embedded_as: HashMap<&str, usize> = HashMap::new();
+        {
+            let conn = self.db.get_read_conn()?;
+            let mut current =
+                conn.prepare("SELECT content_hash FROM embeddings WHERE note_id = ?1")?;
+            let mut twin = conn.prepare(
+                "SELECT note_id FROM embeddings WHERE content_hash = ?1 AND note_id != ?2 LIMIT 1",
+            )?;
+
+            for ((note_id, content), hash) in notes.iter().zip(&hashes) {
+                if !force {
+                    let stored: Option<Option<String>> =
+                        current.query_row([note_id], |row| row.get(0)).optional()?;
+                    if stored.flatten().as_deref() == Some(hash.as_str()) {
+                        plans.push(Plan::Skip);
+                        continue;
+                    }
+                    if let Some(&index) = embedded_as.get(hash.as_str()) {
+                        plans.push(Plan::Embed(index));
+                        continue;
+                    }
+                    let source: Option<String> = twin
+                        .query_row(rusqlite::params![hash, note_id], |row| row.get(0))
+                        .optional()?;
+                    if let Some(source) = source {
+                        plans.push(Plan::Copy(source));
+                        continue;
+                    }
+                }
+                embedded_as.insert(hash, contents.len());
+                plans.push(Plan::Embed(contents.len()));
+                contents.push(content);
             }
-            start += MAX_TOKENS - OVERLAP_TOKENS;
         }
 
-        // Average chunk embeddings
-        let mut avg = vec![0.0f32; EMBEDDING_DIM];
-        for emb in &chunk_embeddings {
-            for (i, v) in emb.iter().enumerate() {
-                avg[i] += v;
+        let chunked = if contents.is_empty() {
+            Vec::new()
+        } else {
+            chunking::embed_chunks(self.provider.as_ref(), &contents, policy, &options)?
+        };
+        let now = chrono::Utc::now().to_rfc3339();
+
+        let mut conn = self.db.get_write_conn()?;
+        let tx = conn.transaction()?;
+
+        // Copies first, before a source in this batch is re-embedded with new content
+        for (((note_id, _), hash), plan) in notes.iter().zip(&hashes).zip(&plans) {
+            if let Plan::Copy(source) = plan {
+                // If the source has changed since, the note is left for the next stale check
+                copy_embedding(&tx, source, note_id, hash, &now)?;
             }
         }
-        let n = chunk_embeddings.len() as f32;
-        for v in &mut avg {
-            *v /= n;
+
+        for (((note_id, _), hash), plan) in notes.iter().zip(&hashes).zip(&plans) {
+            match *plan {
+                Plan::Skip | Plan::Copy(_) => {}
+                Plan::Embed(index) => {
+                    let chunks = &chunked[index];
+                    let embedding = embedding_provider::mean_vector(
+                        chunks.iter().map(|c| c.vector.as_slice()),
+                        self.manifest().dimension,
+                    );
+                    let blob = embedding_to_blob(&embedding);
+
+                    tx.execute(
+                        "INSERT OR REPLACE INTO embeddings (note_id, vector, model_version, updated_at, content_hash)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        rusqlite::params![note_id, blob, model_version, now, hash],
+                    )?;
+
+                    tx.execute(
+                        "DELETE FROM vec_embeddings WHERE note_id = ?1",
+                        [note_id],
+                    )?;
+                    tx.execute(
+                        "INSERT INTO vec_embeddings (note_id, embedding) VALUES (?1, ?2)",
+                        rusqlite::params![note_id, blob],
+                    )?;
+
+                    delete_chunks(&tx, note_id)?;
+                    for (index, chunk) in chunks.iter().enumerate() {
+                        let chunk_blob = embedding_to_blob(&chunk.vector);
+                        tx.execute(
+                            "INSERT INTO note_chunks (note_id, chunk_index, start_offset, end_offset, vector, model_version, chunking)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                            rusqlite::params![
+                                note_id,
+                                index,
+                                chunk.start,
+                                chunk.end,
+                                chunk_blob,
+                                model_version,
+                                chunking_key
+                            ],
+                        )?;
+                        tx.execute(
+                            "INSERT INTO vec_chunks (chunk_id, embedding) VALUES (?1, ?2)",
+                            rusqlite::params![tx.last_insert_rowid(), chunk_blob],
+                        )?;
+                    }
+                }
+            }
         }
 
-        l2_normalize(&mut avg);
-        Ok(avg)
+        tx.commit()?;
+        Ok(())
     }
 
-    /// Run ONNX inference on token IDs with attention mask, mean pool, and normalize.
-    fn embed_tokens(&self, ids: &[u32], attention_mask: &[u32]) -> Result<Vec<f32>, SunderError> {
-        let seq_len = ids.len();
-
-        let input_ids: Vec<i64> = ids.iter().map(|&x| x as i64).collect();
-        let attn_mask: Vec<i64> = attention_mask.iter().map(|&x| x as i64).collect();
-        let token_type_ids: Vec<i64> = vec![0i64; seq_len];
-
-        let input_ids_tensor = Tensor::from_array(([1usize, seq_len], input_ids.into_boxed_slice()))
-            .map_err(|e| SunderError::EmbeddingError(format!("input_ids tensor: {e}")))?;
-        let attn_mask_tensor = Tensor::from_array(([1usize, seq_len], attn_mask.into_boxed_slice()))
-            .map_err(|e| SunderError::EmbeddingError(format!("attention_mask tensor: {e}")))?;
-        let token_type_tensor = Tensor::from_array(([1usize, seq_len], token_type_ids.into_boxed_slice()))
-            .map_err(|e| SunderError::EmbeddingError(format!("token_type_ids tensor: {e}")))?;
-
-        let mut session = self.session.lock()
-            .map_err(|e| SunderError::EmbeddingError(format!("Session lock: {e}")))?;
-        let outputs = session
-            .run([
-                input_ids_tensor.into(),
-                attn_mask_tensor.into(),
-                token_type_tensor.into(),
-            ])
-            .map_err(|e| SunderError::EmbeddingError(format!("Inference failed: {e}")))?;
-
-        // Output shape: [1, seq_len, 384]
-        let (shape, data) = outputs[0]
-            .try_extract_tensor::<f32>()
-            .map_err(|e| SunderError::EmbeddingError(format!("Output extraction: {e}")))?;
-
-        let dims: &[i64] = shape;
-        let hidden_dim = if dims.len() == 3 { dims[2] as usize } else { EMBEDDING_DIM };
-
-        // Mean pooling with attention mask
-        let mut pooled = vec![0.0f32; hidden_dim];
-        let mut total_weight = 0.0f32;
-
-        for (t, &mask_val) in attention_mask.iter().enumerate().take(seq_len) {
-            let w = mask_val as f32;
-            total_weight += w;
-            let offset = t * hidden_dim;
-            for d in 0..hidden_dim {
-                pooled[d] += data[offset + d] * w;
+    /// Embed many notes, in batches spread over the provider's indexing sessions.
+    /// Each note is read when its batch comes up, so notes deleted in the meantime
+    /// are skipped. After each batch `progress` gets the number embedded so far,
+    /// the batch's ids and the title of its last note, and returns whether to
+    /// go on. Returns the number embedded. Unchanged notes are skipped unless
+    /// `force` is set, as in `index_notes`.
+    pub fn index_bulk<F>(
+        &self,
+        note_ids: &[String],
+        force: bool,
+        progress: F,
+    ) -> Result<u32, SunderError>
+    where
+        F: Fn(u32, &[String], &str) -> bool + Sync,
+    {
+        let batches: Vec<&[String]> = note_ids.chunks(NOTES_PER_BATCH).collect();
+        let next = AtomicUsize::new(0);
+        let embedded = AtomicU32::new(0);
+        let workers = self.parallelism().min(batches.len().max(1));
+
+        let work = || -> Result<(), SunderError> {
+            while let Some(batch) = batches.get(next.fetch_add(1, Ordering::SeqCst)) {
+                let result = self.load_notes(batch).and_then(|notes| {
+                    let pairs: Vec<(&str, &str)> = notes
+                        .iter()
+                        .map(|(id, _, content)| (id.as_str(), content.as_str()))
+                        .collect();
+                    self.index_notes(&pairs, force)?;
+                    Ok(notes)
+                });
+                let notes = match result {
+                    Ok(notes) => notes,
+                    Err(e) => {
+                        // Stop the other workers at their next batch
+                        next.store(batches.len(), Ordering::SeqCst);
+                        return Err(e);
+                    }
+                };
+                let count = notes.len() as u32;
+                let total = embedded.fetch_add(count, Ordering::SeqCst) + count;
+                let title = notes.last().map_or("", |(_, title, _)| title.as_str());
+                if !progress(total, batch, title) {
+                    // Stopped by the caller; batches already started still finish
+                    next.store(batches.len(), Ordering::SeqCst);
+                }
             }
-        }
+            Ok(())
+        };
+
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers).map(|_| scope.spawn(work)).collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle.join().unwrap_or_else(|_| {
+                        next.store(batches.len(), Ordering::SeqCst);
+                        Err(SunderError::Internal(
+                            "Indexing worker panicked".to_string(),
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        Ok(embedded.into_inner())
+    }
 
-        if total_weight > 0.0 {
-            for v in &mut pooled {
-                *v /= total_weight;
+    /// Id, title and content of the notes among `ids` that still exist.
+    fn load_notes(&self, ids: &[String]) -> Result<Vec<(String, String, String)>, SunderError> {
+        let conn = self.db.get_read_conn()?;
+        let mut stmt = conn.prepare("SELECT title, content FROM notes WHERE id = ?1")?;
+        let mut notes = Vec::with_capacity(ids.len());
+        for id in ids {
+            let note = stmt
+                .query_row([id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+                .optional()?;
+            if let Some((title, content)) = note {
+                notes.push((id.clone(), title, content));
             }
         }
+        Ok(notes)
+    }
 
-        l2_normalize(&mut pooled);
-        Ok(pooled)
+    /// Notes long enough to embed whose embedding or chunks are missing, came
+    /// from another model or were chunked with other settings.
+    pub fn stale_note_ids(&self) -> Result<Vec<String>, SunderError> {
+        let (policy, options) = self.chunking()?;
+        let conn = self.db.get_read_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT n.id FROM notes n
+             LEFT JOIN embeddings e ON e.note_id = n.id
+             WHERE n.word_count >= 3
+               AND (e.note_id IS NULL OR e.model_version != ?1
+                    OR NOT EXISTS (SELECT 1 FROM note_chunks c
+                                   WHERE c.note_id = n.id AND c.model_version = ?1
+                                     AND c.chunking = ?2))
+             ORDER BY n.updated_at DESC",
+        )?;
+        let ids = stmt
+            .query_map(
+                [self.model_version(), &chunking::chunking_key(policy, &options)],
+                |row| row.get(0),
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ids)
     }
 
-    /// Store an embedding for a note in both embeddings table and vec_embeddings virtual table.
-    pub fn index_note(&self, note_id: &str, content: &str) -> Result<(), SunderError> {
-        let embedding = self.embed_text(content)?;
-        let blob = embedding_to_blob(&embedding);
-        let now = chrono::Utc::now().to_rfc3339();
+    fn chunking(&self) -> Result<(ChunkingPolicy, PlainTextOptions), SunderError> {
+        let conn = self.db.get_read_conn()?;
+        Ok((chunking::chunking_policy(&conn)?, PlainTextOptions::load(&conn)?))
+    }
+}
 
-        let conn = self.db.get_write_conn()?;
+/// Whether semantic features are available, and why not if they aren't.
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbeddingStatus {
+    pub available: bool,
+    /// Version of the loaded model.
+    pub model_version: Option<String>,
+    /// Directory the model was loaded (or last tried) from.
+    pub model_dir: Option<String>,
+    /// Why the model couldn't be loaded.
+    pub error: Option<String>,
+}
 
-        conn.execute(
-            "INSERT OR REPLACE INTO embeddings (note_id, vector, model_version, updated_at)
-             VALUES (?1, ?2, 'minilm-v2-q8', ?3)",
-            rusqlite::params![note_id, blob, now],
-        )?;
+/// The embedding service, once a model has loaded. Services that embed hold
+/// this rather than the service, so the app runs without a model (notes and
+/// full-text search only) and a model can be loaded or swapped while it runs.
+pub struct EmbeddingSlot {
+    service: RwLock<Option<Arc<EmbeddingService>>>,
+    status: RwLock<EmbeddingStatus>,
+}
 
-        conn.execute(
-            "DELETE FROM vec_embeddings WHERE note_id = ?1",
-            [note_id],
-        )?;
-        conn.execute(
-            "INSERT INTO vec_embeddings (note_id, embedding) VALUES (?1, ?2)",
-            rusqlite::params![note_id, blob],
-        )?;
+impl EmbeddingSlot {
+    /// Load the model in `model_dir`, or start without one if it can't be.
+    pub fn load(model_dir: &Path, db: Arc<DatabaseManager>) -> Self {
+        let slot = Self {
+            service: RwLock::new(None),
+            status: RwLock::new(EmbeddingStatus {
+                available: false,
+                model_version: None,
+                model_dir: None,
+                error: None,
+            }),
+        };
+        if let Err(e) = slot.replace(model_dir, db) {
+            tracing::warn!("Starting without semantic features: {e}");
+        }
+        slot
+    }
 
-        Ok(())
+    /// A slot holding an already created service.
+    pub fn with_service(service: EmbeddingService) -> Self {
+        let status = EmbeddingStatus {
+            available: true,
+            model_version: Some(service.model_version().to_string()),
+            model_dir: None,
+            error: None,
+        };
+        Self {
+            service: RwLock::new(Some(Arc::new(service))),
+            status: RwLock::new(status),
+        }
     }
 
-    /// Remove embedding for a note from both tables.
-    pub fn remove_embedding(&self, note_id: &str) -> Result<(), SunderError> {
-        let conn = self.db.get_write_conn()?;
-        conn.execute("DELETE FROM embeddings WHERE note_id = ?1", [note_id])?;
-        conn.execute("DELETE FROM vec_embeddings WHERE note_id = ?1", [note_id])?;
-        Ok(())
+    /// Load the model in `model_dir` in place of the current one. On failure
+    /// the current model (if any) stays loaded.
+    pub fn replace(
+        &self,
+        model_dir: &Path,
+        db: Arc<DatabaseManager>,
+    ) -> Result<EmbeddingStatus, SunderError> {
+        let loaded = EmbeddingService::new(model_dir, db);
+        let mut status = self
+            .status
+            .write()
+            .map_err(|e| SunderError::Internal(format!("Embedding status lock poisoned: {e}")))?;
+        status.model_dir = Some(model_dir.to_string_lossy().into_owned());
+        let service = match loaded {
+            Ok(service) => service,
+            Err(e) => {
+                status.error = Some(match &e {
+                    SunderError::EmbeddingError(msg) => msg.clone(),
+                    e => e.to_string(),
+                });
+                return Err(e);
+            }
+        };
+
+        tracing::info!(
+            "Loaded embedding model {} from {}",
+            service.model_version(),
+            model_dir.display()
+        );
+        status.available = true;
+        status.model_version = Some(service.model_version().to_string());
+        status.error = None;
+        let mut current = self
+            .service
+            .write()
+            .map_err(|e| SunderError::Internal(format!("Embedding service lock poisoned: {e}")))?;
+        *current = Some(Arc::new(service));
+        Ok(status.clone())
     }
 
-    /// Reindex all notes. Progress reported via callback.
-    pub fn reindex_all<F>(&self, progress_callback: F) -> Result<u32, SunderError>
-    where
-        F: Fn(u32, u32, &str),
-    {
-        if self
-            .reindexing
-            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
-            .is_err()
-        {
-            return Err(SunderError::AlreadyRunning);
+    /// The loaded service, or `EmbeddingUnavailable` if there's no model.
+    pub fn get(&self) -> Result<Arc<EmbeddingService>, SunderError> {
+        self.current().ok_or_else(|| {
+            let reason = self
+                .status()
+                .error
+                .unwrap_or_else(|| "no model loaded".to_string());
+            SunderError::EmbeddingUnavailable(reason)
+        })
+    }
+
+    /// The loaded service, if any.
+    pub fn current(&self) -> Option<Arc<EmbeddingService>> {
+        self.service.read().ok().and_then(|service| service.clone())
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.current().is_some()
+    }
+
+    pub fn status(&self) -> EmbeddingStatus {
+        match self.status.read() {
+            Ok(status) => status.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
         }
+    }
+}
 
-        let result = self.do_reindex_all(&progress_callback);
-        self.reindexing.store(false, Ordering::SeqCst);
-        result
+/// How `index_notes` brings a note's embedding up to date.
+enum Plan {
+    /// Its stored embedding already matches its content.
+    Skip,
+    /// Copy the vectors of the given note, which has the same content.
+    Copy(String),
+    /// Embed it; the index is into the contents embedded.
+    Embed(usize),
+}
+
+/// Hash identifying what a note's embedding was made from: the model, the
+/// chunking and plain text settings, and the content (less trailing whitespace,
+/// which never reaches a passage).
+pub fn content_hash(model_version: &str, chunking_key: &str, content: &str) -> String {
+    let mut hasher = Sha256::new();
+    for part in [model_version, chunking_key, content.trim_end()] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
     }
+    hex::encode(hasher.finalize())
+}
 
-    fn do_reindex_all<F>(&self, progress_callback: &F) -> Result<u32, SunderError>
-    where
-        F: Fn(u32, u32, &str),
-    {
-        let conn = self.db.get_read_conn()?;
-        let mut stmt = conn.prepare("SELECT id, title, content FROM notes")?;
-        let notes: Vec<(String, String, String)> = stmt
-            .query_map([], |row| {
-                Ok((
-                    row.get::<_, String>(0)?,
-                    row.get::<_, String>(1)?,
-                    row.get::<_, String>(2)?,
-                ))
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
-        drop(stmt);
-        drop(conn);
+/// Give `to` a copy of the embedding and chunks of `from`, if they still have
+/// the content hash `hash`.
+fn copy_embedding(
+    conn: &Connection,
+    from: &str,
+    to: &str,
+    hash: &str,
+    now: &str,
+) -> Result<(), SunderError> {
+    let copied = conn.execute(
+        "INSERT OR REPLACE INTO embeddings (note_id, vector, model_version, updated_at, content_hash)
+         SELECT ?1, vector, model_version, ?4, content_hash FROM embeddings
+         WHERE note_id = ?2 AND content_hash = ?3",
+        rusqlite::params![to, from, hash, now],
+    )?;
+    if copied == 0 {
+        return Ok(());
+    }
 
-        let total = notes.len() as u32;
-        let mut indexed = 0u32;
+    conn.execute("DELETE FROM vec_embeddings WHERE note_id = ?1", [to])?;
+    conn.execute(
+        "INSERT INTO vec_embeddings (note_id, embedding)
+         SELECT ?1, vector FROM embeddings WHERE note_id = ?1",
+        [to],
+    )?;
+
+    delete_chunks(conn, to)?;
+    conn.execute(
+        "INSERT INTO note_chunks (note_id, chunk_index, start_offset, end_offset, vector, model_version, chunking)
+         SELECT ?1, chunk_index, start_offset, end_offset, vector, model_version, chunking
+         FROM note_chunks WHERE note_id = ?2 ORDER BY chunk_index",
+        [to, from],
+    )?;
+    conn.execute(
+        "INSERT INTO vec_chunks (chunk_id, embedding)
+         SELECT id, vector FROM note_chunks WHERE note_id = ?1",
+        [to],
+    )?;
+    Ok(())
+}
 
-        for (id, title, content) in &notes {
-            if content.split_whitespace().count() < 3 {
-                indexed += 1;
-                continue;
-            }
+/// The `embedding_sessions` setting: how many model sessions bulk indexing
+/// runs in parallel. Defaults to 1.
+pub fn embedding_sessions(conn: &Connection) -> Result<usize, SunderError> {
+    Ok(setting(conn, "embedding_sessions")?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1)
+        .clamp(1, MAX_EMBEDDING_SESSIONS))
+}
 
-            self.index_note(id, content)?;
-            indexed += 1;
-            progress_callback(indexed, total, title);
+/// Remove a note's passage chunks. `vec_chunks` rows have to go first since
+/// nothing cascades into the virtual table.
+pub fn delete_chunks(conn: &Connection, note_id: &str) -> Result<(), SunderError> {
+    conn.execute(
+        "DELETE FROM vec_chunks WHERE chunk_id IN (SELECT id FROM note_chunks WHERE note_id = ?1)",
+        [note_id],
+    )?;
+    conn.execute("DELETE FROM note_chunks WHERE note_id = ?1", [note_id])?;
+    Ok(())
+}
 
-            if indexed.is_multiple_of(10) {
-                std::thread::sleep(std::time::Duration::from_millis(1));
-            }
-        }
+/// Version of the model whose vectors are in `vec_embeddings`, as recorded by
+/// `prepare_vector_table`.
+pub fn active_model(conn: &Connection) -> Result<Option<String>, SunderError> {
+    setting(conn, "embedding_model")
+}
 
-        Ok(indexed)
+/// Make `vec_embeddings` and `vec_chunks` hold only vectors from `model_version`.
+/// When the dimension changed the tables are recreated and all stored vectors
+/// dropped; otherwise just the stale ones leave the index (their `embeddings`
+/// and `note_chunks` rows stay until re-embedded). Similarities computed from
+/// old vectors are cleared.
+fn prepare_vector_table(
+    conn: &mut Connection,
+    manifest: &ModelManifest,
+    model_version: &str,
+) -> Result<(), SunderError> {
+    let model = active_model(conn)?;
+    let dimension: Option<usize> =
+        setting(conn, "embedding_dimension")?.and_then(|v| v.parse().ok());
+    let same_dimension = dimension == Some(manifest.dimension);
+    if model.as_deref() == Some(model_version) && same_dimension {
+        return Ok(());
     }
-}
 
-fn l2_normalize(v: &mut [f32]) {
-    let norm: f32 = v.iter().map(|x| x * x).sum::<f32>().sqrt();
-    if norm > 0.0 {
-        for x in v.iter_mut() {
-            *x /= norm;
-        }
+    let tx = conn.transaction()?;
+    if !same_dimension {
+        // The migrations size the tables by the recorded dimension, so a model
+        // with another one needs them recreated
+        tx.execute_batch(&format!(
+            "DROP TABLE IF EXISTS vec_embeddings;
+             CREATE VIRTUAL TABLE vec_embeddings USING vec0(
+                 note_id TEXT PRIMARY KEY,
+                 embedding float[{0}] distance_metric=cosine
+             );
+             DROP TABLE IF EXISTS vec_chunks;
+             CREATE VIRTUAL TABLE vec_chunks USING vec0(
+                 chunk_id INTEGER PRIMARY KEY,
+                 embedding float[{0}] distance_metric=cosine
+             );
+             DELETE FROM embeddings;
+             DELETE FROM note_chunks;",
+            manifest.dimension
+        ))?;
+    } else {
+        tx.execute(
+            "DELETE FROM vec_embeddings WHERE note_id IN (
+                 SELECT note_id FROM embeddings WHERE model_version != ?1
+             )",
+            [model_version],
+        )?;
+        tx.execute(
+            "DELETE FROM vec_chunks WHERE chunk_id IN (
+                 SELECT id FROM note_chunks WHERE model_version != ?1
+             )",
+            [model_version],
+        )?;
     }
+    tx.execute("DELETE FROM similarity_cache", [])?;
+    tx.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES ('embedding_model', ?1)",
+        [model_version],
+    )?;
+    tx.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES ('embedding_dimension', ?1)",
+        [manifest.dimension.to_string()],
+    )?;
+    tx.commit()?;
+
+    tracing::info!(
+        "Embedding model changed from {} to {model_version} ({} dimensions); stale notes will be re-embedded",
+        model.as_deref().unwrap_or("none"),
+        manifest.dimension
+    );
+    Ok(())
 }
 
 pub fn embedding_to_blob(embedding: &[f32]) -> Vec<u8> {
@@ -0,0 +1,563 @@
+use crate::error::SunderError;
+use ort::session::Session;
+use ort::value::Tensor;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use tokenizers::Tokenizer;
+
+/// Manifest file describing the model, read from the resource directory when present.
+const MANIFEST_FILE: &str = "model.json";
+
+/// Whether text is a search query or content being indexed. Some models
+/// (e5, bge) expect a different prefix for each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    Query,
+    Passage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pooling {
+    /// Average of the token embeddings, weighted by the attention mask.
+    Mean,
+    /// Embedding of the first (`[CLS]`) token.
+    Cls,
+}
+
+/// Names of the model's ONNX inputs. `token_type_ids` is only fed to models that have it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ModelInputs {
+    pub input_ids: String,
+    pub attention_mask: String,
+    pub token_type_ids: Option<String>,
+}
+
+impl Default for ModelInputs {
+    fn default() -> Self {
+        Self {
+            input_ids: "input_ids".to_string(),
+            attention_mask: "attention_mask".to_string(),
+            token_type_ids: Some("token_type_ids".to_string()),
+        }
+    }
+}
+
+/// Everything needed to run a sentence-embedding model. Fields missing from
+/// `model.json` default to the bundled all-MiniLM-L6-v2 model.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ModelManifest {
+    /// Model name, combined with a hash of the model files into the version
+    /// stored with every embedding.
+    pub id: String,
+    pub model_file: String,
+    pub tokenizer_file: String,
+    pub dimension: usize,
+    pub pooling: Pooling,
+    pub inputs: ModelInputs,
+    /// Output holding the token embeddings; the first output when unset.
+    pub output: Option<String>,
+    /// Longest token sequence the model accepts. Longer texts are embedded in
+    /// overlapping chunks and averaged.
+    pub max_tokens: usize,
+    /// Tokens per passage for passage-level retrieval, at most `max_tokens`.
+    pub chunk_tokens: usize,
+    /// Windows run through the model at once when indexing.
+    pub batch_size: usize,
+    pub query_prefix: String,
+    pub passage_prefix: String,
+}
+
+impl Default for ModelManifest {
+    fn default() -> Self {
+        Self {
+            id: "minilm-v2-q8".to_string(),
+            model_file: "model_quantized.onnx".to_string(),
+            tokenizer_file: "tokenizer.json".to_string(),
+            dimension: 384,
+            pooling: Pooling::Mean,
+            inputs: ModelInputs::default(),
+            output: None,
+            max_tokens: 512,
+            chunk_tokens: 256,
+            batch_size: 16,
+            query_prefix: String::new(),
+            passage_prefix: String::new(),
+        }
+    }
+}
+
+impl ModelManifest {
+    /// Read `model.json` from `resource_dir`, or use the default model when there is none.
+    pub fn load(resource_dir: &Path) -> Result<Self, SunderError> {
+        let path = resource_dir.join(MANIFEST_FILE);
+        let manifest: Self = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| {
+                SunderError::EmbeddingError(format!("Invalid {}: {e}", path.display()))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                return Err(SunderError::EmbeddingError(format!(
+                    "Failed to read {}: {e}",
+                    path.display()
+                )))
+            }
+        };
+
+        if manifest.dimension == 0
+            || manifest.batch_size == 0
+            || manifest.max_tokens < 2
+            || manifest.chunk_tokens < 2
+        {
+            return Err(SunderError::EmbeddingError(format!(
+                "{} needs a non-zero dimension and batch_size, and max_tokens and chunk_tokens of at least 2",
+                path.display()
+            )));
+        }
+        Ok(manifest)
+    }
+
+    fn prefix(&self, kind: InputKind) -> &str {
+        match kind {
+            InputKind::Query => &self.query_prefix,
+            InputKind::Passage => &self.passage_prefix,
+        }
+    }
+}
+
+/// Embedding of one passage of a text.
+#[derive(Debug, Clone)]
+pub struct ChunkEmbedding {
+    /// Character (not byte) offsets of the passage in the embedded text.
+    pub start: usize,
+    pub end: usize,
+    pub vector: Vec<f32>,
+}
+
+/// A text to embed in windows, for `EmbeddingProvider::embed_windows`.
+pub struct WindowInput<'a> {
+    pub text: &'a str,
+    /// Embedded ahead of the text (in its first window) but not part of any range.
+    pub context: &'a str,
+}
+
+/// A local model that turns text into embeddings.
+pub trait EmbeddingProvider: Send + Sync {
+    fn manifest(&self) -> &ModelManifest;
+
+    /// Identity of the loaded model, stored with every embedding. Vectors with
+    /// a different version came from another model and are never compared.
+    fn model_version(&self) -> &str;
+
+    /// Embed text into a unit vector of `manifest().dimension` values. For
+    /// queries and other interactive use; never waits behind `embed_windows`.
+    fn embed(&self, text: &str, kind: InputKind) -> Result<Vec<f32>, SunderError>;
+
+    /// Number of tokens in `text`, not counting special tokens.
+    fn count_tokens(&self, text: &str) -> Result<usize, SunderError>;
+
+    /// Embed each input in windows of `size` tokens overlapping by `overlap`,
+    /// with each window's character range in its input's text. For indexing;
+    /// all the inputs' windows are batched together.
+    fn embed_windows(
+        &self,
+        inputs: &[WindowInput<'_>],
+        kind: InputKind,
+        size: usize,
+        overlap: usize,
+    ) -> Result<Vec<Vec<ChunkEmbedding>>, SunderError>;
+
+    /// Number of threads that can usefully call `embed_windows` at once.
+    fn parallelism(&self) -> usize {
+        1
+    }
+}
+
+/// Intra-op threads for the interactive session; queries are short.
+const INTERACTIVE_THREADS: usize = 2;
+
+/// Runs an ONNX sentence-embedding model with a Hugging Face tokenizer.
+/// Queries get a session of their own so they never queue behind indexing,
+/// which spreads its batches over a pool of sessions.
+pub struct OnnxProvider {
+    manifest: ModelManifest,
+    model_version: String,
+    interactive: Mutex<Session>,
+    bulk: Vec<Mutex<Session>>,
+    next_bulk: AtomicUsize,
+    tokenizer: Tokenizer,
+}
+
+/// Tokens of one window of a text, with the window's character range in it.
+struct Window {
+    ids: Vec<u32>,
+    mask: Vec<u32>,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Lane {
+    Interactive,
+    Bulk,
+}
+
+impl OnnxProvider {
+    /// Load the model with `bulk_sessions` sessions for indexing (at least
+    /// one), which share the machine's cores, plus one for queries.
+    pub fn new(
+        resource_dir: &Path,
+        manifest: ModelManifest,
+        bulk_sessions: usize,
+    ) -> Result<Self, SunderError> {
+        let model_path = resource_dir.join(&manifest.model_file);
+        let tokenizer_path = resource_dir.join(&manifest.tokenizer_file);
+
+        if !model_path.exists() {
+            return Err(SunderError::EmbeddingError(format!(
+                "ONNX model not found: {}",
+                model_path.display()
+            )));
+        }
+        if !tokenizer_path.exists() {
+            return Err(SunderError::EmbeddingError(format!(
+                "Tokenizer not found: {}",
+                tokenizer_path.display()
+            )));
+        }
+
+        let bulk_sessions = bulk_sessions.max(1);
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let interactive = Mutex::new(load_session(&model_path, INTERACTIVE_THREADS)?);
+        let bulk = (0..bulk_sessions)
+            .map(|_| load_session(&model_path, (cores / bulk_sessions).max(1)).map(Mutex::new))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tokenizer = Tokenizer::from_file(&tokenizer_path)
+            .map_err(|e| SunderError::EmbeddingError(format!("Load tokenizer: {e}")))?;
+
+        let model_version = format!(
+            "{}@{}",
+            manifest.id,
+            &hash_files(&[&model_path, &tokenizer_path])?[..16]
+        );
+
+        tracing::info!(
+            "Loaded embedding model {model_version} ({} dimensions, {bulk_sessions} indexing session(s))",
+            manifest.dimension
+        );
+        Ok(Self {
+            manifest,
+            model_version,
+            interactive,
+            bulk,
+            next_bulk: AtomicUsize::new(0),
+            tokenizer,
+        })
+    }
+
+    /// Tokenize `text`, after the prefix for `kind` and `context`, into windows
+    /// of `size` tokens overlapping by `overlap`.
+    fn windows(
+        &self,
+        text: &str,
+        kind: InputKind,
+        context: &str,
+        size: usize,
+        overlap: usize,
+    ) -> Result<Vec<Window>, SunderError> {
+        let prefix = format!("{}{context}", self.manifest.prefix(kind));
+        let prefix_chars = prefix.chars().count();
+        let input = format!("{prefix}{text}");
+        let encoding = self
+            .tokenizer
+            .encode_char_offsets(input, true)
+            .map_err(|e| SunderError::EmbeddingError(format!("Tokenization failed: {e}")))?;
+
+        let ids = encoding.get_ids();
+        let mask = encoding.get_attention_mask();
+        let offsets = encoding.get_offsets();
+
+        let mut windows = Vec::new();
+        let mut start = 0;
+        loop {
+            let end = (start + size).min(ids.len());
+            // Special tokens have empty offsets and, like the prefix, don't
+            // count towards the range
+            let (char_start, char_end) = offsets[start..end]
+                .iter()
+                .filter(|(s, e)| e > s && *s >= prefix_chars)
+                .fold(None, |range: Option<(usize, usize)>, &(s, e)| match range {
+                    Some((rs, re)) => Some((rs.min(s), re.max(e))),
+                    None => Some((s, e)),
+                })
+                .unwrap_or((prefix_chars, prefix_chars));
+            windows.push(Window {
+                ids: ids[start..end].to_vec(),
+                mask: mask[start..end].to_vec(),
+                start: char_start.saturating_sub(prefix_chars),
+                end: char_end.saturating_sub(prefix_chars),
+            });
+
+            if end >= ids.len() {
+                break;
+            }
+            start += size - overlap;
+        }
+        Ok(windows)
+    }
+
+    /// Embed windows in batches of similar length, so little of each batch is
+    /// padding. Vectors come back in the windows' order.
+    fn embed_all(&self, windows: &[Window], lane: Lane) -> Result<Vec<Vec<f32>>, SunderError> {
+        let mut order: Vec<usize> = (0..windows.len()).collect();
+        order.sort_by_key(|&i| windows[i].ids.len());
+
+        let mut vectors = vec![Vec::new(); windows.len()];
+        for batch in order.chunks(self.manifest.batch_size) {
+            let batch_windows: Vec<&Window> = batch.iter().map(|&i| &windows[i]).collect();
+            let mut session = self.lock_session(lane)?;
+            let embedded = self.embed_batch(&mut session, &batch_windows)?;
+            for (&i, vector) in batch.iter().zip(embedded) {
+                vectors[i] = vector;
+            }
+        }
+        Ok(vectors)
+    }
+
+    /// The interactive session, or for bulk work a free indexing session (the
+    /// next one in turn when all are busy).
+    fn lock_session(&self, lane: Lane) -> Result<MutexGuard<'_, Session>, SunderError> {
+        let session = match lane {
+            Lane::Interactive => &self.interactive,
+            Lane::Bulk => {
+                if let Some(guard) = self.bulk.iter().find_map(|s| s.try_lock().ok()) {
+                    return Ok(guard);
+                }
+                &self.bulk[self.next_bulk.fetch_add(1, Ordering::Relaxed) % self.bulk.len()]
+            }
+        };
+        session
+            .lock()
+            .map_err(|e| SunderError::EmbeddingError(format!("Session lock: {e}")))
+    }
+
+    /// Run ONNX inference on a batch of windows, padded to the longest, then
+    /// pool and normalize each one.
+    fn embed_batch(
+        &self,
+        session: &mut Session,
+        windows: &[&Window],
+    ) -> Result<Vec<Vec<f32>>, SunderError> {
+        let batch = windows.len();
+        let seq_len = windows.iter().map(|w| w.ids.len()).max().unwrap_or(0);
+        let inputs = &self.manifest.inputs;
+
+        // Padding uses token 0; the attention mask hides it from the model
+        let mut ids = vec![0i64; batch * seq_len];
+        let mut attention_mask = vec![0i64; batch * seq_len];
+        for (b, window) in windows.iter().enumerate() {
+            let row = b * seq_len;
+            for (t, (&id, &m)) in window.ids.iter().zip(&window.mask).enumerate() {
+                ids[row + t] = id as i64;
+                attention_mask[row + t] = m as i64;
+            }
+        }
+
+        let tensor = |values: Vec<i64>, name: &str| {
+            Tensor::from_array(([batch, seq_len], values.into_boxed_slice()))
+                .map_err(|e| SunderError::EmbeddingError(format!("{name} tensor: {e}")))
+        };
+        let mut feeds = vec![
+            (
+                inputs.input_ids.clone(),
+                tensor(ids, &inputs.input_ids)?.into_dyn(),
+            ),
+            (
+                inputs.attention_mask.clone(),
+                tensor(attention_mask.clone(), &inputs.attention_mask)?.into_dyn(),
+            ),
+        ];
+        if let Some(name) = &inputs.token_type_ids {
+            feeds.push((name.clone(), tensor(vec![0i64; batch * seq_len], name)?.into_dyn()));
+        }
+
+        let outputs = session
+            .run(feeds)
+            .map_err(|e| SunderError::EmbeddingError(format!("Inference failed: {e}")))?;
+
+        let output = match &self.manifest.output {
+            Some(name) => outputs.get(name).ok_or_else(|| {
+                SunderError::EmbeddingError(format!("Model has no output named {name}"))
+            })?,
+            None => &outputs[0],
+        };
+        // Output shape: [batch, seq_len, dimension], or [batch, dimension] for
+        // models that pool themselves
+        let (shape, data) = output
+            .try_extract_tensor::<f32>()
+            .map_err(|e| SunderError::EmbeddingError(format!("Output extraction: {e}")))?;
+
+        let dims: &[i64] = shape;
+        let pooled_output = dims.len() == 2;
+        let hidden_dim = dims.last().map_or(0, |&d| d as usize);
+        if hidden_dim != self.manifest.dimension {
+            return Err(SunderError::EmbeddingError(format!(
+                "Model {} produces {hidden_dim}-dimensional embeddings, manifest says {}",
+                self.manifest.id, self.manifest.dimension
+            )));
+        }
+
+        let mut vectors = Vec::with_capacity(batch);
+        for b in 0..batch {
+            let mut pooled = if pooled_output {
+                data[b * hidden_dim..(b + 1) * hidden_dim].to_vec()
+            } else {
+                let tokens = &data[b * seq_len * hidden_dim..(b + 1) * seq_len * hidden_dim];
+                match self.manifest.pooling {
+                    Pooling::Cls => tokens[..hidden_dim].to_vec(),
+                    Pooling::Mean => {
+                        // Mean pooling with attention mask
+                        let mask = &attention_mask[b * seq_len..(b + 1) * seq_len];
+                        let mut pooled = vec![0.0f32; hidden_dim];
+                        let mut total_weight = 0.0f32;
+
+                        for (t, &mask_val) in mask.iter().enumerate() {
+                            let w = mask_val as f32;
+                            total_weight += w;
+                            let offset = t * hidden_dim;
+                            for d in 0..hidden_dim {
+                                pooled[d] += tokens[offset + d] * w;
+                            }
+                        }
+
+                        if total_weight > 0.0 {
+                            for v in &mut pooled {
+                                *v /= total_weight;
+                            }
+                        }
+                        pooled
+                    }
+                }
+            };
+            l2_normalize(&mut pooled);
+            vectors.push(pooled);
+        }
+        Ok(vectors)
+    }
+}
+
+impl EmbeddingProvider for OnnxProvider {
+    fn manifest(&self) -> &ModelManifest {
+        &self.manifest
+    }
+
+    fn model_version(&self) -> &str {
+        &self.model_version
+    }
+
+    fn embed(&self, text: &str, kind: InputKind) -> Result<Vec<f32>, SunderError> {
+        // Long texts are embedded in windows with half a window of overlap
+        let max_tokens = self.manifest.max_tokens;
+        let windows = self.windows(text, kind, "", max_tokens, max_tokens / 2)?;
+        let vectors = self.embed_all(&windows, Lane::Interactive)?;
+        if vectors.len() == 1 {
+            return Ok(vectors.into_iter().next().unwrap_or_default());
+        }
+        Ok(mean_vector(vectors.iter().map(Vec::as_slice), self.manifest.dimension))
+    }
+
+    fn count_tokens(&self, text: &str) -> Result<usize, SunderError> {
+        self.tokenizer
+            .encode(text, false)
+            .map(|encoding| encoding.len())
+            .map_err(|e| SunderError::EmbeddingError(format!("Tokenization failed: {e}")))
+    }
+
+    fn embed_windows(
+        &self,
+        inputs: &[WindowInput<'_>],
+        kind: InputKind,
+        size: usize,
+        overlap: usize,
+    ) -> Result<Vec<Vec<ChunkEmbedding>>, SunderError> {
+        let mut windows = Vec::new();
+        let mut owners = Vec::new();
+        for (i, input) in inputs.iter().enumerate() {
+            for window in self.windows(input.text, kind, input.context, size, overlap)? {
+                windows.push(window);
+                owners.push(i);
+            }
+        }
+
+        let vectors = self.embed_all(&windows, Lane::Bulk)?;
+        let mut embedded: Vec<Vec<ChunkEmbedding>> = inputs.iter().map(|_| Vec::new()).collect();
+        for ((owner, window), vector) in owners.into_iter().zip(&windows).zip(vectors) {
+            embedded[owner].push(ChunkEmbedding {
+                start: window.start,
+                end: window.end,
+                vector,
+            });
+        }
+        Ok(embedded)
+    }
+
+    fn parallelism(&self) -> usize {
+        self.bulk.len()
+    }
+}
+
+fn load_session(model_path: &Path, threads: usize) -> Result<Session, SunderError> {
+    Session::builder()
+        .map_err(|e| SunderError::EmbeddingError(format!("Session builder: {e}")))?
+        .with_intra_threads(threads)
+        .map_err(|e| SunderError::EmbeddingError(format!("Set threads: {e}")))?
+        .commit_from_file(model_path)
+        .map_err(|e| SunderError::EmbeddingError(format!("Load ONNX: {e}")))
+}
+
+/// Normalized average of vectors, e.g. to get one vector for a whole note.
+pub fn mean_vector<'a>(vectors: impl IntoIterator<Item = &'a [f32]>, dimension: usize) -> Vec<f32> {
+    let mut avg = vec![0.0f32; dimension];
+    let mut n = 0usize;
+    for vector in vectors {
+        for (i, v) in vector.iter().enumerate() {
+            avg[i] += v;
+        }
+        n += 1;
+    }
+    for v in &mut avg {
+        *v /= n.max(1) as f32;
+    }
+
+    l2_normalize(&mut avg);
+    avg
+}
+
+/// SHA-256 over the contents of `paths`, in order, as hex.
+fn hash_files(paths: &[&Path]) -> Result<String, SunderError> {
+    let mut hasher = Sha256::new();
+    for path in paths {
+        let mut file = std::fs::File::open(path).map_err(|e| {
+            SunderError::EmbeddingError(format!("Failed to open {}: {e}", path.display()))
+        })?;
+        std::io::copy(&mut file, &mut hasher).map_err(|e| {
+            SunderError::EmbeddingError(format!("Failed to hash {}: {e}", path.display()))
+        })?;
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn l2_normalize(v: &mut [f32]) {
+    let norm: f32 = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for x in v.iter_mut() {
+            *x /= norm;
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use crate::db::DatabaseManager;
 use crate::error::SunderError;
-use crate::services::embedding::EmbeddingService;
-use crate::services::graph::GraphService;
+use crate::services::front_matter::Document;
+use crate::services::index_queue::{IndexQueueService, JobPriority};
 use crate::services::note::NoteService;
+use crate::services::trash::TrashService;
+use crate::services::vault;
 use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc;
@@ -12,7 +14,7 @@ use tauri::Emitter;
 
 pub struct FileWatcherService {
     db: Arc<DatabaseManager>,
-    embedding_service: Arc<EmbeddingService>,
+    index_queue: Arc<IndexQueueService>,
     watcher: Mutex<Option<WatcherState>>,
 }
 
@@ -22,10 +24,10 @@ struct WatcherState {
 }
 
 impl FileWatcherService {
-    pub fn new(db: Arc<DatabaseManager>, embedding_service: Arc<EmbeddingService>) -> Self {
+    pub fn new(db: Arc<DatabaseManager>, index_queue: Arc<IndexQueueService>) -> Self {
         Self {
             db,
-            embedding_service,
+            index_queue,
             watcher: Mutex::new(None),
         }
     }
@@ -54,7 +56,7 @@ impl FileWatcherService {
         self.stop_watching()?;
 
         let db = Arc::clone(&self.db);
-        let emb = Arc::clone(&self.embedding_service);
+        let queue = Arc::clone(&self.index_queue);
         let watch_dir = canonical_dir.clone();
 
         // Create a channel-based watcher with debounce
@@ -80,7 +82,10 @@ impl FileWatcherService {
                     Ok(Ok(event)) => {
                         if should_process_event(&event, &watch_dir) {
                             for path in &event.paths {
-                                if is_markdown_file(path) && is_safe_path(path, &watch_dir) {
+                                if is_markdown_file(path)
+                                    && is_safe_path(path, &watch_dir)
+                                    && !is_hidden_path(path, &watch_dir)
+                                {
                                     pending_paths.insert(path.clone());
                                 }
                             }
@@ -99,7 +104,7 @@ impl FileWatcherService {
                                 process_file_change(
                                     &path,
                                     &db,
-                                    &emb,
+                                    &queue,
                                     &handle,
                                 );
                             }
@@ -140,6 +145,14 @@ impl FileWatcherService {
         Ok(())
     }
 
+    /// Canonical path of the directory currently being watched.
+    pub fn watch_dir(&self) -> Option<PathBuf> {
+        self.watcher
+            .lock()
+            .ok()
+            .and_then(|state| state.as_ref().map(|ws| ws.watch_dir.clone()))
+    }
+
     /// Scan a directory and import all .md files.
     pub fn scan_directory(
         &self,
@@ -164,6 +177,8 @@ impl FileWatcherService {
     ) -> Result<u32, SunderError> {
         let note_service = NoteService::new(Arc::clone(&self.db));
         let mut imported = 0u32;
+        // Imported notes long enough to embed, queued together once the scan is done
+        let mut to_index = Vec::new();
 
         let entries = walk_md_files(dir)?;
         let total = entries.len() as u32;
@@ -173,15 +188,11 @@ impl FileWatcherService {
                 Ok(true) => {
                     imported += 1;
 
-                    // Index embedding for imported note
                     if let Ok(Some(note)) =
                         note_service.get_note_by_file_path(&path.to_string_lossy())
                     {
                         if note.content.split_whitespace().count() >= 3 {
-                            let _ = self.embedding_service.index_note(&note.id, &note.content);
-                            let graph_svc =
-                                GraphService::new(Arc::clone(&self.db), Arc::clone(&self.embedding_service));
-                            let _ = graph_svc.rebuild_cache_for_note(&note.id);
+                            to_index.push(note.id);
                         }
                     }
                 }
@@ -201,6 +212,11 @@ impl FileWatcherService {
             );
         }
 
+        // The index queue embeds them in the background, reporting progress
+        if let Err(e) = self.index_queue.enqueue_many(&to_index, JobPriority::Bulk) {
+            tracing::warn!("Failed to queue scanned notes for indexing: {e}");
+        }
+
         tracing::info!("Scan complete: {imported}/{total} files imported from {}", dir.display());
         Ok(imported)
     }
@@ -230,11 +246,21 @@ fn is_safe_path(path: &Path, watch_dir: &Path) -> bool {
     }
 }
 
+/// Whether any component below the watch directory is hidden (e.g. `.sunder/trash`).
+/// The initial scan skips these too.
+fn is_hidden_path(path: &Path, watch_dir: &Path) -> bool {
+    path.strip_prefix(watch_dir).is_ok_and(|relative| {
+        relative
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+    })
+}
+
 /// Process a single file change event.
 fn process_file_change(
     path: &Path,
     db: &Arc<DatabaseManager>,
-    emb: &Arc<EmbeddingService>,
+    queue: &IndexQueueService,
     app_handle: &tauri::AppHandle,
 ) {
     let note_service = NoteService::new(Arc::clone(db));
@@ -244,12 +270,12 @@ fn process_file_change(
         // Create or update
         match import_md_file(path, &note_service, db) {
             Ok(true) => {
-                // Index embedding
+                // Queue the note to be embedded
                 if let Ok(Some(note)) = note_service.get_note_by_file_path(&path_str) {
                     if note.content.split_whitespace().count() >= 3 {
-                        let _ = emb.index_note(&note.id, &note.content);
-                        let graph_svc = GraphService::new(Arc::clone(db), Arc::clone(emb));
-                        let _ = graph_svc.rebuild_cache_for_note(&note.id);
+                        if let Err(e) = queue.enqueue(&note.id, JobPriority::Interactive) {
+                            tracing::warn!("Failed to queue {} for indexing: {e}", path.display());
+                        }
                     }
                 }
 
@@ -265,12 +291,11 @@ fn process_file_change(
             Err(e) => tracing::warn!("Failed to import {}: {e}", path.display()),
         }
     } else {
-        // File deleted — remove corresponding note
+        // File deleted — move the corresponding note to the trash so it can be restored
         if let Ok(Some(note)) = note_service.get_note_by_file_path(&path_str) {
-            let _ = emb.remove_embedding(&note.id);
-            // Don't call note_service.delete_note because that tries to delete the file too
-            if let Ok(conn) = db.get_write_conn() {
-                let _ = conn.execute("DELETE FROM notes WHERE id = ?1", [&note.id]);
+            if let Err(e) = TrashService::new(Arc::clone(db)).trash_note(&note.id) {
+                tracing::warn!("Failed to trash note for {}: {e}", path.display());
+                return;
             }
 
             let _ = app_handle.emit(
@@ -293,65 +318,41 @@ fn import_md_file(
 ) -> Result<bool, SunderError> {
     let content = std::fs::read_to_string(path)?;
     let path_str = path.to_string_lossy().to_string();
+    let hash = vault::file_hash(&content);
 
     // Check if note already exists for this file
     let existing = note_service.get_note_by_file_path(&path_str)?;
 
+    // Skip files we wrote ourselves (or have already imported)
+    if existing
+        .as_ref()
+        .is_some_and(|note| note.file_hash.as_deref() == Some(hash.as_str()))
+    {
+        return Ok(false);
+    }
+
     // Extract title from YAML front matter or filename
-    let (title, body) = extract_front_matter(&content, path);
+    let doc = Document::parse(&content);
+    let title = doc.title_or_file_stem(path);
+    let metadata = doc.metadata();
+    let body = doc.body;
 
     if let Some(note) = existing {
         // Skip if content hasn't changed
-        if note.content == body && note.title == title {
+        if note.content == body && note.title == title && note.metadata == metadata {
             return Ok(false);
         }
 
         // Update existing note
-        note_service.update_note(
-            &note.id,
-            Some(title),
-            Some(body),
-        )?;
+        note_service.update_from_file(&note.id, title, body, hash, metadata)?;
         Ok(true)
     } else {
         // Create new note
-        note_service.create_note(title, body, Some(path_str))?;
+        note_service.create_from_file(title, body, path_str, hash, metadata)?;
         Ok(true)
     }
 }
 
-/// Extract title from YAML front matter, falling back to filename.
-fn extract_front_matter(content: &str, path: &Path) -> (String, String) {
-    let fallback_title = path
-        .file_stem()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or_else(|| "Untitled".to_string());
-
-    if !content.starts_with("---") {
-        return (fallback_title, content.to_string());
-    }
-
-    // Find closing ---
-    if let Some(end_idx) = content[3..].find("---") {
-        let front_matter = &content[3..3 + end_idx];
-        let body = content[3 + end_idx + 3..].trim_start().to_string();
-
-        // Parse YAML front matter for title
-        if let Ok(yaml) = serde_yaml::from_str::<serde_yaml::Value>(front_matter) {
-            if let Some(title) = yaml.get("title").and_then(|v| v.as_str()) {
-                let title = title.trim().to_string();
-                if !title.is_empty() {
-                    return (title, body);
-                }
-            }
-        }
-
-        (fallback_title, body)
-    } else {
-        (fallback_title, content.to_string())
-    }
-}
-
 /// Recursively walk directory for .md files.
 fn walk_md_files(dir: &Path) -> Result<Vec<PathBuf>, SunderError> {
     let mut files = Vec::new();
@@ -0,0 +1,149 @@
+use crate::db::DatabaseManager;
+use crate::error::SunderError;
+use crate::services::vault;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug, Serialize)]
+pub struct FolderNode {
+    /// Last path component; empty for the vault root.
+    pub name: String,
+    /// Path relative to the vault root with `/` separators; empty for the root.
+    pub path: String,
+    /// Notes directly in this folder.
+    pub count: u32,
+    /// Notes in this folder or any folder below it.
+    pub total_count: u32,
+    pub has_children: bool,
+    /// Subfolders, or `None` when they were not loaded (below the requested depth).
+    pub children: Option<Vec<FolderNode>>,
+}
+
+pub struct FolderService {
+    db: Arc<DatabaseManager>,
+}
+
+/// Note counts per folder, keyed by vault-relative path.
+#[derive(Default)]
+struct FolderCounts {
+    count: u32,
+    total_count: u32,
+    children: BTreeSet<String>,
+}
+
+impl FolderService {
+    pub fn new(db: Arc<DatabaseManager>) -> Self {
+        Self { db }
+    }
+
+    /// The folder tree under `folder` (the vault root by default), derived from
+    /// the paths of the notes' files. Only folders containing notes appear.
+    /// Subfolders are loaded `depth` levels deep; deeper ones have `children: None`
+    /// and are fetched by calling this again with their path.
+    pub fn get_note_tree(&self, folder: Option<&str>, depth: u32) -> Result<FolderNode, SunderError> {
+        let folder = normalize_folder(folder.unwrap_or(""));
+        let mut folders: BTreeMap<String, FolderCounts> = BTreeMap::new();
+        folders.insert(String::new(), FolderCounts::default());
+
+        if let Some(vault_dir) = vault::active_vault_dir(&self.db)? {
+            let conn = self.db.get_read_conn()?;
+            let mut stmt = conn.prepare("SELECT file_path FROM notes WHERE file_path IS NOT NULL")?;
+            let paths = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for path in &paths {
+                let Some(parts) = folder_components(&vault_dir, Path::new(path)) else {
+                    continue;
+                };
+                let mut current = String::new();
+                folders.entry(String::new()).or_default().total_count += 1;
+                for part in &parts {
+                    let parent = current.clone();
+                    if !current.is_empty() {
+                        current.push('/');
+                    }
+                    current.push_str(part);
+                    folders.entry(parent).or_default().children.insert(current.clone());
+                    folders.entry(current.clone()).or_default().total_count += 1;
+                }
+                folders.entry(current).or_default().count += 1;
+            }
+        }
+
+        if !folders.contains_key(&folder) {
+            return Err(SunderError::NotFound(format!("Folder not found: {folder}")));
+        }
+        Ok(build_node(&folders, &folder, depth))
+    }
+}
+
+fn build_node(folders: &BTreeMap<String, FolderCounts>, path: &str, depth: u32) -> FolderNode {
+    let counts = &folders[path];
+    let children = (depth > 0).then(|| {
+        let mut children: Vec<FolderNode> = counts
+            .children
+            .iter()
+            .map(|child| build_node(folders, child, depth - 1))
+            .collect();
+        children.sort_by_key(|c| c.name.to_lowercase());
+        children
+    });
+
+    FolderNode {
+        name: path.rsplit('/').next().unwrap_or_default().to_string(),
+        path: path.to_string(),
+        count: counts.count,
+        total_count: counts.total_count,
+        has_children: !counts.children.is_empty(),
+        children,
+    }
+}
+
+/// Folder names between the vault root and a note's file, or `None` when the
+/// file is outside the vault.
+fn folder_components(vault_dir: &Path, file: &Path) -> Option<Vec<String>> {
+    let relative = file.strip_prefix(vault_dir).ok()?;
+    let parent = relative.parent()?;
+    Some(
+        parent
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect(),
+    )
+}
+
+/// A vault-relative folder as given by the frontend, with `/` separators and no
+/// leading or trailing slash.
+pub fn normalize_folder(folder: &str) -> String {
+    folder.trim().replace('\\', "/").trim_matches('/').to_string()
+}
+
+/// The prefix shared by all file paths inside `folder`, with `/` separators,
+/// for binding to `folder_filter_clause`.
+pub fn folder_prefix(vault_dir: &Path, folder: &str) -> String {
+    let folder = normalize_folder(folder);
+    let dir = if folder.is_empty() {
+        vault_dir.to_path_buf()
+    } else {
+        vault_dir.join(&folder)
+    };
+    let mut prefix = dir.to_string_lossy().replace('\\', "/");
+    if !prefix.ends_with('/') {
+        prefix.push('/');
+    }
+    prefix
+}
+
+/// SQL condition matching notes whose file is in the folder whose prefix is bound
+/// at `?{prefix_param}`, including subfolders when `?{recursive_param}` is true.
+/// `path_expr` is the file path column to filter.
+pub fn folder_filter_clause(path_expr: &str, prefix_param: usize, recursive_param: usize) -> String {
+    format!(
+        "(substr(replace({path_expr}, '\\', '/'), 1, length(?{prefix_param})) = ?{prefix_param}
+          AND (?{recursive_param}
+               OR instr(substr(replace({path_expr}, '\\', '/'), length(?{prefix_param}) + 1), '/') = 0))"
+    )
+}
@@ -0,0 +1,181 @@
+use std::path::Path;
+
+/// A markdown document split into its YAML front matter and body.
+///
+/// `raw` holds the YAML text between the `---` fences exactly as it appeared
+/// on disk (including its trailing newline) and `separator` holds the
+/// whitespace between the closing fence and the body, so that `render` can
+/// reproduce the original bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    pub raw: Option<String>,
+    pub separator: String,
+    pub body: String,
+    fences: (String, String),
+}
+
+impl Document {
+    pub fn parse(content: &str) -> Self {
+        let open = if content.starts_with("---\r\n") {
+            "---\r\n"
+        } else if content.starts_with("---\n") {
+            "---\n"
+        } else {
+            return Self::without_front_matter(content);
+        };
+        let after_open = &content[open.len()..];
+
+        // The closing fence is a line consisting only of `---` (or `...`)
+        let mut offset = 0;
+        for line in after_open.split_inclusive('\n') {
+            let trimmed = line.trim_end_matches(['\n', '\r']);
+            if trimmed == "---" || trimmed == "..." {
+                let raw = after_open[..offset].to_string();
+                let rest = &after_open[offset + line.len()..];
+                let body = rest.trim_start();
+                let separator = rest[..rest.len() - body.len()].to_string();
+                return Self {
+                    raw: Some(raw),
+                    separator,
+                    body: body.to_string(),
+                    fences: (open.to_string(), line.to_string()),
+                };
+            }
+            offset += line.len();
+        }
+
+        Self::without_front_matter(content)
+    }
+
+    fn without_front_matter(content: &str) -> Self {
+        Self {
+            raw: None,
+            separator: String::new(),
+            body: content.to_string(),
+            fences: default_fences(),
+        }
+    }
+
+    /// Title declared in the front matter, if any.
+    pub fn title(&self) -> Option<String> {
+        let raw = self.raw.as_deref()?;
+        let yaml = serde_yaml::from_str::<serde_yaml::Value>(raw).ok()?;
+        let title = yaml.get("title")?.as_str()?.trim();
+        if title.is_empty() {
+            None
+        } else {
+            Some(title.to_string())
+        }
+    }
+
+    /// All front matter keys as a JSON object, in their original order.
+    /// `None` when there is no front matter or it is not a YAML mapping.
+    pub fn metadata(&self) -> Option<serde_json::Value> {
+        let raw = self.raw.as_deref()?;
+        let yaml = serde_yaml::from_str::<serde_yaml::Value>(raw).ok()?;
+        if !yaml.is_mapping() {
+            return None;
+        }
+        serde_json::to_value(yaml).ok()
+    }
+
+    /// Replace the front matter with `metadata` serialized as YAML. Formatting
+    /// and comments of the previous front matter are not kept, so callers should
+    /// only do this when the metadata actually changed.
+    pub fn set_metadata(&mut self, metadata: &serde_json::Value) -> Result<(), serde_yaml::Error> {
+        if metadata.as_object().is_none_or(|m| m.is_empty()) {
+            self.raw = None;
+            self.separator.clear();
+            return Ok(());
+        }
+
+        let raw = serde_yaml::to_string(metadata)?;
+        if self.raw.is_none() {
+            self.separator = "\n".to_string();
+        }
+        self.raw = Some(raw);
+        Ok(())
+    }
+
+    /// Title for this document: front matter `title`, falling back to the filename.
+    pub fn title_or_file_stem(&self, path: &Path) -> String {
+        self.title().unwrap_or_else(|| file_stem_title(path))
+    }
+
+    /// Set the front matter `title` key, editing only that line so the rest of the
+    /// front matter (ordering, comments, formatting) is preserved.
+    pub fn set_title(&mut self, title: &str) {
+        let line = format!("title: {}", yaml_scalar(title));
+
+        let Some(raw) = &self.raw else {
+            self.raw = Some(format!("{line}\n"));
+            self.separator = "\n".to_string();
+            return;
+        };
+
+        let mut replaced = false;
+        let mut out = String::with_capacity(raw.len() + line.len());
+        let mut lines = raw.split_inclusive('\n').peekable();
+        while let Some(l) = lines.next() {
+            if !replaced && l.starts_with("title:") {
+                out.push_str(&line);
+                out.push('\n');
+                replaced = true;
+                // Drop continuation lines of a multi-line title value
+                while lines
+                    .peek()
+                    .is_some_and(|next| next.starts_with([' ', '\t']))
+                {
+                    lines.next();
+                }
+                continue;
+            }
+            out.push_str(l);
+        }
+
+        if !replaced {
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str(&line);
+            out.push('\n');
+        }
+
+        self.raw = Some(out);
+    }
+
+    pub fn render(&self) -> String {
+        match &self.raw {
+            Some(raw) => {
+                let (open, close) = &self.fences;
+                // A closing fence at EOF needs a newline once a body follows it
+                let close = if close.ends_with('\n') || self.body.is_empty() {
+                    close.clone()
+                } else {
+                    format!("{close}\n")
+                };
+                format!("{open}{raw}{close}{}{}", self.separator, self.body)
+            }
+            None => self.body.clone(),
+        }
+    }
+}
+
+fn default_fences() -> (String, String) {
+    ("---\n".to_string(), "---\n".to_string())
+}
+
+/// Fallback title derived from a file's name.
+pub fn file_stem_title(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "Untitled".to_string())
+}
+
+/// Serialize a string as a single-line YAML scalar, quoting only when needed.
+fn yaml_scalar(value: &str) -> String {
+    match serde_yaml::to_string(value) {
+        Ok(s) => s.trim_end_matches('\n').to_string(),
+        Err(_) => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+    }
+}
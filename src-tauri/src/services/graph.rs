@@ -1,6 +1,6 @@
 use crate::db::DatabaseManager;
 use crate::error::SunderError;
-use crate::services::embedding::blob_to_embedding;
+use crate::services::embedding::{blob_to_embedding, EmbeddingSlot};
 use serde::Serialize;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -27,11 +27,12 @@ pub struct GraphData {
 
 pub struct GraphService {
     db: Arc<DatabaseManager>,
+    embedding: Arc<EmbeddingSlot>,
 }
 
 impl GraphService {
-    pub fn new(db: Arc<DatabaseManager>, _embedding_service: Arc<crate::services::embedding::EmbeddingService>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<DatabaseManager>, embedding: Arc<EmbeddingSlot>) -> Self {
+        Self { db, embedding }
     }
 
     pub fn get_graph(
@@ -89,13 +90,15 @@ impl GraphService {
 
     /// Rebuild similarity cache for a single note against all other notes.
     pub fn rebuild_cache_for_note(&self, note_id: &str) -> Result<(), SunderError> {
+        let embedding_service = self.embedding.get()?;
         let conn = self.db.get_read_conn()?;
 
-        // Get this note's embedding
+        // Get this note's embedding; vectors from other models are not comparable
+        let model_version = embedding_service.model_version();
         let note_vec: Option<Vec<u8>> = conn
             .query_row(
-                "SELECT vector FROM embeddings WHERE note_id = ?1",
-                [note_id],
+                "SELECT vector FROM embeddings WHERE note_id = ?1 AND model_version = ?2",
+                rusqlite::params![note_id, model_version],
                 |row| row.get(0),
             )
             .ok();
@@ -106,9 +109,11 @@ impl GraphService {
         };
 
         // Get all other embeddings
-        let mut stmt = conn.prepare("SELECT note_id, vector FROM embeddings WHERE note_id != ?1")?;
+        let mut stmt = conn.prepare(
+            "SELECT note_id, vector FROM embeddings WHERE note_id != ?1 AND model_version = ?2",
+        )?;
         let others: Vec<(String, Vec<f32>)> = stmt
-            .query_map([note_id], |row| {
+            .query_map(rusqlite::params![note_id, model_version], |row| {
                 let id: String = row.get(0)?;
                 let blob: Vec<u8> = row.get(1)?;
                 Ok((id, blob_to_embedding(&blob)))
@@ -148,10 +153,11 @@ impl GraphService {
 
     /// Rebuild the full similarity cache for all note pairs.
     pub fn rebuild_full_cache(&self) -> Result<u32, SunderError> {
+        let embedding_service = self.embedding.get()?;
         let conn = self.db.get_read_conn()?;
-        let mut stmt = conn.prepare("SELECT note_id, vector FROM embeddings")?;
+        let mut stmt = conn.prepare("SELECT note_id, vector FROM embeddings WHERE model_version = ?1")?;
         let all: Vec<(String, Vec<f32>)> = stmt
-            .query_map([], |row| {
+            .query_map([embedding_service.model_version()], |row| {
                 let id: String = row.get(0)?;
                 let blob: Vec<u8> = row.get(1)?;
                 Ok((id, blob_to_embedding(&blob)))
@@ -0,0 +1,491 @@
+use crate::db::DatabaseManager;
+use crate::error::SunderError;
+use crate::services::embedding::{EmbeddingSlot, NOTES_PER_BATCH};
+use crate::services::graph::GraphService;
+use crate::services::settings::setting;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::Instant;
+
+/// Times a job is tried before it's dropped.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Which jobs the worker takes first. Jobs of the same priority run oldest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobPriority {
+    /// Imports, rescans and re-embedding after a settings or model change.
+    Bulk = 0,
+    /// Notes the user just edited.
+    Interactive = 1,
+}
+
+/// Snapshot of the queue, passed to the listener whenever it changes.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStatus {
+    /// Jobs waiting, including the batch being worked on.
+    pub pending: u32,
+    /// How many of those are interactive.
+    pub interactive: u32,
+    /// Notes embedded since the queue was last empty, across restarts.
+    pub processed: u32,
+    /// Whether bulk jobs are on hold. Interactive jobs still run.
+    pub paused: bool,
+    /// Notes embedded per second since the worker last started working.
+    pub notes_per_second: Option<f64>,
+    /// Estimated seconds until the queue is empty, at that rate.
+    pub eta_seconds: Option<u64>,
+    /// Title of the latest note embedded, when the status follows a batch.
+    pub current_note_title: Option<String>,
+}
+
+type Listener = Box<dyn Fn(&QueueStatus) + Send + Sync>;
+
+/// A claimed job: note id, priority, the version it had when claimed and
+/// whether to re-embed an unchanged note.
+struct Job {
+    note_id: String,
+    priority: i64,
+    version: i64,
+    force: bool,
+}
+
+/// Persistent queue of notes to embed, worked by a single background thread.
+/// Jobs live in `index_jobs`, one per note: enqueueing a note that's already
+/// waiting bumps its version (and priority, if higher) instead of adding
+/// another. The worker reads each note when its job comes up, so a burst of
+/// edits is embedded once, from the latest content, and jobs left when the
+/// app quits run on the next launch. Jobs are removed batch by batch as they
+/// finish, together with the progress count, so an interrupted reindex picks
+/// up where it stopped. Bulk jobs can be paused or cancelled. Without an
+/// embedding model, jobs wait until one is loaded.
+pub struct IndexQueueService {
+    db: Arc<DatabaseManager>,
+    embedding: Arc<EmbeddingSlot>,
+    wake: (Mutex<bool>, Condvar),
+    listener: OnceLock<Listener>,
+    /// Notes embedded since the queue was last empty; persisted as the
+    /// `index_processed` setting.
+    processed: AtomicU32,
+    /// Persisted as the `indexing_paused` setting.
+    paused: AtomicBool,
+    /// Set by `cancel`, to stop the bulk batch being worked on.
+    cancelled: AtomicBool,
+    /// When the worker last started working, and `processed` at the time.
+    rate_since: Mutex<Option<(Instant, u32)>>,
+}
+
+impl IndexQueueService {
+    pub fn new(db: Arc<DatabaseManager>, embedding: Arc<EmbeddingSlot>) -> Self {
+        Self {
+            db,
+            embedding,
+            wake: (Mutex::new(false), Condvar::new()),
+            listener: OnceLock::new(),
+            processed: AtomicU32::new(0),
+            paused: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            rate_since: Mutex::new(None),
+        }
+    }
+
+    /// Start the worker, which first picks up jobs (and the pause and progress
+    /// count) left from the last session. `listener` gets the queue status
+    /// after each change.
+    pub fn start<F>(self: &Arc<Self>, listener: F) -> Result<(), SunderError>
+    where
+        F: Fn(&QueueStatus) + Send + Sync + 'static,
+    {
+        if self.listener.set(Box::new(listener)).is_err() {
+            tracing::warn!("Index queue already started");
+            return Ok(());
+        }
+        {
+            let conn = self.db.get_read_conn()?;
+            let paused = setting(&conn, "indexing_paused")?.as_deref() == Some("true");
+            let processed = setting(&conn, "index_processed")?
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0);
+            self.paused.store(paused, Ordering::SeqCst);
+            self.processed.store(processed, Ordering::SeqCst);
+        }
+        let queue = Arc::clone(self);
+        std::thread::spawn(move || queue.run());
+        Ok(())
+    }
+
+    /// Hold bulk jobs, stopping after the batch being worked on. Stays paused
+    /// across restarts until `resume`.
+    pub fn pause(&self) -> Result<(), SunderError> {
+        self.set_paused(true)
+    }
+
+    /// Let paused bulk jobs run again.
+    pub fn resume(&self) -> Result<(), SunderError> {
+        self.set_paused(false)?;
+        self.wake();
+        Ok(())
+    }
+
+    fn set_paused(&self, paused: bool) -> Result<(), SunderError> {
+        {
+            let conn = self.db.get_write_conn()?;
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('indexing_paused', ?1)",
+                [paused.to_string()],
+            )?;
+        }
+        self.paused.store(paused, Ordering::SeqCst);
+        self.notify(None);
+        Ok(())
+    }
+
+    /// Drop all bulk jobs, stopping after the batch being worked on.
+    /// Interactive jobs are kept. Returns the number of jobs dropped.
+    pub fn cancel(&self) -> Result<u32, SunderError> {
+        let dropped = {
+            let conn = self.db.get_write_conn()?;
+            conn.execute(
+                "DELETE FROM index_jobs WHERE priority = ?1",
+                [JobPriority::Bulk as i64],
+            )?
+        };
+        self.cancelled.store(true, Ordering::SeqCst);
+        tracing::info!("Cancelled indexing of {dropped} note(s)");
+        self.notify(None);
+        // Let an idle worker reset the progress count
+        self.wake();
+        Ok(dropped as u32)
+    }
+
+    /// Queue a note to be embedded.
+    pub fn enqueue(&self, note_id: &str, priority: JobPriority) -> Result<(), SunderError> {
+        self.enqueue_many(&[note_id.to_string()], priority)
+    }
+
+    /// Queue several notes to be embedded, in order.
+    pub fn enqueue_many(&self, note_ids: &[String], priority: JobPriority) -> Result<(), SunderError> {
+        self.insert_jobs(note_ids, priority, false)
+    }
+
+    /// Queue notes; with `force` they're re-embedded even if unchanged.
+    fn insert_jobs(
+        &self,
+        note_ids: &[String],
+        priority: JobPriority,
+        force: bool,
+    ) -> Result<(), SunderError> {
+        if note_ids.is_empty() {
+            return Ok(());
+        }
+        {
+            let mut conn = self.db.get_write_conn()?;
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare(
+                    "INSERT INTO index_jobs (note_id, priority, force, enqueued_at)
+                     VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT(note_id) DO UPDATE SET
+                         priority = MAX(priority, excluded.priority),
+                         force = MAX(force, excluded.force),
+                         version = version + 1,
+                         attempts = 0",
+                )?;
+                let now = chrono::Utc::now().to_rfc3339();
+                for note_id in note_ids {
+                    stmt.execute(rusqlite::params![note_id, priority as i64, force, now])?;
+                }
+            }
+            tx.commit()?;
+        }
+
+        self.notify(None);
+        self.wake();
+        Ok(())
+    }
+
+    /// Queue the notes whose embeddings are missing, from another model or
+    /// chunked with other settings, and wake the worker for jobs that were
+    /// waiting on a model. Returns how many were queued; none without a model.
+    pub fn enqueue_stale(&self) -> Result<u32, SunderError> {
+        let Some(embedding_service) = self.embedding.current() else {
+            return Ok(0);
+        };
+        let ids = embedding_service.stale_note_ids()?;
+        self.enqueue_many(&ids, JobPriority::Bulk)?;
+        self.wake();
+        Ok(ids.len() as u32)
+    }
+
+    /// Queue every note long enough to embed. Without `force`, notes whose
+    /// embedding matches their content are skipped when their job runs.
+    /// Returns how many were queued.
+    pub fn enqueue_all(&self, force: bool) -> Result<u32, SunderError> {
+        let ids: Vec<String> = {
+            let conn = self.db.get_read_conn()?;
+            let mut stmt = conn.prepare(
+                "SELECT id FROM notes WHERE word_count >= 3 ORDER BY updated_at DESC",
+            )?;
+            let ids = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            ids
+        };
+        self.insert_jobs(&ids, JobPriority::Bulk, force)?;
+        Ok(ids.len() as u32)
+    }
+
+    /// Number of jobs waiting, and how many of them are interactive.
+    pub fn depth(&self) -> Result<(u32, u32), SunderError> {
+        let conn = self.db.get_read_conn()?;
+        Ok(conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(priority = ?1), 0) FROM index_jobs",
+            [JobPriority::Interactive as i64],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?)
+    }
+
+    pub fn status(&self) -> Result<QueueStatus, SunderError> {
+        self.snapshot(None)
+    }
+
+    fn snapshot(&self, current_note_title: Option<&str>) -> Result<QueueStatus, SunderError> {
+        let (pending, interactive) = self.depth()?;
+        let processed = self.processed.load(Ordering::SeqCst);
+
+        let since = self.rate_since.lock().ok().and_then(|since| *since);
+        let notes_per_second = since.and_then(|(start, processed_then)| {
+            let elapsed = start.elapsed().as_secs_f64();
+            let done = processed.saturating_sub(processed_then);
+            (elapsed > 0.0 && done > 0).then(|| done as f64 / elapsed)
+        });
+        let eta_seconds = notes_per_second.map(|rate| (pending as f64 / rate).ceil() as u64);
+
+        Ok(QueueStatus {
+            pending,
+            interactive,
+            processed,
+            paused: self.paused.load(Ordering::SeqCst),
+            notes_per_second,
+            eta_seconds,
+            current_note_title: current_note_title.map(str::to_string),
+        })
+    }
+
+    fn run(&self) {
+        let graph_service = GraphService::new(Arc::clone(&self.db), Arc::clone(&self.embedding));
+        // Whether any bulk jobs ran; their similarities are rebuilt all at once
+        // when the queue empties
+        let mut rebuild_graph = false;
+
+        loop {
+            let Some(embedding_service) = self.embedding.current() else {
+                self.idle();
+                continue;
+            };
+            let jobs = match self.claim(embedding_service.parallelism()) {
+                Ok(jobs) => jobs,
+                Err(e) => {
+                    tracing::error!("Failed to read index queue: {e}");
+                    std::thread::sleep(std::time::Duration::from_secs(5));
+                    continue;
+                }
+            };
+
+            if jobs.is_empty() {
+                // Paused with bulk jobs left: keep the progress count for later
+                if !matches!(self.depth(), Ok((0, _))) {
+                    self.idle();
+                    continue;
+                }
+                if rebuild_graph {
+                    if let Err(e) = graph_service.rebuild_full_cache() {
+                        tracing::error!("Failed to rebuild graph cache: {e}");
+                    }
+                    rebuild_graph = false;
+                }
+                let processed = self.processed.load(Ordering::SeqCst);
+                if processed > 0 {
+                    tracing::info!("Index queue empty after embedding {processed} note(s)");
+                    match self.complete(&[], 0) {
+                        Ok(()) => self.processed.store(0, Ordering::SeqCst),
+                        Err(e) => tracing::error!("Failed to update index queue: {e}"),
+                    }
+                    self.notify(None);
+                }
+                self.idle();
+                continue;
+            }
+
+            let bulk = jobs[0].priority == JobPriority::Bulk as i64;
+            let ids: Vec<String> = jobs.iter().map(|job| job.note_id.clone()).collect();
+            let by_id: HashMap<&str, &Job> =
+                jobs.iter().map(|job| (job.note_id.as_str(), job)).collect();
+            let before = self.processed.load(Ordering::SeqCst);
+            if let Ok(mut since) = self.rate_since.lock() {
+                since.get_or_insert((Instant::now(), before));
+            }
+            // Jobs are finished batch by batch along with the progress count,
+            // so the depth stays accurate and a restart doesn't redo them
+            let progress = |done: u32, batch: &[String], title: &str| {
+                let finished: Vec<&Job> = batch
+                    .iter()
+                    .filter_map(|id| by_id.get(id.as_str()).copied())
+                    .collect();
+                match self.complete(&finished, before + done) {
+                    // Batches can finish out of order across sessions
+                    Ok(()) => {
+                        self.processed.fetch_max(before + done, Ordering::SeqCst);
+                    }
+                    Err(e) => tracing::error!("Failed to update index queue: {e}"),
+                }
+                self.notify(Some(title));
+                !(bulk
+                    && (self.paused.load(Ordering::SeqCst)
+                        || self.cancelled.load(Ordering::SeqCst)))
+            };
+            let result = embedding_service.index_bulk(&ids, jobs[0].force, progress);
+            match result {
+                Ok(_) if !bulk => {
+                    for id in &ids {
+                        if let Err(e) = graph_service.rebuild_cache_for_note(id) {
+                            tracing::error!("Failed to rebuild graph cache for {id}: {e}");
+                        }
+                    }
+                }
+                Ok(_) => rebuild_graph = true,
+                Err(e) => {
+                    tracing::error!("Failed to index {} note(s): {e}", jobs.len());
+                    if let Err(e) = self.fail(&jobs) {
+                        tracing::error!("Failed to update index queue: {e}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// The next jobs to run: all of one priority and all forced or not, enough
+    /// for `parallelism` batches. Jobs for notes too short to embed are dropped
+    /// on the way.
+    fn claim(&self, parallelism: usize) -> Result<Vec<Job>, SunderError> {
+        // A cancel only stops the batch running when it was made
+        self.cancelled.store(false, Ordering::SeqCst);
+        {
+            let conn = self.db.get_write_conn()?;
+            conn.execute(
+                "DELETE FROM index_jobs
+                 WHERE note_id IN (SELECT id FROM notes WHERE word_count < 3)",
+                [],
+            )?;
+        }
+
+        let limit = NOTES_PER_BATCH * parallelism;
+        let conn = self.db.get_read_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT j.note_id, j.priority, j.version, j.force
+             FROM index_jobs j,
+                  (SELECT priority, force FROM index_jobs
+                   WHERE ?2 = 0 OR priority != ?3
+                   ORDER BY priority DESC, force DESC LIMIT 1) top
+             WHERE j.priority = top.priority AND j.force = top.force
+             ORDER BY j.enqueued_at
+             LIMIT ?1",
+        )?;
+        let paused = self.paused.load(Ordering::SeqCst);
+        let params = rusqlite::params![limit as i64, paused, JobPriority::Bulk as i64];
+        let jobs = stmt
+            .query_map(params, |row| {
+                Ok(Job {
+                    note_id: row.get(0)?,
+                    priority: row.get(1)?,
+                    version: row.get(2)?,
+                    force: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(jobs)
+    }
+
+    /// Remove finished jobs, unless they were enqueued again while running, and
+    /// record the progress count with them.
+    fn complete(&self, jobs: &[&Job], processed: u32) -> Result<(), SunderError> {
+        let mut conn = self.db.get_write_conn()?;
+        let tx = conn.transaction()?;
+        for job in jobs {
+            tx.execute(
+                "DELETE FROM index_jobs WHERE note_id = ?1 AND version = ?2",
+                rusqlite::params![job.note_id, job.version],
+            )?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES ('index_processed', ?1)",
+            [processed.to_string()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Count a failed attempt for jobs still waiting, dropping those that have
+    /// used up their tries.
+    fn fail(&self, jobs: &[Job]) -> Result<(), SunderError> {
+        let mut conn = self.db.get_write_conn()?;
+        let tx = conn.transaction()?;
+        for job in jobs {
+            tx.execute(
+                "UPDATE index_jobs SET attempts = attempts + 1 WHERE note_id = ?1",
+                [&job.note_id],
+            )?;
+        }
+        let dropped = tx.execute(
+            "DELETE FROM index_jobs WHERE attempts >= ?1",
+            [MAX_ATTEMPTS],
+        )?;
+        tx.commit()?;
+        if dropped > 0 {
+            tracing::warn!("Gave up indexing {dropped} note(s) after {MAX_ATTEMPTS} attempts");
+        }
+        Ok(())
+    }
+
+    /// Block until there's work, restarting the rate measurement after.
+    fn idle(&self) {
+        if let Ok(mut since) = self.rate_since.lock() {
+            *since = None;
+        }
+        self.wait();
+    }
+
+    fn wake(&self) {
+        let (woken, condvar) = &self.wake;
+        if let Ok(mut woken) = woken.lock() {
+            *woken = true;
+            condvar.notify_one();
+        }
+    }
+
+    /// Block until something is enqueued or resumed, or a model is loaded.
+    fn wait(&self) {
+        let (woken, condvar) = &self.wake;
+        let Ok(mut woken) = woken.lock() else {
+            return;
+        };
+        while !*woken {
+            woken = match condvar.wait(woken) {
+                Ok(guard) => guard,
+                Err(_) => return,
+            };
+        }
+        *woken = false;
+    }
+
+    fn notify(&self, current_note_title: Option<&str>) {
+        let Some(listener) = self.listener.get() else {
+            return;
+        };
+        match self.snapshot(current_note_title) {
+            Ok(status) => listener(&status),
+            Err(e) => tracing::warn!("Failed to read index queue depth: {e}"),
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use crate::db::DatabaseManager;
 use crate::error::SunderError;
-use crate::services::embedding::{embedding_to_blob, EmbeddingService};
+use crate::services::embedding::{embedding_to_blob, EmbeddingSlot};
+use crate::services::passage::{self, Passage, ScoreAggregation};
+use crate::services::plaintext::{self, PlainTextOptions};
 use lru::LruCache;
 use serde::Serialize;
 use sha2::{Digest, Sha256};
@@ -13,19 +15,21 @@ pub struct LatentLink {
     pub title: String,
     pub similarity: f64,
     pub snippet: String,
+    /// The passage of the linked note closest to the content.
+    pub passage: Passage,
 }
 
 pub struct LinkService {
     db: Arc<DatabaseManager>,
-    embedding_service: Arc<EmbeddingService>,
+    embedding: Arc<EmbeddingSlot>,
     cache: Mutex<LruCache<String, Vec<LatentLink>>>,
 }
 
 impl LinkService {
-    pub fn new(db: Arc<DatabaseManager>, embedding_service: Arc<EmbeddingService>) -> Self {
+    pub fn new(db: Arc<DatabaseManager>, embedding: Arc<EmbeddingSlot>) -> Self {
         Self {
             db,
-            embedding_service,
+            embedding,
             cache: Mutex::new(LruCache::new(NonZeroUsize::new(64).unwrap())),
         }
     }
@@ -36,13 +40,21 @@ impl LinkService {
         exclude_note_id: Option<&str>,
         threshold: f64,
         limit: u32,
+        aggregation: ScoreAggregation,
     ) -> Result<Vec<LatentLink>, SunderError> {
         if content.split_whitespace().count() < 3 {
             return Ok(Vec::new());
         }
 
-        // Check cache
-        let cache_key = content_hash(content);
+        let embedding_service = self.embedding.get()?;
+
+        // Check cache; entries from a model since replaced don't match
+        let cache_key = format!(
+            "{}:{:?}:{}",
+            embedding_service.model_version(),
+            aggregation,
+            content_hash(content)
+        );
         if let Ok(mut cache) = self.cache.lock() {
             if let Some(cached) = cache.get(&cache_key) {
                 let mut results = cached.clone();
@@ -55,33 +67,31 @@ impl LinkService {
             }
         }
 
-        let embedding = self.embedding_service.embed_text(content)?;
+        let embedding = embedding_service.embed_text(content)?;
         let blob = embedding_to_blob(&embedding);
 
         let conn = self.db.get_read_conn()?;
-        let mut stmt = conn.prepare(
-            "SELECT v.note_id, v.distance, n.title, n.content
-             FROM vec_embeddings v
-             JOIN notes n ON n.id = v.note_id
-             WHERE v.embedding MATCH ?1
-             ORDER BY v.distance
-             LIMIT ?2",
-        )?;
-
+        let options = PlainTextOptions::load(&conn)?;
         // Fetch more than needed so we can filter
         let fetch_limit = (limit * 3).max(20);
-        let links: Vec<LatentLink> = stmt
-            .query_map(rusqlite::params![blob, fetch_limit], |row| {
-                let content: String = row.get(3)?;
-                let distance: f64 = row.get(1)?;
-                Ok(LatentLink {
-                    note_id: row.get(0)?,
-                    title: row.get(2)?,
-                    similarity: 1.0 - distance,
-                    snippet: make_snippet(&content),
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
+        let links: Vec<LatentLink> = passage::match_notes(
+            &conn,
+            &blob,
+            embedding_service.model_version(),
+            fetch_limit,
+            None,
+            None,
+            aggregation,
+        )?
+        .into_iter()
+        .map(|m| LatentLink {
+            snippet: plaintext::snippet(&m.content, &options),
+            note_id: m.id,
+            title: m.title,
+            similarity: m.score,
+            passage: m.passage,
+        })
+        .collect();
 
         // Cache before filtering
         if let Ok(mut cache) = self.cache.lock() {
@@ -104,26 +114,3 @@ fn content_hash(content: &str) -> String {
     hasher.update(content.as_bytes());
     hex::encode(hasher.finalize())
 }
-
-fn make_snippet(content: &str) -> String {
-    let stripped: String = content
-        .chars()
-        .take(250)
-        .collect::<String>()
-        .lines()
-        .map(|line| {
-            line.trim()
-                .trim_start_matches('#')
-                .trim()
-                .replace("**", "")
-                .replace('*', "")
-        })
-        .collect::<Vec<_>>()
-        .join(" ");
-
-    if stripped.len() > 200 {
-        format!("{}...", &stripped[..200])
-    } else {
-        stripped
-    }
-}
@@ -0,0 +1,192 @@
+use crate::error::SunderError;
+use crate::services::markdown;
+use crate::services::note::{row_to_note, Note, NOTE_COLUMNS};
+use crate::services::vault;
+use crate::services::wikilink;
+use rusqlite::Connection;
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A note being renamed and/or moved.
+pub struct MoveTarget<'a> {
+    /// The note as it is before the move.
+    pub note: &'a Note,
+    pub new_title: &'a str,
+    pub new_path: Option<&'a Path>,
+    pub vault_dir: Option<&'a Path>,
+}
+
+/// Notes whose content has to change for the move, paired with the new content:
+/// every note with a wikilink or relative markdown link to the moved note, and
+/// the moved note itself when its own relative links would break.
+pub fn plan_rewrites(
+    conn: &Connection,
+    target: &MoveTarget<'_>,
+) -> Result<Vec<(Note, String)>, SunderError> {
+    let note = target.note;
+    let mut source_ids = BTreeSet::new();
+    source_ids.insert(note.id.clone());
+
+    let mut stmt = conn.prepare("SELECT DISTINCT source_id FROM note_links WHERE target_id = ?1")?;
+    for id in stmt.query_map([&note.id], |row| row.get::<_, String>(0))? {
+        source_ids.insert(id?);
+    }
+
+    // Relative markdown links mention the file name, raw or percent-encoded
+    if let Some(file_name) = note
+        .file_path
+        .as_deref()
+        .and_then(|p| Path::new(p).file_name())
+        .map(|n| n.to_string_lossy().to_string())
+    {
+        let mut stmt = conn.prepare(
+            "SELECT id FROM notes
+             WHERE file_path IS NOT NULL AND (instr(content, ?1) > 0 OR instr(content, ?2) > 0)",
+        )?;
+        let encoded = encode_destination(&file_name);
+        for id in stmt.query_map([&file_name, &encoded], |row| row.get::<_, String>(0))? {
+            source_ids.insert(id?);
+        }
+    }
+
+    let mut rewrites = Vec::new();
+    for id in source_ids {
+        let source = conn.query_row(
+            &format!("SELECT {NOTE_COLUMNS} FROM notes WHERE id = ?1"),
+            [&id],
+            row_to_note,
+        )?;
+        let content = rewrite_content(conn, &source, target)?;
+        if content != source.content {
+            rewrites.push((source, content));
+        }
+    }
+    Ok(rewrites)
+}
+
+fn rewrite_content(
+    conn: &Connection,
+    source: &Note,
+    target: &MoveTarget<'_>,
+) -> Result<String, SunderError> {
+    let content = &source.content;
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+
+    for link in wikilink::parse_wikilinks(content) {
+        if wikilink::resolve_target(conn, &link.target)?.as_deref() != Some(target.note.id.as_str()) {
+            continue;
+        }
+        let Some(new_text) = wikilink_target(&link.target, target) else {
+            continue;
+        };
+        let raw = &content[link.start..link.end];
+        if new_text != link.target {
+            if let Some(offset) = raw.find(link.target.as_str()) {
+                let start = link.start + offset;
+                edits.push((start..start + link.target.len(), new_text));
+            }
+        }
+    }
+
+    if let Some(source_path) = source.file_path.as_deref().map(Path::new) {
+        let is_moved_note = source.id == target.note.id;
+        let old_dir = source_path.parent().unwrap_or(Path::new(""));
+        let new_dir = match target.new_path {
+            Some(new_path) if is_moved_note => new_path.parent().unwrap_or(Path::new("")),
+            _ => old_dir,
+        };
+        let old_target = target.note.file_path.as_deref().map(|p| vault::normalize_path(Path::new(p)));
+
+        for link in markdown::inline_links(content) {
+            if !markdown::is_relative_destination(&link.destination) {
+                continue;
+            }
+            let (path_part, fragment) = match link.destination.split_once('#') {
+                Some((path, fragment)) => (path, Some(fragment)),
+                None => (link.destination.as_str(), None),
+            };
+            let decoded = percent_encoding::percent_decode_str(path_part).decode_utf8_lossy();
+            let resolved = vault::normalize_path(&old_dir.join(decoded.as_ref()));
+
+            let points_at_moved = old_target.as_ref() == Some(&resolved);
+            let new_resolved: PathBuf = match target.new_path {
+                Some(new_path) if points_at_moved => new_path.to_path_buf(),
+                // The moved note's links to other files break when its folder changes
+                _ if is_moved_note && new_dir != old_dir && resolved.exists() => resolved,
+                _ => continue,
+            };
+
+            let relative = vault::relative_link_path(new_dir, &new_resolved);
+            let mut destination = if link.angle_brackets {
+                relative
+            } else {
+                encode_destination(&relative)
+            };
+            if let Some(fragment) = fragment {
+                destination.push('#');
+                destination.push_str(fragment);
+            }
+            if destination != link.destination {
+                edits.push((link.range, destination));
+            }
+        }
+    }
+
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    let mut content = content.clone();
+    for (range, replacement) in edits {
+        content.replace_range(range, &replacement);
+    }
+    Ok(content)
+}
+
+/// New target text for a wikilink that resolved to the moved note, or `None`
+/// when the link still resolves as written (it uses an alias).
+fn wikilink_target(old: &str, target: &MoveTarget<'_>) -> Option<String> {
+    let note = target.note;
+    if old.to_lowercase() == note.title.to_lowercase() {
+        return Some(target.new_title.to_string());
+    }
+    let aliases = wikilink::front_matter_aliases(note.metadata.as_ref());
+    if aliases.iter().any(|a| a.to_lowercase() == old.to_lowercase()) {
+        return None;
+    }
+
+    // The link was written as a path: keep the same style
+    let Some(new_path) = target.new_path else {
+        return Some(target.new_title.to_string());
+    };
+    let with_extension = old.to_lowercase().ends_with(".md");
+    let in_vault = target
+        .vault_dir
+        .and_then(|dir| new_path.strip_prefix(dir).ok())
+        .filter(|_| old.contains('/'));
+    let path = match in_vault {
+        Some(relative) => relative.to_path_buf(),
+        None => PathBuf::from(new_path.file_name()?),
+    };
+    let path = if with_extension { path } else { path.with_extension("") };
+    Some(
+        path.components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+/// Escape the characters that would end or break a bare link destination.
+fn encode_destination(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            ' ' => encoded.push_str("%20"),
+            '(' => encoded.push_str("%28"),
+            ')' => encoded.push_str("%29"),
+            '<' => encoded.push_str("%3C"),
+            '>' => encoded.push_str("%3E"),
+            c => encoded.push(c),
+        }
+    }
+    encoded
+}
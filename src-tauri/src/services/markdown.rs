@@ -0,0 +1,210 @@
+use std::ops::Range;
+
+/// Byte ranges of code in a markdown document: fenced code blocks (``` or ~~~,
+/// including the fence lines) and inline code spans. Text inside these ranges
+/// is never treated as tags or links.
+pub fn code_ranges(content: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut fence: Option<(usize, &str)> = None;
+    let mut line_start = 0;
+
+    for line in content.split_inclusive('\n') {
+        let offset = line_start;
+        line_start += line.len();
+        let trimmed = line.trim_start();
+
+        if let Some((start, marker)) = fence {
+            if trimmed.starts_with(marker) {
+                ranges.push(start..line_start);
+                fence = None;
+            }
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+            fence = Some((offset, marker));
+            continue;
+        }
+
+        ranges.extend(
+            inline_code_spans(line)
+                .into_iter()
+                .map(|r| offset + r.start..offset + r.end),
+        );
+    }
+
+    // An unclosed fence runs to the end of the document
+    if let Some((start, _)) = fence {
+        ranges.push(start..content.len());
+    }
+    ranges
+}
+
+/// Inline code spans within a single line. A span opened by N backticks is
+/// closed by the next run of exactly N backticks; unmatched runs are literal.
+fn inline_code_spans(line: &str) -> Vec<Range<usize>> {
+    let bytes = line.as_bytes();
+    let run_at = |i: usize| bytes[i..].iter().take_while(|&&b| b == b'`').count();
+
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'`' {
+            i += 1;
+            continue;
+        }
+        let open = run_at(i);
+        let mut j = i + open;
+        let mut close = None;
+        while j < bytes.len() {
+            if bytes[j] == b'`' {
+                let run = run_at(j);
+                if run == open {
+                    close = Some(j + run);
+                    break;
+                }
+                j += run;
+            } else {
+                j += 1;
+            }
+        }
+        match close {
+            Some(end) => {
+                spans.push(i..end);
+                i = end;
+            }
+            None => i += open,
+        }
+    }
+    spans
+}
+
+/// Whether `pos` falls inside any of the (sorted, non-overlapping) `ranges`.
+pub fn in_ranges(ranges: &[Range<usize>], pos: usize) -> bool {
+    let idx = ranges.partition_point(|r| r.end <= pos);
+    ranges.get(idx).is_some_and(|r| r.contains(&pos))
+}
+
+/// The trimmed line around byte offset `pos`, shortened to roughly `max_chars`
+/// characters centred on `pos`. Used for link and mention context snippets.
+pub fn line_context(content: &str, pos: usize, max_chars: usize) -> String {
+    let pos = pos.min(content.len());
+    let start = content[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let end = content[pos..].find('\n').map_or(content.len(), |i| pos + i);
+    let line = &content[start..end];
+    let pos_in_line = pos - start;
+
+    if line.chars().count() <= max_chars {
+        return line.trim().to_string();
+    }
+
+    let before: Vec<(usize, char)> = line[..pos_in_line].char_indices().collect();
+    let from = before
+        .len()
+        .checked_sub(max_chars / 2)
+        .map_or(0, |i| before[i].0);
+    let to = line[from..]
+        .char_indices()
+        .nth(max_chars)
+        .map_or(line.len(), |(i, _)| from + i);
+
+    let mut context = line[from..to].trim().to_string();
+    if from > 0 {
+        context.insert_str(0, "...");
+    }
+    if to < line.len() {
+        context.push_str("...");
+    }
+    context
+}
+
+/// 1-based line number of byte offset `pos`.
+pub fn line_number(content: &str, pos: usize) -> u32 {
+    content[..pos.min(content.len())].matches('\n').count() as u32 + 1
+}
+
+/// An inline link or image, `[text](destination "title")`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlineLink {
+    /// Destination as written, without surrounding `<>`.
+    pub destination: String,
+    /// Byte range of `destination` in the content.
+    pub range: Range<usize>,
+    /// Whether the destination was written as `<destination>`.
+    pub angle_brackets: bool,
+}
+
+/// Inline markdown links and images outside code. Reference-style links are
+/// not included.
+pub fn inline_links(content: &str) -> Vec<InlineLink> {
+    let code = code_ranges(content);
+    let mut links = Vec::new();
+    let mut search_from = 0;
+
+    while let Some(found) = content[search_from..].find("](") {
+        let bracket = search_from + found;
+        search_from = bracket + 2;
+        let line_start = content[..bracket].rfind('\n').map_or(0, |i| i + 1);
+        if in_ranges(&code, bracket) || !content[line_start..bracket].contains('[') {
+            continue;
+        }
+
+        let after = bracket + 2;
+        let rest = &content[after..];
+        let leading = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+        let start = after + leading;
+
+        let (range, angle_brackets) = if content[start..].starts_with('<') {
+            let Some(close) = content[start + 1..].find(['>', '\n']) else {
+                continue;
+            };
+            if content.as_bytes()[start + 1 + close] != b'>' {
+                continue;
+            }
+            (start + 1..start + 1 + close, true)
+        } else {
+            // Bare destination: ends at whitespace or the `)` balancing the opening one
+            let mut depth = 0usize;
+            let mut end = content.len();
+            for (i, c) in content[start..].char_indices() {
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth == 0 => {
+                        end = start + i;
+                        break;
+                    }
+                    ')' => depth -= 1,
+                    c if c.is_whitespace() => {
+                        end = start + i;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            (start..end, false)
+        };
+
+        if range.is_empty() {
+            continue;
+        }
+        search_from = range.end;
+        links.push(InlineLink {
+            destination: content[range.clone()].to_string(),
+            range,
+            angle_brackets,
+        });
+    }
+
+    links
+}
+
+/// Whether a link destination is a relative path to a local file, as opposed
+/// to a URL, an absolute path or an in-page `#anchor`.
+pub fn is_relative_destination(destination: &str) -> bool {
+    let path = destination.split('#').next().unwrap_or("");
+    if path.is_empty() || path.starts_with(['/', '\\']) {
+        return false;
+    }
+    // A `scheme:` before the first slash means a URL (http:, mailto:, obsidian:, ...)
+    let first_segment = path.split(['/', '\\']).next().unwrap_or("");
+    !first_segment.contains(':')
+}
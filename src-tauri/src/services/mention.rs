@@ -0,0 +1,259 @@
+use crate::db::DatabaseManager;
+use crate::error::SunderError;
+use crate::services::markdown;
+use crate::services::note::{Note, NoteService};
+use crate::services::wikilink;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+const CONTEXT_CHARS: usize = 160;
+/// Candidate notes fetched from the FTS index per title or alias.
+const MAX_CANDIDATES: u32 = 200;
+
+/// A place where another note's text contains this note's title or an alias
+/// without linking to it.
+#[derive(Debug, Serialize)]
+pub struct UnlinkedMention {
+    pub source_id: String,
+    pub source_title: String,
+    /// The text as it appears in the source note.
+    pub matched_text: String,
+    /// Byte range of the mention in the source note's content. Pass back to
+    /// `link_mention` to turn it into a wikilink.
+    pub start: usize,
+    pub end: usize,
+    /// 1-based line of the mention in the source note.
+    pub line: u32,
+    pub context: String,
+}
+
+pub struct MentionService {
+    db: Arc<DatabaseManager>,
+}
+
+impl MentionService {
+    pub fn new(db: Arc<DatabaseManager>) -> Self {
+        Self { db }
+    }
+
+    /// Unlinked mentions of a note's title and aliases in other notes. The FTS
+    /// index narrows down candidates; each is then scanned for whole-word,
+    /// case-insensitive occurrences outside code and existing wikilinks.
+    pub fn get_unlinked_mentions(&self, note_id: &str) -> Result<Vec<UnlinkedMention>, SunderError> {
+        let note = NoteService::new(Arc::clone(&self.db)).get_note(note_id)?;
+        let names = note_names(&note);
+
+        let conn = self.db.get_read_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT n.id, n.title, n.content
+             FROM notes_fts
+             JOIN notes n ON n.rowid = notes_fts.rowid
+             WHERE notes_fts MATCH ?1 AND n.id != ?2
+             ORDER BY n.updated_at DESC
+             LIMIT ?3",
+        )?;
+
+        let mut candidates: Vec<(String, String, String)> = Vec::new();
+        let mut seen = HashSet::new();
+        for name in &names {
+            let Some(query) = fts_phrase_query(name) else {
+                continue;
+            };
+            let rows = stmt
+                .query_map(rusqlite::params![query, note_id, MAX_CANDIDATES], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?
+                .collect::<Result<Vec<(String, String, String)>, _>>()?;
+            for row in rows {
+                if seen.insert(row.0.clone()) {
+                    candidates.push(row);
+                }
+            }
+        }
+
+        let mut mentions = Vec::new();
+        for (source_id, source_title, content) in candidates {
+            for range in find_unlinked(&content, &names) {
+                mentions.push(UnlinkedMention {
+                    source_id: source_id.clone(),
+                    source_title: source_title.clone(),
+                    matched_text: content[range.clone()].to_string(),
+                    line: markdown::line_number(&content, range.start),
+                    context: markdown::line_context(&content, range.start, CONTEXT_CHARS),
+                    start: range.start,
+                    end: range.end,
+                });
+            }
+        }
+
+        Ok(mentions)
+    }
+
+    /// Turn an unlinked mention in `source_id` into a wikilink to `target_id`.
+    /// The mention must still be at `start..end`; otherwise the note changed
+    /// since the mentions were listed and nothing is written. The link uses
+    /// the target's title, or an alias or file name when the title can't go
+    /// in a wikilink (e.g. "C# tips"); if none can, linking fails.
+    pub fn link_mention(
+        &self,
+        source_id: &str,
+        target_id: &str,
+        start: usize,
+        end: usize,
+    ) -> Result<Note, SunderError> {
+        if source_id == target_id {
+            return Err(SunderError::ValidationError(
+                "A note can't link a mention of itself".to_string(),
+            ));
+        }
+        let note_service = NoteService::new(Arc::clone(&self.db));
+        let source = note_service.get_note(source_id)?;
+        let target = note_service.get_note(target_id)?;
+
+        let stale = || {
+            SunderError::ValidationError(
+                "Mention no longer matches the note content; refresh and try again".to_string(),
+            )
+        };
+        let matched = source.content.get(start..end).ok_or_else(stale)?;
+        let names = note_names(&target);
+        if !find_unlinked(&source.content, &names).contains(&(start..end)) {
+            return Err(stale());
+        }
+
+        let name = {
+            let conn = self.db.get_read_conn()?;
+            link_name(&conn, &target)?
+        }
+        .ok_or_else(|| {
+            SunderError::ValidationError(format!(
+                "\"{}\" has no title, alias or file name a wikilink can use",
+                target.title
+            ))
+        })?;
+        let link = if matched == name {
+            format!("[[{matched}]]")
+        } else if matched.contains(['[', ']', '\n']) {
+            return Err(SunderError::ValidationError(format!(
+                "\"{matched}\" can't be shown as a wikilink's text"
+            )));
+        } else {
+            format!("[[{name}|{matched}]]")
+        };
+        let mut content = source.content.clone();
+        content.replace_range(start..end, &link);
+
+        note_service.update_note(source_id, None, Some(content))
+    }
+}
+
+/// A name for `note` that reads back from a wikilink as a link to it: its
+/// title, else an alias, else its file name. Names containing what ends or
+/// splits a wikilink's target (`|`, `#`, brackets) are skipped, as are names
+/// that resolve to another note.
+fn link_name(conn: &Connection, note: &Note) -> Result<Option<String>, SunderError> {
+    let file_stem = note
+        .file_path
+        .as_deref()
+        .and_then(|path| Path::new(path).file_stem())
+        .map(|stem| stem.to_string_lossy().to_string());
+    let candidates = std::iter::once(note.title.clone())
+        .chain(wikilink::front_matter_aliases(note.metadata.as_ref()))
+        .chain(file_stem);
+    for name in candidates {
+        if name.trim() != name || name.contains(['|', '#', '[', ']', '\n']) {
+            continue;
+        }
+        if wikilink::resolve_target(conn, &name)?.as_deref() == Some(note.id.as_str()) {
+            return Ok(Some(name));
+        }
+    }
+    Ok(None)
+}
+
+/// A note's title followed by its aliases, longest first so a longer name wins
+/// over a shorter one it contains.
+fn note_names(note: &Note) -> Vec<String> {
+    let mut names = vec![note.title.clone()];
+    names.extend(wikilink::front_matter_aliases(note.metadata.as_ref()));
+    names.sort_by_key(|n| std::cmp::Reverse(n.chars().count()));
+    names.dedup_by(|a, b| a.to_lowercase() == b.to_lowercase());
+    names
+}
+
+/// FTS5 phrase query for `name` against the content column. `None` when the
+/// name has no indexable words.
+fn fts_phrase_query(name: &str) -> Option<String> {
+    if !name.chars().any(char::is_alphanumeric) {
+        return None;
+    }
+    Some(format!("content : \"{}\"", name.replace('"', "\"\"")))
+}
+
+/// Byte ranges of whole-word, case-insensitive occurrences of any of `names`
+/// that are not inside code or an existing wikilink. Overlapping matches keep
+/// the earliest (and, since names are longest first, the longest).
+fn find_unlinked(content: &str, names: &[String]) -> Vec<Range<usize>> {
+    let mut skip = markdown::code_ranges(content);
+    skip.extend(wikilink::parse_wikilinks(content).into_iter().map(|l| l.start..l.end));
+    skip.sort_by_key(|r| r.start);
+
+    let mut found: Vec<Range<usize>> = Vec::new();
+    for name in names {
+        for range in find_phrase(content, name) {
+            let overlaps_skip = skip.iter().any(|s| s.start < range.end && range.start < s.end);
+            let overlaps_found = found.iter().any(|f| f.start < range.end && range.start < f.end);
+            if !overlaps_skip && !overlaps_found {
+                found.push(range);
+            }
+        }
+    }
+    found.sort_by_key(|r| r.start);
+    found
+}
+
+/// Case-insensitive whole-word occurrences of `phrase` in `content`.
+fn find_phrase(content: &str, phrase: &str) -> Vec<Range<usize>> {
+    let phrase: Vec<char> = phrase.chars().flat_map(char::to_lowercase).collect();
+    if phrase.is_empty() {
+        return Vec::new();
+    }
+
+    let mut ranges = Vec::new();
+    let mut prev: Option<char> = None;
+    for (i, c) in content.char_indices() {
+        let at_word_start = prev.is_none_or(|p| !p.is_alphanumeric());
+        prev = Some(c);
+        if !at_word_start {
+            continue;
+        }
+        if let Some(len) = lowercase_prefix_len(&content[i..], &phrase) {
+            let end = i + len;
+            if content[end..].chars().next().is_none_or(|n| !n.is_alphanumeric()) {
+                ranges.push(i..end);
+            }
+        }
+    }
+    ranges
+}
+
+/// Byte length of the prefix of `text` that lowercases to `phrase`, if any.
+fn lowercase_prefix_len(text: &str, phrase: &[char]) -> Option<usize> {
+    let mut matched = 0;
+    for (i, c) in text.char_indices() {
+        if matched == phrase.len() {
+            return Some(i);
+        }
+        for lower in c.to_lowercase() {
+            if phrase.get(matched) != Some(&lower) {
+                return None;
+            }
+            matched += 1;
+        }
+    }
+    (matched == phrase.len()).then_some(text.len())
+}
@@ -1,8 +1,24 @@
+pub mod chunking;
+pub mod folders;
+pub mod front_matter;
+pub mod index_queue;
+pub mod link_rewrite;
+pub mod markdown;
+pub mod mention;
 pub mod note;
+pub mod passage;
+pub mod plaintext;
+pub mod query;
+pub mod revision;
 pub mod settings;
+pub mod tags;
+pub mod trash;
+pub mod vault;
+pub mod wikilink;
 
 // Stubs for future tasks
 pub mod embedding;
+pub mod embedding_provider;
 pub mod file_watcher;
 pub mod graph;
 pub mod link;
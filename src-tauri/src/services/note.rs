@@ -1,6 +1,17 @@
 use crate::db::DatabaseManager;
 use crate::error::SunderError;
+use crate::services::folders;
+use crate::services::link_rewrite::{self, MoveTarget};
+use crate::services::plaintext::{self, PlainTextOptions};
+use crate::services::revision::{self, RevisionSource};
+use crate::services::settings::setting;
+use crate::services::tags;
+use crate::services::trash::TrashService;
+use crate::services::vault;
+use crate::services::wikilink;
+use rusqlite::Connection;
 use serde::Serialize;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[derive(Debug, Serialize, Clone)]
@@ -12,6 +23,11 @@ pub struct Note {
     pub word_count: u32,
     pub created_at: String,
     pub updated_at: String,
+    /// YAML front matter of the linked file as a JSON object.
+    pub metadata: Option<serde_json::Value>,
+    /// Hash of the linked file as last written or imported, used to skip our own writes.
+    #[serde(skip)]
+    pub file_hash: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -37,31 +53,77 @@ impl NoteService {
         Self { db }
     }
 
+    /// Create a note. When a vault is active and no file path is given, the note
+    /// also gets a markdown file in the vault root.
     pub fn create_note(
         &self,
         title: String,
         content: String,
         file_path: Option<String>,
     ) -> Result<Note, SunderError> {
-        // Validate title
-        let title = title.trim().to_string();
-        if title.is_empty() {
-            return Err(SunderError::ValidationError(
-                "Title cannot be empty".to_string(),
-            ));
-        }
-        if title.len() > 500 {
-            return Err(SunderError::ValidationError(
-                "Title must be 500 characters or fewer".to_string(),
-            ));
+        if file_path.is_some() {
+            return self.insert_note(title, content, file_path, None, None, RevisionSource::Create);
         }
 
-        // Validate content size (max 2MB)
-        if content.len() > 2 * 1024 * 1024 {
-            return Err(SunderError::ContentTooLarge(
-                "Content exceeds 2MB limit".to_string(),
-            ));
-        }
+        let Some(vault_dir) = vault::active_vault_dir(&self.db)? else {
+            return self.insert_note(title, content, None, None, None, RevisionSource::Create);
+        };
+
+        let title = validate_title(title)?;
+        validate_content(&content)?;
+
+        let path = vault::unique_note_path(&vault_dir, &title, |candidate| {
+            Ok(self
+                .get_note_by_file_path(&candidate.to_string_lossy())?
+                .is_some())
+        })?;
+        let now = chrono::Utc::now().to_rfc3339();
+        let written = vault::write_new_note_file(&path, &title, &now, &content)?;
+
+        let path_str = path.to_string_lossy().to_string();
+        self.insert_note(
+            title,
+            content,
+            Some(path_str),
+            Some(written.hash),
+            written.metadata,
+            RevisionSource::Create,
+        )
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&path);
+        })
+    }
+
+    /// Create a note for a markdown file just read from the watched directory.
+    pub fn create_from_file(
+        &self,
+        title: String,
+        content: String,
+        file_path: String,
+        file_hash: String,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<Note, SunderError> {
+        self.insert_note(
+            title,
+            content,
+            Some(file_path),
+            Some(file_hash),
+            metadata,
+            RevisionSource::Import,
+        )
+    }
+
+    fn insert_note(
+        &self,
+        title: String,
+        content: String,
+        file_path: Option<String>,
+        file_hash: Option<String>,
+        metadata: Option<serde_json::Value>,
+        source: RevisionSource,
+    ) -> Result<Note, SunderError> {
+        let title = validate_title(title)?;
+        validate_content(&content)?;
 
         let id = uuid::Uuid::now_v7().to_string();
         let word_count = content.split_whitespace().count() as u32;
@@ -69,12 +131,23 @@ impl NoteService {
 
         let conn = self.db.get_write_conn()?;
         conn.execute(
-            "INSERT INTO notes (id, title, content, file_path, word_count, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            rusqlite::params![id, title, content, file_path, word_count, now, now],
+            "INSERT INTO notes (id, title, content, file_path, word_count, created_at, updated_at, file_hash, metadata)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![
+                id,
+                title,
+                content,
+                file_path,
+                word_count,
+                now,
+                now,
+                file_hash,
+                metadata_to_sql(&metadata),
+            ],
         )?;
+        revision::record_revision(&conn, &self.db, &id, &title, &content, source)?;
 
-        Ok(Note {
+        let note = Note {
             id,
             title,
             content,
@@ -82,26 +155,19 @@ impl NoteService {
             word_count,
             created_at: now.clone(),
             updated_at: now,
-        })
+            metadata,
+            file_hash,
+        };
+        sync_derived(&conn, &note)?;
+        Ok(note)
     }
 
     pub fn get_note(&self, id: &str) -> Result<Note, SunderError> {
         let conn = self.db.get_read_conn()?;
         conn.query_row(
-            "SELECT id, title, content, file_path, word_count, created_at, updated_at
-             FROM notes WHERE id = ?1",
+            &format!("SELECT {NOTE_COLUMNS} FROM notes WHERE id = ?1"),
             [id],
-            |row| {
-                Ok(Note {
-                    id: row.get(0)?,
-                    title: row.get(1)?,
-                    content: row.get(2)?,
-                    file_path: row.get(3)?,
-                    word_count: row.get(4)?,
-                    created_at: row.get(5)?,
-                    updated_at: row.get(6)?,
-                })
-            },
+            row_to_note,
         )
         .map_err(|e| match e {
             rusqlite::Error::QueryReturnedNoRows => {
@@ -113,111 +179,406 @@ impl NoteService {
 
     pub fn get_note_by_file_path(&self, path: &str) -> Result<Option<Note>, SunderError> {
         let conn = self.db.get_read_conn()?;
-        let mut stmt = conn.prepare(
-            "SELECT id, title, content, file_path, word_count, created_at, updated_at
-             FROM notes WHERE file_path = ?1",
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {NOTE_COLUMNS} FROM notes WHERE file_path = ?1"
+        ))?;
 
-        let result = stmt
-            .query_row([path], |row| {
-                Ok(Note {
-                    id: row.get(0)?,
-                    title: row.get(1)?,
-                    content: row.get(2)?,
-                    file_path: row.get(3)?,
-                    word_count: row.get(4)?,
-                    created_at: row.get(5)?,
-                    updated_at: row.get(6)?,
-                })
-            })
-            .optional()?;
+        let result = stmt.query_row([path], row_to_note).optional()?;
 
         Ok(result)
     }
 
+    /// Update a note. Notes linked to a markdown file are written back to disk
+    /// before the database row changes, so the file never lags behind the app.
     pub fn update_note(
         &self,
         id: &str,
         title: Option<String>,
         content: Option<String>,
     ) -> Result<Note, SunderError> {
-        // Verify note exists
-        let existing = self.get_note(id)?;
+        self.update_note_as(id, title, content, RevisionSource::Edit)
+    }
 
-        let new_title = match title {
-            Some(t) => {
-                let t = t.trim().to_string();
-                if t.is_empty() {
-                    return Err(SunderError::ValidationError(
-                        "Title cannot be empty".to_string(),
-                    ));
-                }
-                if t.len() > 500 {
-                    return Err(SunderError::ValidationError(
-                        "Title must be 500 characters or fewer".to_string(),
-                    ));
-                }
-                t
+    /// `update_note`, recording the given source on the revision it creates.
+    pub fn update_note_as(
+        &self,
+        id: &str,
+        title: Option<String>,
+        content: Option<String>,
+        source: RevisionSource,
+    ) -> Result<Note, SunderError> {
+        let existing = self.get_note(id)?;
+        let (new_title, new_content) = resolve_update(&existing, title, content)?;
+        let metadata = existing.metadata.clone();
+
+        let (metadata, file_hash) = match &existing.file_path {
+            Some(path) => {
+                let written = vault::write_note_file(
+                    Path::new(path),
+                    &new_title,
+                    &new_content,
+                    metadata.as_ref(),
+                )?;
+                (written.metadata, Some(written.hash))
             }
-            None => existing.title,
+            None => (retitle_metadata(metadata, &new_title), None),
         };
 
-        let new_content = match content {
-            Some(c) => {
-                if c.len() > 2 * 1024 * 1024 {
-                    return Err(SunderError::ContentTooLarge(
-                        "Content exceeds 2MB limit".to_string(),
-                    ));
-                }
-                c
+        self.write_update(existing, new_title, new_content, metadata, file_hash, source)
+    }
+
+    /// Update the content and front matter of several notes at once; `None`
+    /// keeps a note's metadata. Either all of them change or none do: the rows
+    /// are written in one transaction, and if anything fails the files already
+    /// written are put back.
+    pub fn update_notes_and_metadata(
+        &self,
+        updates: Vec<(Note, String, Option<serde_json::Value>)>,
+        source: RevisionSource,
+    ) -> Result<Vec<Note>, SunderError> {
+        // Files overwritten so far, with their previous contents, for rollback
+        let mut originals: Vec<(PathBuf, Option<String>)> = Vec::new();
+        let result = (|| -> Result<Vec<Note>, SunderError> {
+            let now = chrono::Utc::now().to_rfc3339();
+            let mut notes = Vec::with_capacity(updates.len());
+            for (existing, content, metadata) in &updates {
+                validate_content(content)?;
+                let metadata = metadata.clone().or_else(|| existing.metadata.clone());
+                let (metadata, file_hash) = match &existing.file_path {
+                    Some(path) => {
+                        let path = PathBuf::from(path);
+                        originals.push((path.clone(), std::fs::read_to_string(&path).ok()));
+                        let written = vault::write_note_file(
+                            &path,
+                            &existing.title,
+                            content,
+                            metadata.as_ref(),
+                        )?;
+                        (written.metadata, Some(written.hash))
+                    }
+                    None => (retitle_metadata(metadata, &existing.title), None),
+                };
+                notes.push(Note {
+                    word_count: content.split_whitespace().count() as u32,
+                    content: content.clone(),
+                    updated_at: now.clone(),
+                    metadata,
+                    file_hash: file_hash.or_else(|| existing.file_hash.clone()),
+                    ..existing.clone()
+                });
             }
-            None => existing.content,
-        };
 
-        let word_count = new_content.split_whitespace().count() as u32;
-        let now = chrono::Utc::now().to_rfc3339();
+            let mut conn = self.db.get_write_conn()?;
+            let tx = conn.transaction()?;
+            for (note, (existing, _, _)) in notes.iter().zip(&updates) {
+                store_update(&tx, &self.db, note, &existing.content, source)?;
+            }
+            tx.commit()?;
+            Ok(notes)
+        })();
 
-        let conn = self.db.get_write_conn()?;
-        conn.execute(
-            "UPDATE notes SET title = ?1, content = ?2, word_count = ?3, updated_at = ?4
-             WHERE id = ?5",
-            rusqlite::params![new_title, new_content, word_count, now, id],
-        )?;
+        if result.is_err() {
+            restore_files(&originals);
+        }
+        result
+    }
 
-        Ok(Note {
-            id: id.to_string(),
-            title: new_title,
-            content: new_content,
+    /// Apply changes read from the note's markdown file. Unlike `update_note`
+    /// this never writes back to disk.
+    pub fn update_from_file(
+        &self,
+        id: &str,
+        title: String,
+        content: String,
+        file_hash: String,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<Note, SunderError> {
+        let existing = self.get_note(id)?;
+        let (new_title, new_content) = resolve_update(&existing, Some(title), Some(content))?;
+        self.write_update(
+            existing,
+            new_title,
+            new_content,
+            metadata,
+            Some(file_hash),
+            RevisionSource::Import,
+        )
+    }
+
+    fn write_update(
+        &self,
+        existing: Note,
+        title: String,
+        content: String,
+        metadata: Option<serde_json::Value>,
+        file_hash: Option<String>,
+        source: RevisionSource,
+    ) -> Result<Note, SunderError> {
+        let note = Note {
+            id: existing.id,
+            title,
+            word_count: content.split_whitespace().count() as u32,
+            content,
             file_path: existing.file_path,
-            word_count,
             created_at: existing.created_at,
-            updated_at: now,
-        })
+            updated_at: chrono::Utc::now().to_rfc3339(),
+            metadata,
+            file_hash: file_hash.or(existing.file_hash),
+        };
+
+        let mut conn = self.db.get_write_conn()?;
+        let tx = conn.transaction()?;
+        store_update(&tx, &self.db, &note, &existing.content, source)?;
+        tx.commit()?;
+        Ok(note)
     }
 
-    pub fn delete_note(&self, id: &str) -> Result<(), SunderError> {
-        let note = self.get_note(id)?;
+    /// Rename and/or move a note, keeping its id, revisions and embedding.
+    ///
+    /// `new_path` may be absolute or relative to the vault. Without it, a
+    /// file-backed note whose file name follows its title (as a slug or
+    /// verbatim) is renamed to match the new title. Wikilinks and relative
+    /// markdown links pointing at the note are rewritten in the linking notes,
+    /// and all database changes are committed in one transaction. If that
+    /// fails, the files touched on disk are put back. Returns the moved note
+    /// and the ids of the notes whose content was rewritten, which may include
+    /// the moved note itself.
+    pub fn move_note(
+        &self,
+        id: &str,
+        new_title: Option<String>,
+        new_path: Option<String>,
+    ) -> Result<(Note, Vec<String>), SunderError> {
+        let existing = self.get_note(id)?;
+        let title = match new_title {
+            Some(t) => validate_title(t)?,
+            None => existing.title.clone(),
+        };
+        let vault_dir = vault::active_vault_dir(&self.db)?;
+        let old_path = existing.file_path.as_deref().map(PathBuf::from);
+        let target_path = self.move_target_path(&existing, &title, new_path, vault_dir.as_deref())?;
+
+        let rewrites = {
+            let conn = self.db.get_write_conn()?;
+            link_rewrite::plan_rewrites(
+                &conn,
+                &MoveTarget {
+                    note: &existing,
+                    new_title: &title,
+                    new_path: target_path.as_deref(),
+                    vault_dir: vault_dir.as_deref(),
+                },
+            )?
+        };
+        let own_content = rewrites
+            .iter()
+            .find(|(note, _)| note.id == existing.id)
+            .map_or_else(|| existing.content.clone(), |(_, content)| content.clone());
+
+        // Files overwritten so far, with their previous contents, for rollback
+        let mut originals: Vec<(PathBuf, Option<String>)> = Vec::new();
+        let result = (|| -> Result<Note, SunderError> {
+            let (metadata, file_hash) = match &target_path {
+                Some(path) => {
+                    let original = match &old_path {
+                        Some(old) if old != path => {
+                            let original = std::fs::read_to_string(old).ok();
+                            vault::move_file(old, path)?;
+                            originals.push((old.clone(), original.clone()));
+                            original
+                        }
+                        _ => std::fs::read_to_string(path).ok(),
+                    };
+                    originals.push((path.clone(), original));
+                    let written = vault::write_note_file(
+                        path,
+                        &title,
+                        &own_content,
+                        existing.metadata.as_ref(),
+                    )?;
+                    (written.metadata, Some(written.hash))
+                }
+                None => (retitle_metadata(existing.metadata.clone(), &title), None),
+            };
+
+            let now = chrono::Utc::now().to_rfc3339();
+            let moved = Note {
+                id: existing.id.clone(),
+                title: title.clone(),
+                word_count: own_content.split_whitespace().count() as u32,
+                content: own_content.clone(),
+                file_path: target_path.as_ref().map(|p| p.to_string_lossy().to_string()),
+                created_at: existing.created_at.clone(),
+                updated_at: now.clone(),
+                metadata,
+                file_hash,
+            };
+
+            let mut updated_sources = Vec::new();
+            for (source, content) in rewrites.iter().filter(|(n, _)| n.id != existing.id) {
+                let file_hash = match &source.file_path {
+                    Some(path) => {
+                        let path = PathBuf::from(path);
+                        originals.push((path.clone(), std::fs::read_to_string(&path).ok()));
+                        let written = vault::write_note_file(
+                            &path,
+                            &source.title,
+                            content,
+                            source.metadata.as_ref(),
+                        )?;
+                        Some(written.hash)
+                    }
+                    None => source.file_hash.clone(),
+                };
+                updated_sources.push(Note {
+                    word_count: content.split_whitespace().count() as u32,
+                    content: content.clone(),
+                    updated_at: now.clone(),
+                    file_hash,
+                    ..source.clone()
+                });
+            }
 
-        let conn = self.db.get_write_conn()?;
-        conn.execute("DELETE FROM notes WHERE id = ?1", [id])?;
+            let mut conn = self.db.get_write_conn()?;
+            let tx = conn.transaction()?;
+            store_update(&tx, &self.db, &moved, &existing.content, RevisionSource::Edit)?;
+            for (updated, (source, _)) in updated_sources
+                .iter()
+                .zip(rewrites.iter().filter(|(n, _)| n.id != existing.id))
+            {
+                store_update(&tx, &self.db, updated, &source.content, RevisionSource::Edit)?;
+            }
+            tx.commit()?;
+
+            tracing::info!(
+                "Moved note {} ({} linking note(s) updated)",
+                moved.id,
+                updated_sources.len()
+            );
+            Ok(moved)
+        })();
+        let rewritten = rewrites.into_iter().map(|(note, _)| note.id).collect();
+
+        if result.is_err() {
+            // Undo in reverse: restore rewritten files, then move the note's file back
+            restore_files(&originals);
+            if let (Some(old), Some(new)) = (&old_path, &target_path) {
+                // Skip case-only renames, where both paths may be the same file
+                if old.to_string_lossy().to_lowercase() != new.to_string_lossy().to_lowercase() {
+                    let _ = std::fs::remove_file(new);
+                }
+            }
+        }
+        result.map(|moved| (moved, rewritten))
+    }
+
+    /// Where a moved note's file should go, or `None` for notes without a file.
+    fn move_target_path(
+        &self,
+        existing: &Note,
+        title: &str,
+        new_path: Option<String>,
+        vault_dir: Option<&Path>,
+    ) -> Result<Option<PathBuf>, SunderError> {
+        let old_path = existing.file_path.as_deref().map(PathBuf::from);
+
+        let Some(new_path) = new_path.map(|p| p.trim().to_string()).filter(|p| !p.is_empty()) else {
+            // No explicit path: follow the title if the file name did
+            let Some(old) = old_path else {
+                return Ok(None);
+            };
+            let stem = old.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+            let new_stem = if stem == vault::slugify(&existing.title) {
+                vault::slugify(title)
+            } else if stem == existing.title {
+                vault::title_file_stem(title)
+            } else {
+                return Ok(Some(old));
+            };
+            if new_stem == stem {
+                return Ok(Some(old));
+            }
+            let dir = old.parent().unwrap_or(Path::new("."));
+            return vault::unique_path_for_stem(dir, &new_stem, |candidate| {
+                Ok(self
+                    .get_note_by_file_path(&candidate.to_string_lossy())?
+                    .is_some())
+            })
+            .map(Some);
+        };
 
-        // Delete associated file if it exists
-        if let Some(path) = &note.file_path {
-            let path = std::path::Path::new(path);
-            if path.exists() {
-                std::fs::remove_file(path)?;
+        let mut path = PathBuf::from(&new_path);
+        if path.is_relative() {
+            let base = vault_dir
+                .map(Path::to_path_buf)
+                .or_else(|| old_path.as_ref().and_then(|p| p.parent().map(Path::to_path_buf)))
+                .ok_or_else(|| {
+                    SunderError::ValidationError(
+                        "A relative path needs a vault or an existing file to resolve against".to_string(),
+                    )
+                })?;
+            path = base.join(path);
+        }
+        match path.extension() {
+            None => {
+                path.set_extension("md");
+            }
+            Some(ext) if ext.eq_ignore_ascii_case("md") => {}
+            Some(_) => {
+                return Err(SunderError::ValidationError(format!(
+                    "Notes must be markdown files: {new_path}"
+                )))
             }
         }
+        let path = vault::normalize_path(&path);
 
-        Ok(())
+        if old_path.as_ref() == Some(&path) {
+            return Ok(Some(path));
+        }
+        // A case-only rename is the same file on case-insensitive filesystems
+        let same_file = old_path.as_ref().is_some_and(|old| {
+            old.to_string_lossy().to_lowercase() == path.to_string_lossy().to_lowercase()
+        });
+        if (path.exists() && !same_file)
+            || self.get_note_by_file_path(&path.to_string_lossy())?.is_some()
+        {
+            return Err(SunderError::AlreadyExists(format!(
+                "A file already exists at {}",
+                path.display()
+            )));
+        }
+        Ok(Some(path))
+    }
+
+    /// Delete a note by moving it (and its file) to the trash.
+    pub fn delete_note(&self, id: &str) -> Result<(), SunderError> {
+        TrashService::new(Arc::clone(&self.db)).trash_note(id)
     }
 
+    /// List notes, optionally only those tagged with `tag` or a tag nested below it,
+    /// and only those whose file is in the vault-relative `folder` (and, when
+    /// `recursive`, its subfolders).
     pub fn list_notes(
         &self,
         offset: u32,
         limit: u32,
         sort_by: &str,
+        tag: Option<&str>,
+        folder: Option<&str>,
+        recursive: bool,
     ) -> Result<NoteList, SunderError> {
+        let folder_prefix = match folder {
+            Some(folder) => {
+                let vault_dir = vault::active_vault_dir(&self.db)?.ok_or_else(|| {
+                    SunderError::ValidationError(
+                        "Filtering by folder needs a vault directory".to_string(),
+                    )
+                })?;
+                Some(folders::folder_prefix(&vault_dir, folder))
+            }
+            None => None,
+        };
+
         let conn = self.db.get_read_conn()?;
 
         let order_clause = match sort_by {
@@ -225,19 +586,30 @@ impl NoteService {
             "title" => "title ASC",
             _ => "updated_at DESC",
         };
+        let tag = tag.map(tags::normalize_tag);
+        let where_clause = format!(
+            "WHERE (?1 IS NULL OR {}) AND (?2 IS NULL OR {})",
+            tags::tag_filter_clause("id", 1),
+            folders::folder_filter_clause("file_path", 2, 3)
+        );
 
-        let total: u32 =
-            conn.query_row("SELECT COUNT(*) FROM notes", [], |row| row.get(0))?;
+        let total: u32 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM notes {where_clause}"),
+            rusqlite::params![tag, folder_prefix, recursive],
+            |row| row.get(0),
+        )?;
 
         let query = format!(
-            "SELECT id, title, content, updated_at FROM notes ORDER BY {order_clause} LIMIT ?1 OFFSET ?2"
+            "SELECT id, title, content, updated_at FROM notes {where_clause}
+             ORDER BY {order_clause} LIMIT ?4 OFFSET ?5"
         );
 
+        let options = PlainTextOptions::load(&conn)?;
         let mut stmt = conn.prepare(&query)?;
         let notes = stmt
-            .query_map(rusqlite::params![limit, offset], |row| {
+            .query_map(rusqlite::params![tag, folder_prefix, recursive, limit, offset], |row| {
                 let content: String = row.get(2)?;
-                let snippet = make_snippet(&content);
+                let snippet = plaintext::snippet(&content, &options);
                 Ok(NoteListItem {
                     id: row.get(0)?,
                     title: row.get(1)?,
@@ -249,30 +621,209 @@ impl NoteService {
 
         Ok(NoteList { notes, total })
     }
+
+    /// Recompute the text full-text search indexes for every note, after the
+    /// `fts_plain_text` or plain text settings change. Returns the number of notes reindexed.
+    pub fn refresh_fts_content(&self) -> Result<u32, SunderError> {
+        let mut conn = self.db.get_write_conn()?;
+        let tx = conn.transaction()?;
+        let notes: Vec<(String, String)> = {
+            let mut stmt = tx.prepare("SELECT id, content FROM notes")?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows
+        };
+        let mut changed = 0u32;
+        for (id, content) in &notes {
+            if sync_fts_content(&tx, id, content)? {
+                changed += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    /// Re-derive tags for every note when the derived data format has changed
+    /// since it was last built (or was never built). Returns the number of notes processed.
+    pub fn backfill_derived(&self) -> Result<u32, SunderError> {
+        let mut conn = self.db.get_write_conn()?;
+        let built: u32 = setting(&conn, "derived_index_version")?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        if built >= DERIVED_INDEX_VERSION {
+            return Ok(0);
+        }
+
+        let tx = conn.transaction()?;
+        let notes: Vec<Note> = {
+            let mut stmt = tx.prepare(&format!("SELECT {NOTE_COLUMNS} FROM notes"))?;
+            let rows = stmt
+                .query_map([], row_to_note)?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows
+        };
+        for note in &notes {
+            sync_derived(&tx, note)?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES ('derived_index_version', ?1)",
+            [DERIVED_INDEX_VERSION.to_string()],
+        )?;
+        tx.commit()?;
+
+        tracing::info!("Rebuilt derived note data for {} note(s)", notes.len());
+        Ok(notes.len() as u32)
+    }
 }
 
-/// Create a snippet from content: first 200 chars with markdown stripped
-fn make_snippet(content: &str) -> String {
-    let stripped: String = content
-        .chars()
-        .take(250)
-        .collect::<String>()
-        .lines()
-        .map(|line| {
-            line.trim()
-                .trim_start_matches('#')
-                .trim()
-                .replace("**", "")
-                .replace('*', "")
-        })
-        .collect::<Vec<_>>()
-        .join(" ");
+/// Put back files overwritten by a failed update, newest first. Files that
+/// didn't exist before are removed.
+fn restore_files(originals: &[(PathBuf, Option<String>)]) {
+    for (path, original) in originals.iter().rev() {
+        match original {
+            Some(text) => {
+                let _ = vault::write_atomic(path, text);
+            }
+            None => {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
 
-    if stripped.len() > 200 {
-        format!("{}...", &stripped[..200])
-    } else {
-        stripped
+/// Write an updated note row along with its revision (when the content
+/// changed) and derived data. Callers run it in a transaction so none of it
+/// is kept if any of it fails.
+fn store_update(
+    conn: &Connection,
+    db: &Arc<DatabaseManager>,
+    note: &Note,
+    previous_content: &str,
+    source: RevisionSource,
+) -> Result<(), SunderError> {
+    conn.execute(
+        "UPDATE notes SET title = ?1, content = ?2, word_count = ?3, updated_at = ?4, file_hash = ?5,
+                          metadata = ?6, file_path = ?7
+         WHERE id = ?8",
+        rusqlite::params![
+            note.title,
+            note.content,
+            note.word_count,
+            note.updated_at,
+            note.file_hash,
+            metadata_to_sql(&note.metadata),
+            note.file_path,
+            note.id,
+        ],
+    )?;
+    if note.content != previous_content {
+        revision::record_revision(conn, db, &note.id, &note.title, &note.content, source)?;
     }
+    sync_derived(conn, note)
+}
+
+/// Bump when `sync_derived` starts deriving something new so existing notes get backfilled.
+const DERIVED_INDEX_VERSION: u32 = 2;
+
+/// Rebuild the data derived from a note's content and front matter: tags,
+/// aliases and wikilinks. Runs on the caller's write connection alongside the note write.
+pub(crate) fn sync_derived(conn: &Connection, note: &Note) -> Result<(), SunderError> {
+    tags::sync_note_tags(conn, &note.id, note.metadata.as_ref(), &note.content)?;
+    wikilink::sync_note_aliases(conn, &note.id, note.metadata.as_ref())?;
+    wikilink::sync_note_links(conn, note)?;
+    sync_fts_content(conn, &note.id, &note.content)?;
+    Ok(())
+}
+
+/// Set the text `notes_fts` indexes for a note from its content and the plain
+/// text settings. Returns whether it changed.
+fn sync_fts_content(conn: &Connection, note_id: &str, content: &str) -> Result<bool, SunderError> {
+    let fts_content = plaintext::fts_content(conn, content)?;
+    let changed = conn.execute(
+        "UPDATE notes SET fts_content = ?1 WHERE id = ?2 AND fts_content IS NOT ?1",
+        rusqlite::params![fts_content, note_id],
+    )?;
+    Ok(changed > 0)
+}
+
+pub(crate) const NOTE_COLUMNS: &str =
+    "id, title, content, file_path, word_count, created_at, updated_at, file_hash, metadata";
+
+pub(crate) fn row_to_note(row: &rusqlite::Row<'_>) -> Result<Note, rusqlite::Error> {
+    Ok(Note {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        content: row.get(2)?,
+        file_path: row.get(3)?,
+        word_count: row.get(4)?,
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+        file_hash: row.get(7)?,
+        metadata: row
+            .get::<_, Option<String>>(8)?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+    })
+}
+
+pub(crate) fn metadata_to_sql(metadata: &Option<serde_json::Value>) -> Option<String> {
+    metadata.as_ref().map(|m| m.to_string())
+}
+
+/// Keep a `title` key in metadata in sync with the note's title.
+fn retitle_metadata(metadata: Option<serde_json::Value>, title: &str) -> Option<serde_json::Value> {
+    let mut metadata = metadata?;
+    if let Some(existing) = metadata.get_mut("title") {
+        *existing = serde_json::Value::String(title.to_string());
+    }
+    Some(metadata)
+}
+
+fn validate_title(title: String) -> Result<String, SunderError> {
+    let title = title.trim().to_string();
+    if title.is_empty() {
+        return Err(SunderError::ValidationError(
+            "Title cannot be empty".to_string(),
+        ));
+    }
+    if title.len() > 500 {
+        return Err(SunderError::ValidationError(
+            "Title must be 500 characters or fewer".to_string(),
+        ));
+    }
+    Ok(title)
+}
+
+fn validate_content(content: &str) -> Result<(), SunderError> {
+    // Max 2MB
+    if content.len() > 2 * 1024 * 1024 {
+        return Err(SunderError::ContentTooLarge(
+            "Content exceeds 2MB limit".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Merge an update's optional fields with the existing note, validating both.
+fn resolve_update(
+    existing: &Note,
+    title: Option<String>,
+    content: Option<String>,
+) -> Result<(String, String), SunderError> {
+    let new_title = match title {
+        Some(t) => validate_title(t)?,
+        None => existing.title.clone(),
+    };
+
+    let new_content = match content {
+        Some(c) => {
+            validate_content(&c)?;
+            c
+        }
+        None => existing.content.clone(),
+    };
+
+    Ok((new_title, new_content))
 }
 
 // Needed for optional query results
@@ -0,0 +1,163 @@
+use crate::error::SunderError;
+use crate::services::query::NoteFilter;
+use rusqlite::{Connection, OptionalExtension, ToSql};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Passages fetched from the vector index per requested note, so notes whose
+/// passages crowd the top of the ranking don't leave too few distinct notes.
+const CHUNKS_PER_NOTE: u32 = 8;
+/// sqlite-vec's upper bound on `k`.
+const MAX_KNN: u32 = 4096;
+/// Passages averaged per note by `ScoreAggregation::TopKMean`.
+const TOP_K: usize = 3;
+
+/// How the scores of a note's matching passages combine into the note's score.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoreAggregation {
+    /// The best passage's score.
+    #[default]
+    Max,
+    /// Mean of the best three passages' scores, favouring notes that match throughout.
+    TopKMean,
+}
+
+/// The part of a note that matched best.
+#[derive(Debug, Clone, Serialize)]
+pub struct Passage {
+    pub text: String,
+    /// Character (not byte) offsets of the passage in the note's content.
+    pub start: u32,
+    pub end: u32,
+}
+
+pub struct NoteMatch {
+    pub id: String,
+    pub title: String,
+    pub content: String,
+    pub score: f64,
+    pub passage: Passage,
+}
+
+struct ChunkHit {
+    note_id: String,
+    score: f64,
+    start: u32,
+    end: u32,
+}
+
+/// Notes whose passages are nearest to `query` (an embedding blob), best first,
+/// scored by cosine similarity.
+/// Only chunks embedded by `model_version` are compared. With a `filter`, just
+/// the chunks of the notes it lets through are scored directly, since a KNN
+/// query can't take extra conditions.
+pub fn match_notes(
+    conn: &Connection,
+    query: &[u8],
+    model_version: &str,
+    limit: u32,
+    filter: Option<&NoteFilter>,
+    exclude_note_id: Option<&str>,
+    aggregation: ScoreAggregation,
+) -> Result<Vec<NoteMatch>, SunderError> {
+    let k = (limit + u32::from(exclude_note_id.is_some()))
+        .saturating_mul(CHUNKS_PER_NOTE)
+        .clamp(1, MAX_KNN);
+
+    let filter = filter.filter(|filter| !filter.is_empty());
+    let (clause, values) = filter.map(|filter| filter.sql("c.note_id", 4)).unzip();
+    let mut stmt = match &clause {
+        None => conn.prepare(
+            "SELECT c.note_id, v.distance, c.start_offset, c.end_offset
+             FROM vec_chunks v
+             JOIN note_chunks c ON c.id = v.chunk_id AND c.model_version = ?3
+             WHERE v.embedding MATCH ?1 AND v.k = ?2
+             ORDER BY v.distance",
+        )?,
+        Some(clause) => conn.prepare(&format!(
+            "SELECT c.note_id, vec_distance_cosine(c.vector, ?1) AS distance, c.start_offset, c.end_offset
+             FROM note_chunks c
+             WHERE c.model_version = ?3 AND {clause}
+             ORDER BY distance
+             LIMIT ?2"
+        ))?,
+    };
+    let mut params = rusqlite::params![query, k, model_version].to_vec();
+    params.extend(values.iter().flatten().map(|value| value as &dyn ToSql));
+    let hits = stmt
+        .query_map(params.as_slice(), |row| {
+            let distance: f64 = row.get(1)?;
+            Ok(ChunkHit {
+                note_id: row.get(0)?,
+                // Cosine distance, so this is the cosine similarity in [-1, 1]
+                score: 1.0 - distance,
+                start: row.get(2)?,
+                end: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Hits arrive best first, so each note's first hit is its best passage
+    let mut by_note: Vec<(String, Vec<ChunkHit>)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for hit in hits {
+        if exclude_note_id == Some(hit.note_id.as_str()) {
+            continue;
+        }
+        match index.get(&hit.note_id) {
+            Some(&i) => by_note[i].1.push(hit),
+            None => {
+                index.insert(hit.note_id.clone(), by_note.len());
+                by_note.push((hit.note_id.clone(), vec![hit]));
+            }
+        }
+    }
+
+    let mut scored: Vec<(f64, String, ChunkHit)> = by_note
+        .into_iter()
+        .map(|(note_id, hits)| {
+            let score = match aggregation {
+                ScoreAggregation::Max => hits[0].score,
+                ScoreAggregation::TopKMean => {
+                    let top = &hits[..hits.len().min(TOP_K)];
+                    top.iter().map(|h| h.score).sum::<f64>() / top.len() as f64
+                }
+            };
+            (score, note_id, hits.into_iter().next().expect("grouped hits are non-empty"))
+        })
+        .collect();
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    scored.truncate(limit as usize);
+
+    let mut matches = Vec::with_capacity(scored.len());
+    for (score, note_id, best) in scored {
+        let Some((title, content)) = conn
+            .query_row(
+                "SELECT title, content FROM notes WHERE id = ?1",
+                [&note_id],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?
+        else {
+            continue;
+        };
+        let text = content
+            .chars()
+            .skip(best.start as usize)
+            .take(best.end.saturating_sub(best.start) as usize)
+            .collect();
+        matches.push(NoteMatch {
+            id: note_id,
+            title,
+            content,
+            score,
+            passage: Passage {
+                text,
+                start: best.start,
+                end: best.end,
+            },
+        });
+    }
+    Ok(matches)
+}
@@ -0,0 +1,259 @@
+use crate::error::SunderError;
+use crate::services::settings::setting;
+use pulldown_cmark::{Event, LinkType, Options, Parser, Tag, TagEnd};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Snippet length in characters, before the ellipsis.
+const SNIPPET_CHARS: usize = 200;
+/// Markdown read for a snippet; enough to fill it unless the note opens with a long code block.
+const SNIPPET_SOURCE_BYTES: usize = 4096;
+
+/// What happens to fenced and indented code blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodeBlockPolicy {
+    /// Keep the code as text.
+    Keep,
+    /// Leave code blocks out. Inline code is always kept.
+    #[default]
+    Drop,
+}
+
+impl CodeBlockPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CodeBlockPolicy::Keep => "keep",
+            CodeBlockPolicy::Drop => "drop",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "keep" => Some(CodeBlockPolicy::Keep),
+            "drop" => Some(CodeBlockPolicy::Drop),
+            _ => None,
+        }
+    }
+}
+
+/// What happens to links and images. Wikilinks always become their alias or target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkPolicy {
+    /// Keep the link text (or image alt text) only.
+    #[default]
+    Text,
+    /// Follow the text with the URL in parentheses, unless they're the same.
+    TextAndUrl,
+}
+
+impl LinkPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkPolicy::Text => "text",
+            LinkPolicy::TextAndUrl => "text_and_url",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "text" => Some(LinkPolicy::Text),
+            "text_and_url" => Some(LinkPolicy::TextAndUrl),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlainTextOptions {
+    pub code_blocks: CodeBlockPolicy,
+    pub links: LinkPolicy,
+}
+
+impl PlainTextOptions {
+    /// The `plain_text_code_blocks` and `plain_text_links` settings.
+    pub fn load(conn: &Connection) -> Result<Self, SunderError> {
+        let code_blocks = setting(conn, "plain_text_code_blocks")?
+            .as_deref()
+            .and_then(CodeBlockPolicy::parse)
+            .unwrap_or_default();
+        let links = setting(conn, "plain_text_links")?
+            .as_deref()
+            .and_then(LinkPolicy::parse)
+            .unwrap_or_default();
+        Ok(Self { code_blocks, links })
+    }
+
+    /// Short description of the options, stored with derived text so it can
+    /// be recomputed when they change.
+    pub fn key(&self) -> String {
+        format!("code={},links={}", self.code_blocks.as_str(), self.links.as_str())
+    }
+}
+
+/// Markdown rendered as plain text, remembering where each piece came from.
+#[derive(Debug, Clone, Default)]
+pub struct PlainText {
+    pub text: String,
+    /// Byte offset in `text` where each piece starts, with its source range.
+    spans: Vec<(usize, Range<usize>)>,
+}
+
+impl PlainText {
+    /// Byte offset in the markdown of the byte at `offset` in `text`. Offsets
+    /// in the separators between pieces map to the end of the piece before.
+    pub fn source_offset(&self, offset: usize) -> usize {
+        self.map_offset(self.spans.partition_point(|(start, _)| *start <= offset), offset)
+    }
+
+    /// Like `source_offset`, for the exclusive end of a range: an offset where
+    /// a piece starts maps to the end of the piece before it.
+    pub fn source_end(&self, offset: usize) -> usize {
+        self.map_offset(self.spans.partition_point(|(start, _)| *start < offset), offset)
+    }
+
+    /// Map `offset` through the piece before `spans[i]`.
+    fn map_offset(&self, i: usize, offset: usize) -> usize {
+        match i.checked_sub(1).map(|i| &self.spans[i]) {
+            Some((start, source)) => (source.start + (offset - start)).min(source.end),
+            None => self.spans.first().map_or(0, |(_, source)| source.start),
+        }
+    }
+
+    fn push(&mut self, text: &str, source: Range<usize>) {
+        if text.is_empty() {
+            return;
+        }
+        self.spans.push((self.text.len(), source));
+        self.text.push_str(text);
+    }
+
+    /// End the current line or paragraph, without leading or doubled breaks.
+    fn break_line(&mut self, blank: bool) {
+        let trimmed = self.text.trim_end_matches([' ', '\n']).len();
+        if trimmed == 0 {
+            return;
+        }
+        self.text.truncate(trimmed);
+        while self.spans.last().is_some_and(|(start, _)| *start >= trimmed) {
+            self.spans.pop();
+        }
+        self.text.push_str(if blank { "\n\n" } else { "\n" });
+    }
+
+    fn space(&mut self) {
+        if !self.text.is_empty() && !self.text.ends_with([' ', '\n']) {
+            self.text.push(' ');
+        }
+    }
+}
+
+/// Render markdown as plain text: formatting, HTML and front matter go, blocks
+/// are separated by blank lines, and code blocks and links follow `options`.
+pub fn to_plain_text(markdown: &str, options: &PlainTextOptions) -> String {
+    render(markdown, options).text
+}
+
+/// Like `to_plain_text`, keeping the mapping back to the markdown.
+pub fn render(markdown: &str, options: &PlainTextOptions) -> PlainText {
+    let parser_options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_WIKILINKS
+        | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS;
+
+    let mut out = PlainText::default();
+    let mut in_code_block = false;
+    let mut in_metadata = false;
+    // URL of each open link or image, to append when it closes
+    let mut links: Vec<Option<String>> = Vec::new();
+
+    for (event, range) in Parser::new_ext(markdown, parser_options).into_offset_iter() {
+        match event {
+            Event::Start(Tag::MetadataBlock(_)) => in_metadata = true,
+            Event::End(TagEnd::MetadataBlock(_)) => in_metadata = false,
+            _ if in_metadata => {}
+
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                out.break_line(true);
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                out.break_line(true);
+            }
+            Event::Text(text) if in_code_block => match options.code_blocks {
+                CodeBlockPolicy::Keep => out.push(&text, range),
+                CodeBlockPolicy::Drop => {}
+            },
+
+            Event::Start(Tag::Link { link_type, dest_url, .. })
+            | Event::Start(Tag::Image { link_type, dest_url, .. }) => {
+                let url = match link_type {
+                    LinkType::WikiLink { .. } => None,
+                    _ if options.links == LinkPolicy::TextAndUrl && !dest_url.is_empty() => {
+                        Some(dest_url.to_string())
+                    }
+                    _ => None,
+                };
+                links.push(url);
+            }
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                if let Some(Some(url)) = links.pop() {
+                    if !out.text.ends_with(url.as_str()) {
+                        out.space();
+                        out.text.push_str(&format!("({url})"));
+                    }
+                }
+            }
+
+            Event::Text(text) | Event::Code(text) => out.push(&text, range),
+            Event::SoftBreak => out.space(),
+            Event::HardBreak => out.break_line(false),
+            Event::End(TagEnd::TableCell) => out.space(),
+            Event::End(TagEnd::Item) | Event::End(TagEnd::TableRow) | Event::End(TagEnd::TableHead) => {
+                out.break_line(false)
+            }
+            Event::End(TagEnd::Paragraph)
+            | Event::End(TagEnd::Heading(_))
+            | Event::End(TagEnd::BlockQuote(_))
+            | Event::End(TagEnd::List(_))
+            | Event::End(TagEnd::Table)
+            | Event::End(TagEnd::FootnoteDefinition)
+            | Event::Rule => out.break_line(true),
+            _ => {}
+        }
+    }
+
+    let trimmed = out.text.trim_end().len();
+    out.text.truncate(trimmed);
+    out
+}
+
+/// One-line preview of a note: its plain text with whitespace collapsed, cut
+/// to 200 characters.
+pub fn snippet(content: &str, options: &PlainTextOptions) -> String {
+    let mut end = content.len().min(SNIPPET_SOURCE_BYTES);
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    let text = to_plain_text(&content[..end], options);
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    match collapsed.char_indices().nth(SNIPPET_CHARS) {
+        Some((cut, _)) => format!("{}...", &collapsed[..cut]),
+        None => collapsed,
+    }
+}
+
+/// The text `notes_fts` indexes in place of a note's markdown, when the
+/// `fts_plain_text` setting is on.
+pub fn fts_content(conn: &Connection, content: &str) -> Result<Option<String>, SunderError> {
+    if setting(conn, "fts_plain_text")?.as_deref() != Some("true") {
+        return Ok(None);
+    }
+    Ok(Some(to_plain_text(content, &PlainTextOptions::load(conn)?)))
+}
@@ -0,0 +1,597 @@
+use crate::error::SunderError;
+use crate::services::{folders, tags};
+use chrono::NaiveDate;
+use rusqlite::types::Value;
+use std::path::{Path, PathBuf};
+
+/// A parsed search query: what the full-text index has to match, the words to
+/// embed for semantic search, and conditions on the notes themselves.
+///
+/// Syntax: words match anywhere (all of them by default), `"exact phrase"`,
+/// `prefix*`, `title:word` (or `title:"phrase"`), `-word` or `NOT word` to
+/// exclude, `a OR b`, `AND`, and parentheses for grouping. Filters `tag:name`,
+/// `path:folder`, `before:YYYY-MM-DD`, `after:YYYY-MM-DD` and `words>:N` can be
+/// negated with `-` but not combined with `OR` or put in parentheses.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    /// FTS5 expression notes have to match; `None` when the query is only
+    /// filters and exclusions.
+    pub fts: Option<String>,
+    /// The query's words and phrases as plain text, for semantic search.
+    pub text: String,
+    /// Conditions every result has to meet.
+    pub filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// Tagged with the tag or one nested below it.
+    Tag(String),
+    /// File in the vault-relative folder or below it.
+    Path(String),
+    /// Last updated before the day.
+    Before(NaiveDate),
+    /// Last updated on or after the day.
+    After(NaiveDate),
+    /// More than this many words.
+    MinWords(u32),
+    /// Matches the FTS5 expression.
+    Match(String),
+    Not(Box<Filter>),
+}
+
+/// Parse a search query, failing with `InvalidQuery` when it's malformed and
+/// `EmptyQuery` when it asks for nothing.
+pub fn parse(query: &str) -> Result<SearchQuery, SunderError> {
+    let tokens = tokenize(query)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let node = parser.parse_or(0)?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(invalid(match token {
+            Token::Close => "Unmatched ')'".to_string(),
+            token => format!("Unexpected {}", token.describe()),
+        }));
+    }
+
+    // The top level is a conjunction of terms, exclusions and filters
+    let conjuncts = match node {
+        None => return Err(SunderError::EmptyQuery),
+        Some(Node::And(items)) => items,
+        Some(node) => vec![node],
+    };
+    let mut positive = Vec::new();
+    let mut filters = Vec::new();
+    for node in conjuncts {
+        match node {
+            Node::Filter(filter) => filters.push(filter),
+            Node::Not(inner) => match *inner {
+                Node::Filter(filter) => filters.push(Filter::Not(Box::new(filter))),
+                inner => filters.push(Filter::Not(Box::new(Filter::Match(fts(&inner)?)))),
+            },
+            node => positive.push(node),
+        }
+    }
+
+    let mut words = Vec::new();
+    for node in &positive {
+        node.collect_text(&mut words);
+    }
+    let fts = match positive.len() {
+        0 => None,
+        1 => Some(fts(&positive[0])?),
+        _ => Some(fts(&Node::And(positive))?),
+    };
+    Ok(SearchQuery {
+        fts,
+        text: words.join(" "),
+        filters,
+    })
+}
+
+/// The SQL form of a set of filters, for use in any statement.
+#[derive(Debug, Clone, Default)]
+pub struct NoteFilter {
+    filters: Vec<Filter>,
+    vault_dir: Option<PathBuf>,
+}
+
+impl NoteFilter {
+    /// `vault_dir` is needed for `path:` filters, which fail without one.
+    pub fn new(filters: Vec<Filter>, vault_dir: Option<&Path>) -> Result<Self, SunderError> {
+        if vault_dir.is_none() && filters.iter().any(Filter::needs_vault) {
+            return Err(SunderError::ValidationError(
+                "Filtering by path needs a vault directory".to_string(),
+            ));
+        }
+        Ok(Self {
+            filters,
+            vault_dir: vault_dir.map(Path::to_path_buf),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// SQL condition matching the notes whose id is in `note_id_expr` that meet
+    /// every filter, with parameters numbered from `?{first_param}`, and the
+    /// values to bind to them.
+    pub fn sql(&self, note_id_expr: &str, first_param: usize) -> (String, Vec<Value>) {
+        if self.filters.is_empty() {
+            return ("1".to_string(), Vec::new());
+        }
+        let mut values = Vec::new();
+        let conditions: Vec<String> = self
+            .filters
+            .iter()
+            .map(|filter| self.condition(filter, first_param, &mut values))
+            .collect();
+        (
+            format!(
+                "{note_id_expr} IN (SELECT n.id FROM notes n WHERE {})",
+                conditions.join(" AND ")
+            ),
+            values,
+        )
+    }
+
+    /// Condition on the note `n`, binding its values after those in `values`.
+    fn condition(&self, filter: &Filter, first_param: usize, values: &mut Vec<Value>) -> String {
+        let param = first_param + values.len();
+        match filter {
+            Filter::Tag(tag) => {
+                values.push(Value::Text(tag.clone()));
+                tags::tag_filter_clause("n.id", param)
+            }
+            Filter::Path(folder) => {
+                let vault_dir = self.vault_dir.as_deref().unwrap_or(Path::new(""));
+                values.push(Value::Text(folders::folder_prefix(vault_dir, folder)));
+                values.push(Value::Integer(1));
+                folders::folder_filter_clause("n.file_path", param, param + 1)
+            }
+            // Timestamps are RFC 3339, so they compare with a bare date as text
+            Filter::Before(day) => {
+                values.push(Value::Text(day.to_string()));
+                format!("n.updated_at < ?{param}")
+            }
+            Filter::After(day) => {
+                values.push(Value::Text(day.to_string()));
+                format!("n.updated_at >= ?{param}")
+            }
+            Filter::MinWords(count) => {
+                values.push(Value::Integer(i64::from(*count)));
+                format!("n.word_count > ?{param}")
+            }
+            Filter::Match(expr) => {
+                values.push(Value::Text(expr.clone()));
+                format!("n.rowid IN (SELECT rowid FROM notes_fts WHERE notes_fts MATCH ?{param})")
+            }
+            Filter::Not(inner) => format!("NOT ({})", self.condition(inner, first_param, values)),
+        }
+    }
+}
+
+impl Filter {
+    fn needs_vault(&self) -> bool {
+        match self {
+            Filter::Path(_) => true,
+            Filter::Not(inner) => inner.needs_vault(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// A word or quoted phrase, with the column it's limited to and whether
+    /// it's a prefix.
+    Term {
+        text: String,
+        prefix: bool,
+        column: Option<&'static str>,
+    },
+    Filter(Filter),
+    Minus,
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Term { text, .. } => format!("'{text}'"),
+            Token::Filter(_) => "filter".to_string(),
+            Token::Minus => "'-'".to_string(),
+            Token::And => "AND".to_string(),
+            Token::Or => "OR".to_string(),
+            Token::Not => "NOT".to_string(),
+            Token::Open => "'('".to_string(),
+            Token::Close => "')'".to_string(),
+        }
+    }
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, SunderError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        match c {
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+                continue;
+            }
+            // `-` negates what follows it directly; elsewhere it's part of a word
+            '-' if chars
+                .clone()
+                .nth(1)
+                .is_some_and(|(_, next)| !next.is_whitespace() && next != ')') =>
+            {
+                chars.next();
+                tokens.push(Token::Minus);
+                continue;
+            }
+            '"' => {
+                chars.next();
+                let text = quoted(query, start, &mut chars)?;
+                let prefix = chars.next_if(|&(_, c)| c == '*').is_some();
+                tokens.push(Token::Term {
+                    text,
+                    prefix,
+                    column: None,
+                });
+                continue;
+            }
+            _ => {}
+        }
+
+        // A bare word runs to whitespace, a parenthesis or a quote
+        let mut end = start;
+        while let Some(&(i, c)) = chars.peek() {
+            if c.is_whitespace() || matches!(c, '(' | ')' | '"') {
+                break;
+            }
+            end = i + c.len_utf8();
+            chars.next();
+        }
+        let word = &query[start..end];
+
+        if let Some((field, value)) = word.split_once(':') {
+            if let Some(field) = Field::parse(field) {
+                // `field:"quoted value"`
+                let (value, phrase) = if value.is_empty() && chars.next_if(|&(_, c)| c == '"').is_some() {
+                    (quoted(query, end, &mut chars)?, true)
+                } else {
+                    (value.to_string(), false)
+                };
+                tokens.push(field.token(value, phrase, &mut chars)?);
+                continue;
+            }
+        }
+
+        tokens.push(match word {
+            "AND" => Token::And,
+            "OR" => Token::Or,
+            "NOT" => Token::Not,
+            _ => {
+                let (text, prefix) = prefixed(word)?;
+                Token::Term {
+                    text: text.to_string(),
+                    prefix,
+                    column: None,
+                }
+            }
+        });
+    }
+    Ok(tokens)
+}
+
+/// The text up to the closing quote, the opening one (at `open`) having been read.
+fn quoted(
+    query: &str,
+    open: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+) -> Result<String, SunderError> {
+    let mut text = String::new();
+    for (_, c) in chars.by_ref() {
+        if c == '"' {
+            return Ok(text);
+        }
+        text.push(c);
+    }
+    Err(invalid(format!(
+        "Unclosed quote at character {}",
+        query[..open].chars().count() + 1
+    )))
+}
+
+/// A word without its trailing `*`, and whether it had one.
+fn prefixed(word: &str) -> Result<(&str, bool), SunderError> {
+    let (text, prefix) = match word.strip_suffix('*') {
+        Some(text) => (text, true),
+        None => (word, false),
+    };
+    if text.contains('*') {
+        return Err(invalid(format!("'{word}': '*' can only end a word")));
+    }
+    if prefix && text.is_empty() {
+        return Err(invalid("'*' needs a word to complete".to_string()));
+    }
+    Ok((text, prefix))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Title,
+    Tag,
+    Path,
+    Before,
+    After,
+    Words,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "title" => Some(Field::Title),
+            "tag" => Some(Field::Tag),
+            "path" => Some(Field::Path),
+            "before" => Some(Field::Before),
+            "after" => Some(Field::After),
+            "words>" => Some(Field::Words),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Title => "title:",
+            Field::Tag => "tag:",
+            Field::Path => "path:",
+            Field::Before => "before:",
+            Field::After => "after:",
+            Field::Words => "words>:",
+        }
+    }
+
+    fn token(
+        self,
+        value: String,
+        phrase: bool,
+        chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+    ) -> Result<Token, SunderError> {
+        if value.trim().is_empty() {
+            return Err(invalid(format!("{} needs a value", self.name())));
+        }
+        let date = |value: &str| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+                invalid(format!("{} needs a date like 2024-05-31, not '{value}'", self.name()))
+            })
+        };
+        Ok(match self {
+            Field::Title => {
+                let (text, prefix) = if phrase {
+                    (value.as_str(), chars.next_if(|&(_, c)| c == '*').is_some())
+                } else {
+                    prefixed(&value)?
+                };
+                Token::Term {
+                    text: text.to_string(),
+                    prefix,
+                    column: Some("title"),
+                }
+            }
+            Field::Tag => Token::Filter(Filter::Tag(tags::normalize_tag(&value))),
+            Field::Path => Token::Filter(Filter::Path(folders::normalize_folder(&value))),
+            Field::Before => Token::Filter(Filter::Before(date(&value)?)),
+            Field::After => Token::Filter(Filter::After(date(&value)?)),
+            Field::Words => Token::Filter(Filter::MinWords(value.parse().map_err(|_| {
+                invalid(format!("words>: needs a number, not '{value}'"))
+            })?)),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Term {
+        text: String,
+        prefix: bool,
+        column: Option<&'static str>,
+    },
+    Filter(Filter),
+    And(Vec<Node>),
+    Or(Vec<Node>),
+    Not(Box<Node>),
+}
+
+impl Node {
+    /// Words of the terms not excluded, for embedding.
+    fn collect_text(&self, words: &mut Vec<String>) {
+        match self {
+            Node::Term { text, .. } => words.push(text.clone()),
+            Node::And(items) | Node::Or(items) => {
+                for item in items {
+                    item.collect_text(words);
+                }
+            }
+            Node::Filter(_) | Node::Not(_) => {}
+        }
+    }
+
+    fn has_filter(&self) -> bool {
+        match self {
+            Node::Filter(_) => true,
+            Node::And(items) | Node::Or(items) => items.iter().any(Node::has_filter),
+            Node::Not(inner) => inner.has_filter(),
+            Node::Term { .. } => false,
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// `and (OR and)*`. `depth` counts the parentheses around it.
+    fn parse_or(&mut self, depth: usize) -> Result<Option<Node>, SunderError> {
+        let mut branches = Vec::new();
+        loop {
+            match self.parse_and(depth)? {
+                Some(branch) => branches.push(branch),
+                None if branches.is_empty() && self.peek() != Some(&Token::Or) => return Ok(None),
+                None => return Err(invalid("OR needs a term on each side".to_string())),
+            }
+            if self.peek() != Some(&Token::Or) {
+                break;
+            }
+            self.pos += 1;
+        }
+        if branches.len() == 1 {
+            return Ok(branches.pop());
+        }
+        if branches.iter().any(Node::has_filter) {
+            return Err(invalid("Filters can't be combined with OR".to_string()));
+        }
+        Ok(Some(Node::Or(branches)))
+    }
+
+    /// `unary (AND? unary)*`, up to an OR, a closing parenthesis or the end.
+    fn parse_and(&mut self, depth: usize) -> Result<Option<Node>, SunderError> {
+        let mut items = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::Or) | Some(Token::Close) => break,
+                Some(Token::And) => {
+                    self.pos += 1;
+                    if items.is_empty() || matches!(self.peek(), None | Some(Token::Or | Token::Close)) {
+                        return Err(invalid("AND needs a term on each side".to_string()));
+                    }
+                }
+                Some(_) => items.push(self.parse_unary(depth)?),
+            }
+        }
+        Ok(match items.len() {
+            0 => None,
+            1 => items.pop(),
+            _ => Some(Node::And(items)),
+        })
+    }
+
+    fn parse_unary(&mut self, depth: usize) -> Result<Node, SunderError> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            return Err(invalid("Query ends too early".to_string()));
+        };
+        self.pos += 1;
+        match token {
+            Token::Minus | Token::Not => {
+                if self.peek().is_none() {
+                    return Err(invalid(format!("{} needs something to exclude", token.describe())));
+                }
+                let inner = self.parse_unary(depth)?;
+                if matches!(inner, Node::Not(_)) {
+                    return Err(invalid("Exclusions can't be nested".to_string()));
+                }
+                Ok(Node::Not(Box::new(inner)))
+            }
+            Token::Open => {
+                let node = self.parse_or(depth + 1)?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(invalid("Unclosed '('".to_string()));
+                }
+                self.pos += 1;
+                node.ok_or_else(|| invalid("Empty parentheses".to_string()))
+            }
+            Token::Filter(filter) if depth == 0 => Ok(Node::Filter(filter)),
+            Token::Filter(_) => Err(invalid("Filters can't go inside parentheses".to_string())),
+            Token::Term {
+                text,
+                prefix,
+                column,
+            } => Ok(Node::Term {
+                text,
+                prefix,
+                column,
+            }),
+            token => Err(invalid(format!("Unexpected {}", token.describe()))),
+        }
+    }
+}
+
+/// FTS5 expression for a node. FTS5's NOT is binary, so exclusions have to sit
+/// in a group with something to exclude from.
+fn fts(node: &Node) -> Result<String, SunderError> {
+    Ok(match node {
+        Node::Term {
+            text,
+            prefix,
+            column,
+        } => {
+            // Quoting makes the text a phrase, whatever punctuation it holds
+            let mut expr = format!("\"{}\"", text.replace('"', "\"\""));
+            if *prefix {
+                expr.push('*');
+            }
+            match column {
+                Some(column) => format!("{column} : {expr}"),
+                None => expr,
+            }
+        }
+        Node::And(items) => {
+            let (excluded, included): (Vec<&Node>, Vec<&Node>) =
+                items.iter().partition(|item| matches!(item, Node::Not(_)));
+            if included.is_empty() {
+                return Err(invalid(
+                    "A group of only exclusions needs a term to exclude from".to_string(),
+                ));
+            }
+            let included = included
+                .iter()
+                .map(|item| fts(item).map(|expr| format!("({expr})")))
+                .collect::<Result<Vec<_>, _>>()?
+                .join(" AND ");
+            if excluded.is_empty() {
+                included
+            } else {
+                let excluded = excluded
+                    .iter()
+                    .map(|item| match item {
+                        Node::Not(inner) => fts(inner).map(|expr| format!("({expr})")),
+                        _ => unreachable!("partitioned on Node::Not"),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(" OR ");
+                format!("({included}) NOT ({excluded})")
+            }
+        }
+        Node::Or(items) => items
+            .iter()
+            .map(|item| fts(item).map(|expr| format!("({expr})")))
+            .collect::<Result<Vec<_>, _>>()?
+            .join(" OR "),
+        Node::Not(_) => {
+            return Err(invalid(
+                "An exclusion needs a term to exclude from in the same group".to_string(),
+            ))
+        }
+        Node::Filter(_) => return Err(invalid("Filters can't go inside parentheses".to_string())),
+    })
+}
+
+fn invalid(message: String) -> SunderError {
+    SunderError::InvalidQuery(message)
+}
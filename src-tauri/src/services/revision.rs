@@ -0,0 +1,209 @@
+use crate::db::DatabaseManager;
+use crate::error::SunderError;
+use crate::services::note::{Note, NoteService};
+use crate::services::settings::SettingsService;
+use rusqlite::Connection;
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use std::sync::Arc;
+
+/// What caused a revision to be recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevisionSource {
+    Create,
+    Edit,
+    Import,
+    Restore,
+}
+
+impl RevisionSource {
+    fn as_str(self) -> &'static str {
+        match self {
+            RevisionSource::Create => "create",
+            RevisionSource::Edit => "edit",
+            RevisionSource::Import => "import",
+            RevisionSource::Restore => "restore",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevisionSummary {
+    pub id: i64,
+    pub note_id: String,
+    pub title: String,
+    pub source: String,
+    pub word_count: u32,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiffLine {
+    pub op: String, // "equal", "insert", or "delete"
+    pub old_line: Option<u32>,
+    pub new_line: Option<u32>,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevisionDiff {
+    pub from_revision_id: i64,
+    /// `None` when diffing against the note's current content.
+    pub to_revision_id: Option<i64>,
+    pub insertions: u32,
+    pub deletions: u32,
+    pub lines: Vec<DiffLine>,
+}
+
+pub struct RevisionService {
+    db: Arc<DatabaseManager>,
+}
+
+impl RevisionService {
+    pub fn new(db: Arc<DatabaseManager>) -> Self {
+        Self { db }
+    }
+
+    pub fn list_revisions(&self, note_id: &str) -> Result<Vec<RevisionSummary>, SunderError> {
+        let conn = self.db.get_read_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, note_id, title, source, content, created_at
+             FROM note_revisions WHERE note_id = ?1
+             ORDER BY id DESC",
+        )?;
+        let revisions = stmt
+            .query_map([note_id], |row| {
+                let content: String = row.get(4)?;
+                Ok(RevisionSummary {
+                    id: row.get(0)?,
+                    note_id: row.get(1)?,
+                    title: row.get(2)?,
+                    source: row.get(3)?,
+                    word_count: content.split_whitespace().count() as u32,
+                    created_at: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(revisions)
+    }
+
+    /// Line diff from one revision to another, or to the current note content
+    /// when `to_revision_id` is `None`.
+    pub fn diff_revisions(
+        &self,
+        note_id: &str,
+        from_revision_id: i64,
+        to_revision_id: Option<i64>,
+    ) -> Result<RevisionDiff, SunderError> {
+        let old = self.get_revision_content(note_id, from_revision_id)?;
+        let new = match to_revision_id {
+            Some(id) => self.get_revision_content(note_id, id)?,
+            None => NoteService::new(Arc::clone(&self.db)).get_note(note_id)?.content,
+        };
+
+        let diff = TextDiff::from_lines(&old, &new);
+        let mut insertions = 0u32;
+        let mut deletions = 0u32;
+        let lines = diff
+            .iter_all_changes()
+            .map(|change| {
+                let op = match change.tag() {
+                    ChangeTag::Equal => "equal",
+                    ChangeTag::Insert => {
+                        insertions += 1;
+                        "insert"
+                    }
+                    ChangeTag::Delete => {
+                        deletions += 1;
+                        "delete"
+                    }
+                };
+                DiffLine {
+                    op: op.to_string(),
+                    old_line: change.old_index().map(|i| i as u32 + 1),
+                    new_line: change.new_index().map(|i| i as u32 + 1),
+                    text: change.value().trim_end_matches(['\n', '\r']).to_string(),
+                }
+            })
+            .collect();
+
+        Ok(RevisionDiff {
+            from_revision_id,
+            to_revision_id,
+            insertions,
+            deletions,
+            lines,
+        })
+    }
+
+    /// Restore an old revision's content as a new update (which itself becomes a revision).
+    pub fn restore_revision(&self, note_id: &str, revision_id: i64) -> Result<Note, SunderError> {
+        let content = self.get_revision_content(note_id, revision_id)?;
+        NoteService::new(Arc::clone(&self.db)).update_note_as(
+            note_id,
+            None,
+            Some(content),
+            RevisionSource::Restore,
+        )
+    }
+
+    fn get_revision_content(&self, note_id: &str, revision_id: i64) -> Result<String, SunderError> {
+        let conn = self.db.get_read_conn()?;
+        conn.query_row(
+            "SELECT content FROM note_revisions WHERE id = ?1 AND note_id = ?2",
+            rusqlite::params![revision_id, note_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => {
+                SunderError::NotFound(format!("Revision not found: {revision_id}"))
+            }
+            _ => SunderError::from(e),
+        })
+    }
+}
+
+/// Record a content snapshot for a note and prune old revisions. Takes the
+/// caller's write connection so it runs alongside the note write.
+pub fn record_revision(
+    conn: &Connection,
+    db: &Arc<DatabaseManager>,
+    note_id: &str,
+    title: &str,
+    content: &str,
+    source: RevisionSource,
+) -> Result<(), SunderError> {
+    let now = chrono::Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO note_revisions (note_id, title, content, source, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![note_id, title, content, source.as_str(), now],
+    )?;
+
+    let settings = SettingsService::new(Arc::clone(db)).get_settings()?;
+
+    // Always keep the newest revision, whatever the limits say
+    if settings.revision_max_per_note > 0 {
+        conn.execute(
+            "DELETE FROM note_revisions
+             WHERE note_id = ?1 AND id NOT IN (
+                 SELECT id FROM note_revisions WHERE note_id = ?1
+                 ORDER BY id DESC LIMIT ?2
+             )",
+            rusqlite::params![note_id, settings.revision_max_per_note.max(1)],
+        )?;
+    }
+    if settings.revision_max_age_days > 0 {
+        let cutoff = (chrono::Utc::now()
+            - chrono::Duration::days(settings.revision_max_age_days as i64))
+        .to_rfc3339();
+        conn.execute(
+            "DELETE FROM note_revisions
+             WHERE note_id = ?1 AND created_at < ?2
+               AND id != (SELECT MAX(id) FROM note_revisions WHERE note_id = ?1)",
+            rusqlite::params![note_id, cutoff],
+        )?;
+    }
+
+    Ok(())
+}
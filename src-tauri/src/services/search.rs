@@ -1,8 +1,16 @@
 use crate::db::DatabaseManager;
 use crate::error::SunderError;
-use crate::services::embedding::{embedding_to_blob, EmbeddingService};
+use crate::services::embedding::{embedding_to_blob, EmbeddingSlot};
+use crate::services::passage::{self, Passage, ScoreAggregation};
+use crate::services::plaintext::{self, PlainText, PlainTextOptions};
+use crate::services::query::{self, Filter, NoteFilter};
+use crate::services::settings::setting;
+use crate::services::{tags, vault};
+use rusqlite::{Connection, ToSql};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -13,248 +21,868 @@ pub enum SearchMode {
     Semantic,
 }
 
+/// Marks FTS5 puts around hits in `snippet()` and `highlight()` output.
+const HIT_OPEN: char = '\u{2}';
+const HIT_CLOSE: char = '\u{3}';
+/// Tokens of context FTS5 puts in a full-text snippet.
+const SNIPPET_TOKENS: u32 = 32;
+/// Notes ranked on each side of hybrid and semantic search; their pages go
+/// no deeper than this.
+const RANKING_DEPTH: u32 = 500;
+
+/// How hybrid search turns the full-text and semantic rankings into one score.
+/// With the score-based methods, a ranking whose scores are all the same (a
+/// single match, say) scores 1 throughout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FusionMethod {
+    /// Reciprocal rank fusion: `1 / (k + rank)` per ranking, ignoring scores.
+    #[default]
+    Rrf,
+    /// Scores scaled to 0..1 between the worst and best in each ranking.
+    MinMax,
+    /// Scores as standard deviations from each ranking's mean, shifted so the
+    /// worst is 0.
+    ZScore,
+}
+
+impl FusionMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FusionMethod::Rrf => "rrf",
+            FusionMethod::MinMax => "min_max",
+            FusionMethod::ZScore => "z_score",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "rrf" => Some(FusionMethod::Rrf),
+            "min_max" => Some(FusionMethod::MinMax),
+            "z_score" => Some(FusionMethod::ZScore),
+            _ => None,
+        }
+    }
+}
+
+/// How hybrid search combines its rankings: each note's score is the sum over
+/// the rankings it's in of `weight * normalized score`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FusionOptions {
+    pub method: FusionMethod,
+    /// The `k` in reciprocal rank fusion; larger values flatten the head of
+    /// each ranking.
+    pub rrf_k: f64,
+    pub fulltext_weight: f64,
+    pub semantic_weight: f64,
+}
+
+impl Default for FusionOptions {
+    fn default() -> Self {
+        Self {
+            method: FusionMethod::Rrf,
+            rrf_k: 60.0,
+            fulltext_weight: 1.0,
+            semantic_weight: 1.0,
+        }
+    }
+}
+
+impl FusionOptions {
+    /// The `search_fusion`, `search_rrf_k`, `search_fulltext_weight` and
+    /// `search_semantic_weight` settings.
+    pub fn load(conn: &Connection) -> Result<Self, SunderError> {
+        let number = |key: &str, default: f64| -> Result<f64, SunderError> {
+            Ok(setting(conn, key)?
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default))
+        };
+        let defaults = Self::default();
+        Ok(Self {
+            method: setting(conn, "search_fusion")?
+                .as_deref()
+                .and_then(FusionMethod::parse)
+                .unwrap_or_default(),
+            rrf_k: number("search_rrf_k", defaults.rrf_k)?,
+            fulltext_weight: number("search_fulltext_weight", defaults.fulltext_weight)?,
+            semantic_weight: number("search_semantic_weight", defaults.semantic_weight)?,
+        })
+    }
+
+    /// Normalized scores for a ranking, best first, given its raw scores
+    /// (higher is better).
+    fn normalize(&self, scores: &[f64]) -> Vec<f64> {
+        match self.method {
+            FusionMethod::Rrf => (0..scores.len())
+                .map(|rank| 1.0 / (self.rrf_k + rank as f64 + 1.0))
+                .collect(),
+            FusionMethod::MinMax => {
+                let (min, max) = min_max(scores);
+                scores
+                    .iter()
+                    .map(|s| {
+                        if max > min {
+                            (s - min) / (max - min)
+                        } else {
+                            1.0
+                        }
+                    })
+                    .collect()
+            }
+            FusionMethod::ZScore => {
+                let n = scores.len() as f64;
+                let mean = scores.iter().sum::<f64>() / n;
+                let std_dev = (scores.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / n).sqrt();
+                if std_dev == 0.0 {
+                    return vec![1.0; scores.len()];
+                }
+                let (min, _) = min_max(scores);
+                scores.iter().map(|s| (s - min) / std_dev).collect()
+            }
+        }
+    }
+}
+
+fn min_max(scores: &[f64]) -> (f64, f64) {
+    scores
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &s| {
+            (min.min(s), max.max(s))
+        })
+}
+
+/// Where a note stood in one ranking and what that added to its score.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SignalScore {
+    /// 1 for the best match.
+    pub rank: u32,
+    /// BM25 for full-text matches (made positive, higher is better), or the
+    /// aggregated passage similarity for semantic ones.
+    pub score: f64,
+    pub normalized: f64,
+    pub weight: f64,
+    /// `weight * normalized`.
+    pub contribution: f64,
+}
+
+/// How a result's score was computed, returned when searching with `explain`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreExplanation {
+    /// The fusion settings, for hybrid results; single-mode scores are the
+    /// ranking's own.
+    pub fusion: Option<FusionOptions>,
+    pub fulltext: Option<SignalScore>,
+    pub semantic: Option<SignalScore>,
+}
+
+/// A span of text, as character (not byte) offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TextRange {
+    pub start: u32,
+    pub end: u32,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct SearchResult {
     pub id: String,
     pub title: String,
+    /// Text around the full-text hits, or the best passage for semantic matches.
     pub snippet: String,
+    /// The hits within `snippet`.
+    pub snippet_highlights: Vec<TextRange>,
+    /// The hits within the note's content: each full-text hit, or the best
+    /// passage for semantic matches.
+    pub matches: Vec<TextRange>,
     pub score: f64,
     pub match_type: String, // "fulltext", "semantic", or "both"
+    /// Best-matching passage, for semantic matches.
+    pub passage: Option<Passage>,
+    /// Position (1 for the best) and score in the full-text ranking.
+    pub fulltext_rank: Option<u32>,
+    pub fulltext_score: Option<f64>,
+    /// Position and score in the semantic ranking.
+    pub semantic_rank: Option<u32>,
+    pub semantic_score: Option<f64>,
+    /// Full scoring breakdown, when searching with `explain`.
+    pub explanation: Option<ScoreExplanation>,
+}
+
+/// Where a page of search results starts: `{"offset": 20}` or `{"cursor": ...}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageStart {
+    Offset(u32),
+    /// The `next_cursor` of the page before, from the same search.
+    Cursor(String),
+}
+
+/// Which page of search results to return.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageRequest {
+    pub limit: u32,
+    pub start: PageStart,
+}
+
+/// How notes are matched and scored, beyond the query itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SearchOptions {
+    /// How passage scores combine into a note's semantic score.
+    pub aggregation: ScoreAggregation,
+    /// Cosine similarity below which semantic matches are dropped; the
+    /// `search_min_similarity` setting when unset.
+    pub min_similarity: Option<f64>,
+    /// Return each result's scoring breakdown.
+    pub explain: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    /// Results across all pages.
+    pub total: u32,
+    /// Notes matching the full-text query, when there is one.
+    pub fulltext_total: Option<u32>,
+    /// Notes the semantic side ranked, when it ran. Approximate: it only sees
+    /// the notes with the nearest passages, up to `RANKING_DEPTH`.
+    pub semantic_total: Option<u32>,
+    /// Start of the next page, if there is one.
+    pub next_cursor: Option<String>,
 }
 
 struct ScoredNote {
     id: String,
     title: String,
     snippet: String,
+    snippet_highlights: Vec<TextRange>,
+    matches: Vec<TextRange>,
+    score: f64,
+    passage: Option<Passage>,
+}
+
+impl ScoredNote {
+    /// This note as a result scored from its place in the rankings.
+    fn into_result(
+        self,
+        fulltext: Option<SignalScore>,
+        semantic: Option<SignalScore>,
+        fusion: Option<FusionOptions>,
+        explain: bool,
+    ) -> SearchResult {
+        let match_type = match (&fulltext, &semantic) {
+            (Some(_), Some(_)) => "both",
+            (None, Some(_)) => "semantic",
+            _ => "fulltext",
+        };
+        let score = match fusion {
+            Some(_) => fulltext
+                .iter()
+                .chain(&semantic)
+                .map(|s| s.contribution)
+                .sum(),
+            None => self.score,
+        };
+        SearchResult {
+            id: self.id,
+            title: self.title,
+            snippet: self.snippet,
+            snippet_highlights: self.snippet_highlights,
+            matches: self.matches,
+            score,
+            match_type: match_type.to_string(),
+            passage: self.passage,
+            fulltext_rank: fulltext.map(|s| s.rank),
+            fulltext_score: fulltext.map(|s| s.score),
+            semantic_rank: semantic.map(|s| s.rank),
+            semantic_score: semantic.map(|s| s.score),
+            explanation: explain.then_some(ScoreExplanation {
+                fusion,
+                fulltext,
+                semantic,
+            }),
+        }
+    }
+}
+
+/// A note's place in the final ranking, before its snippet is made.
+struct RankedNote {
+    id: String,
     score: f64,
+    fulltext: Option<SignalScore>,
+    semantic: Option<SignalScore>,
+    /// The note as a semantic match, when it is one.
+    semantic_match: Option<ScoredNote>,
+}
+
+/// The value of the setting `key`, if it has one.
+/// Place in a ranking, starting from `first_rank`, for each of `scores`.
+fn signal_scores(
+    scores: &[f64],
+    normalized: &[f64],
+    weight: f64,
+    first_rank: u32,
+) -> Vec<SignalScore> {
+    scores
+        .iter()
+        .zip(normalized)
+        .zip(first_rank..)
+        .map(|((&score, &normalized), rank)| SignalScore {
+            rank,
+            score,
+            normalized,
+            weight,
+            contribution: weight * normalized,
+        })
+        .collect()
+}
+
+/// Identifies a search, so its cursors can't be used with another.
+fn search_fingerprint(
+    query: &str,
+    mode: &SearchMode,
+    tag: Option<&str>,
+    aggregation: ScoreAggregation,
+    min_similarity: f64,
+    fusion: &FusionOptions,
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "{query}\0{mode:?}\0{tag:?}\0{aggregation:?}\0{min_similarity}\0{fusion:?}"
+    ));
+    hex::encode(&hasher.finalize()[..8])
+}
+
+fn cursor_offset(cursor: &str, fingerprint: &str) -> Result<u32, SunderError> {
+    cursor
+        .split_once('.')
+        .filter(|(_, search)| *search == fingerprint)
+        .and_then(|(offset, _)| offset.parse().ok())
+        .ok_or_else(|| {
+            SunderError::InvalidValue("cursor is malformed or from another search".to_string())
+        })
 }
 
 pub struct SearchService {
     db: Arc<DatabaseManager>,
-    embedding_service: Arc<EmbeddingService>,
+    embedding: Arc<EmbeddingSlot>,
 }
 
 impl SearchService {
-    pub fn new(db: Arc<DatabaseManager>, embedding_service: Arc<EmbeddingService>) -> Self {
-        Self {
-            db,
-            embedding_service,
-        }
+    pub fn new(db: Arc<DatabaseManager>, embedding: Arc<EmbeddingSlot>) -> Self {
+        Self { db, embedding }
     }
 
+    /// Search notes, optionally restricted to those tagged with `tag` or a tag
+    /// nested below it. The query is parsed by `query::parse`; its filters
+    /// apply to both full-text and semantic matches, and a query of nothing but
+    /// filters and exclusions lists the notes meeting them, most recently
+    /// updated first. Semantic matching is done per passage, with passage
+    /// scores combined into note scores by `options.aggregation`; notes whose
+    /// score falls below the minimum similarity are left out. Without an
+    /// embedding model, hybrid search is full-text only and semantic search
+    /// fails with `EmbeddingUnavailable`.
+    ///
+    /// Results come a page at a time. Hybrid and semantic search rank a fixed
+    /// number of notes on each side and page through the result, so while notes
+    /// and settings stay the same, pages neither repeat nor skip a note.
     pub fn search(
         &self,
         query: &str,
         mode: &SearchMode,
-        limit: u32,
-    ) -> Result<Vec<SearchResult>, SunderError> {
-        let query = query.trim();
-        if query.is_empty() {
-            return Err(SunderError::EmptyQuery);
+        page_request: &PageRequest,
+        tag: Option<&str>,
+        options: &SearchOptions,
+    ) -> Result<SearchPage, SunderError> {
+        let limit = page_request.limit;
+        let SearchOptions {
+            aggregation,
+            explain,
+            ..
+        } = *options;
+        let (fusion, min_similarity) = {
+            let conn = self.db.get_read_conn()?;
+            let min_similarity = match options.min_similarity {
+                Some(similarity) => similarity,
+                None => setting(&conn, "search_min_similarity")?
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0.2),
+            };
+            (FusionOptions::load(&conn)?, min_similarity)
+        };
+        if !(-1.0..=1.0).contains(&min_similarity) {
+            return Err(SunderError::InvalidValue(
+                "min_similarity must be between -1.0 and 1.0".to_string(),
+            ));
         }
+        let fingerprint =
+            search_fingerprint(query, mode, tag, aggregation, min_similarity, &fusion);
+        let offset = match &page_request.start {
+            PageStart::Offset(offset) => *offset,
+            PageStart::Cursor(cursor) => cursor_offset(cursor, &fingerprint)?,
+        };
 
-        match mode {
-            SearchMode::Fulltext => {
-                let results = self.fulltext_search(query, limit)?;
-                Ok(results
-                    .into_iter()
-                    .map(|r| SearchResult {
-                        id: r.id,
-                        title: r.title,
-                        snippet: r.snippet,
-                        score: r.score,
-                        match_type: "fulltext".to_string(),
-                    })
-                    .collect())
+        let query = query::parse(query)?;
+        let mut filters = query.filters;
+        if let Some(tag) = tag {
+            filters.push(Filter::Tag(tags::normalize_tag(tag)));
+        }
+        let vault_dir = vault::active_vault_dir(&self.db)?;
+        let filter = NoteFilter::new(filters, vault_dir.as_deref())?;
+        let fts = query.fts.as_deref();
+
+        let mut page = SearchPage {
+            results: Vec::new(),
+            total: 0,
+            fulltext_total: None,
+            semantic_total: None,
+            next_cursor: None,
+        };
+        let window = offset as usize..offset.saturating_add(limit) as usize;
+        let (ranked, fusion) = match mode {
+            SearchMode::Hybrid if self.embedding.is_available() && !query.text.is_empty() => {
+                let fts_ranking = match fts {
+                    Some(fts) => {
+                        page.fulltext_total = Some(self.fulltext_count(fts, &filter)?);
+                        self.fulltext_ranking(fts, &filter, RANKING_DEPTH, 0)?
+                    }
+                    None => Vec::new(),
+                };
+                let sem_results =
+                    self.semantic_ranking(&query.text, &filter, aggregation, min_similarity)?;
+                page.semantic_total = Some(sem_results.len() as u32);
+                let ranked = fuse(fts_ranking, sem_results, &fusion);
+                page.total = ranked.len() as u32;
+                (take_window(ranked, &window), Some(fusion))
             }
-            SearchMode::Semantic => {
-                let embedding = self.embedding_service.embed_text(query)?;
-                let results = self.semantic_search(&embedding, limit)?;
-                Ok(results
+            SearchMode::Semantic if !query.text.is_empty() => {
+                let sem_results =
+                    self.semantic_ranking(&query.text, &filter, aggregation, min_similarity)?;
+                page.semantic_total = Some(sem_results.len() as u32);
+                page.total = sem_results.len() as u32;
+                let scores: Vec<f64> = sem_results.iter().map(|r| r.score).collect();
+                let ranked = sem_results
                     .into_iter()
-                    .map(|r| SearchResult {
-                        id: r.id,
-                        title: r.title,
-                        snippet: r.snippet,
-                        score: r.score,
-                        match_type: "semantic".to_string(),
-                    })
-                    .collect())
+                    .zip(signal_scores(&scores, &scores, 1.0, 1))
+                    .map(|(note, signal)| RankedNote {
+                        id: note.id.clone(),
+                        score: note.score,
+                        fulltext: None,
+                        semantic: Some(signal),
+                        semantic_match: Some(note),
+                    });
+                (take_window(ranked.collect(), &window), None)
             }
-            SearchMode::Hybrid => self.hybrid_search(query, limit),
+            _ => match fts {
+                Some(fts) => {
+                    let total = self.fulltext_count(fts, &filter)?;
+                    page.fulltext_total = Some(total);
+                    page.total = total;
+                    let ranking = self.fulltext_ranking(fts, &filter, limit, offset)?;
+                    let scores: Vec<f64> = ranking.iter().map(|(_, score)| *score).collect();
+                    let ranked = ranking
+                        .into_iter()
+                        .zip(signal_scores(&scores, &scores, 1.0, offset + 1))
+                        .map(|((id, score), signal)| RankedNote {
+                            id,
+                            score,
+                            fulltext: Some(signal),
+                            semantic: None,
+                            semantic_match: None,
+                        });
+                    (ranked.collect(), None)
+                }
+                None => {
+                    // A listing of filtered notes isn't a full-text ranking
+                    let (notes, total) = self.filtered_notes(&filter, limit, offset)?;
+                    page.total = total;
+                    page.results = notes
+                        .into_iter()
+                        .map(|note| note.into_result(None, None, None, explain))
+                        .collect();
+                    (Vec::new(), None)
+                }
+            },
+        };
+
+        page.results
+            .extend(self.page_results(fts, ranked, fusion, explain)?);
+        if window.end < page.total as usize {
+            page.next_cursor = Some(format!("{}.{fingerprint}", window.end));
         }
+        Ok(page)
     }
 
-    fn fulltext_search(&self, query: &str, limit: u32) -> Result<Vec<ScoredNote>, SunderError> {
-        let sanitized = sanitize_fts_query(query);
-        if sanitized.is_empty() {
-            return Ok(Vec::new());
+    /// Results for a page of ranked notes, with snippets and matches.
+    fn page_results(
+        &self,
+        fts: Option<&str>,
+        ranked: Vec<RankedNote>,
+        fusion: Option<FusionOptions>,
+        explain: bool,
+    ) -> Result<Vec<SearchResult>, SunderError> {
+        let fts_ids: Vec<&str> = ranked
+            .iter()
+            .filter(|r| r.fulltext.is_some())
+            .map(|r| r.id.as_str())
+            .collect();
+        let mut fts_notes = match fts {
+            Some(fts) => self.fulltext_details(fts, &fts_ids)?,
+            None => HashMap::new(),
+        };
+        let options = PlainTextOptions::load(&*self.db.get_read_conn()?)?;
+
+        // Notes keep their full-text snippet and matches when both rankings
+        // have them. One deleted since it was ranked is left out.
+        let mut results = Vec::with_capacity(ranked.len());
+        for r in ranked {
+            let note = match (fts_notes.remove(&r.id), r.semantic_match) {
+                (Some(mut note), Some(semantic)) => {
+                    // Full-text hits only in the title leave nothing to jump to
+                    if note.matches.is_empty() {
+                        note.matches = semantic.matches;
+                    }
+                    note.passage = semantic.passage;
+                    note
+                }
+                (Some(note), None) => note,
+                (None, Some(mut semantic)) if r.fulltext.is_none() => {
+                    if let Some(passage) = &semantic.passage {
+                        semantic.snippet = plaintext::snippet(&passage.text, &options);
+                    }
+                    semantic
+                }
+                _ => continue,
+            };
+            results.push(note.into_result(r.fulltext, r.semantic, fusion, explain));
         }
+        Ok(results)
+    }
+
+    /// Number of notes matching the FTS5 expression `fts`.
+    fn fulltext_count(&self, fts: &str, filter: &NoteFilter) -> Result<u32, SunderError> {
+        let conn = self.db.get_read_conn()?;
+        let (clause, values) = filter.sql("n.id", 2);
+        let mut params: Vec<&dyn ToSql> = vec![&fts];
+        params.extend(values.iter().map(|value| value as &dyn ToSql));
+        let count = conn.query_row(
+            &format!(
+                "SELECT COUNT(*)
+                 FROM notes_fts
+                 JOIN notes n ON n.rowid = notes_fts.rowid
+                 WHERE notes_fts MATCH ?1 AND {clause}"
+            ),
+            params.as_slice(),
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
 
+    /// IDs and scores (BM25, made positive) of notes matching the FTS5
+    /// expression `fts`, best first.
+    fn fulltext_ranking(
+        &self,
+        fts: &str,
+        filter: &NoteFilter,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<(String, f64)>, SunderError> {
         let conn = self.db.get_read_conn()?;
-        let mut stmt = conn.prepare(
-            "SELECT n.id, n.title, n.content, bm25(notes_fts) as rank
+        let (clause, values) = filter.sql("n.id", 4);
+        let mut stmt = conn.prepare(&format!(
+            "SELECT n.id, bm25(notes_fts) as rank
              FROM notes_fts
              JOIN notes n ON n.rowid = notes_fts.rowid
-             WHERE notes_fts MATCH ?1
-             ORDER BY rank
-             LIMIT ?2",
-        )?;
+             WHERE notes_fts MATCH ?1 AND {clause}
+             ORDER BY rank, n.id
+             LIMIT ?2 OFFSET ?3"
+        ))?;
+        let mut params: Vec<&dyn ToSql> = vec![&fts, &limit, &offset];
+        params.extend(values.iter().map(|value| value as &dyn ToSql));
+        let ranking = stmt
+            .query_map(params.as_slice(), |row| {
+                Ok((row.get(0)?, row.get::<_, f64>(1)?.abs()))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ranking)
+    }
 
-        let results = stmt
-            .query_map(rusqlite::params![sanitized, limit], |row| {
+    /// The notes `ids` as matches for the FTS5 expression `fts`, with snippets
+    /// and match offsets around the hits.
+    fn fulltext_details(
+        &self,
+        fts: &str,
+        ids: &[&str],
+    ) -> Result<HashMap<String, ScoredNote>, SunderError> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let conn = self.db.get_read_conn()?;
+        let options = PlainTextOptions::load(&conn)?;
+        let placeholders: Vec<String> = (2..ids.len() + 2).map(|i| format!("?{i}")).collect();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT n.id, n.title, n.content, n.fts_content,
+                    bm25(notes_fts),
+                    snippet(notes_fts, 1, '{HIT_OPEN}', '{HIT_CLOSE}', '...', {SNIPPET_TOKENS}),
+                    highlight(notes_fts, 1, '{HIT_OPEN}', '{HIT_CLOSE}')
+             FROM notes_fts
+             JOIN notes n ON n.rowid = notes_fts.rowid
+             WHERE notes_fts MATCH ?1 AND n.id IN ({})",
+            placeholders.join(", ")
+        ))?;
+        let mut params: Vec<&dyn ToSql> = vec![&fts];
+        params.extend(ids.iter().map(|id| id as &dyn ToSql));
+        let notes = stmt
+            .query_map(params.as_slice(), |row| {
                 let content: String = row.get(2)?;
+                let fts_content: Option<String> = row.get(3)?;
+                let (snippet, snippet_highlights) = split_hits(&row.get::<_, String>(5)?, true);
+                let (indexed, hits) = split_hits(&row.get::<_, String>(6)?, false);
+                // Plain-text hits are mapped back to the markdown they came
+                // from; if the index is out of date with the settings, a hit
+                // can't be placed and the note is returned without matches
+                let matches = match fts_content {
+                    None => hits,
+                    Some(_) => {
+                        let rendered = plaintext::render(&content, &options);
+                        if rendered.text == indexed {
+                            source_ranges(&hits, &rendered, &content)
+                        } else {
+                            Vec::new()
+                        }
+                    }
+                };
                 Ok(ScoredNote {
                     id: row.get(0)?,
                     title: row.get(1)?,
-                    snippet: make_snippet(&content),
-                    score: row.get::<_, f64>(3)?.abs(),
+                    snippet,
+                    snippet_highlights,
+                    matches,
+                    score: row.get::<_, f64>(4)?.abs(),
+                    passage: None,
                 })
             })?
             .collect::<Result<Vec<_>, _>>()?;
-
-        Ok(results)
+        Ok(notes
+            .into_iter()
+            .map(|note| (note.id.clone(), note))
+            .collect())
     }
 
-    fn semantic_search(
+    /// A page of the notes the filter lets through, most recently updated
+    /// first, with plain previews, and how many there are.
+    fn filtered_notes(
         &self,
-        query_embedding: &[f32],
+        filter: &NoteFilter,
         limit: u32,
-    ) -> Result<Vec<ScoredNote>, SunderError> {
-        let blob = embedding_to_blob(query_embedding);
+        offset: u32,
+    ) -> Result<(Vec<ScoredNote>, u32), SunderError> {
         let conn = self.db.get_read_conn()?;
+        let options = PlainTextOptions::load(&conn)?;
 
-        let mut stmt = conn.prepare(
-            "SELECT v.note_id, v.distance, n.title, n.content
-             FROM vec_embeddings v
-             JOIN notes n ON n.id = v.note_id
-             WHERE v.embedding MATCH ?1
-             ORDER BY v.distance
-             LIMIT ?2",
+        let (clause, values) = filter.sql("n.id", 1);
+        let params: Vec<&dyn ToSql> = values.iter().map(|value| value as &dyn ToSql).collect();
+        let total = conn.query_row(
+            &format!("SELECT COUNT(*) FROM notes n WHERE {clause}"),
+            params.as_slice(),
+            |row| row.get(0),
         )?;
 
-        let results = stmt
-            .query_map(rusqlite::params![blob, limit], |row| {
-                let content: String = row.get(3)?;
-                let distance: f64 = row.get(1)?;
+        let (clause, values) = filter.sql("n.id", 3);
+        let mut stmt = conn.prepare(&format!(
+            "SELECT n.id, n.title, n.content
+             FROM notes n
+             WHERE {clause}
+             ORDER BY n.updated_at DESC, n.id
+             LIMIT ?1 OFFSET ?2"
+        ))?;
+        let mut params: Vec<&dyn ToSql> = vec![&limit, &offset];
+        params.extend(values.iter().map(|value| value as &dyn ToSql));
+        let notes = stmt
+            .query_map(params.as_slice(), |row| {
+                let content: String = row.get(2)?;
                 Ok(ScoredNote {
                     id: row.get(0)?,
-                    title: row.get(2)?,
-                    snippet: make_snippet(&content),
-                    score: 1.0 - distance, // Convert distance to similarity
+                    title: row.get(1)?,
+                    snippet: plaintext::snippet(&content, &options),
+                    snippet_highlights: Vec::new(),
+                    matches: Vec::new(),
+                    score: 0.0,
+                    passage: None,
                 })
             })?
             .collect::<Result<Vec<_>, _>>()?;
 
-        Ok(results)
+        Ok((notes, total))
     }
 
-    fn hybrid_search(
+    /// Notes nearest in meaning to `text` and at least `min_similarity` alike,
+    /// best first, up to `RANKING_DEPTH`. Snippets are left for `page_results`
+    /// to make.
+    fn semantic_ranking(
         &self,
-        query: &str,
-        limit: u32,
-    ) -> Result<Vec<SearchResult>, SunderError> {
-        let fts_results = self.fulltext_search(query, limit * 2)?;
-        let embedding = self.embedding_service.embed_text(query)?;
-        let sem_results = self.semantic_search(&embedding, limit * 2)?;
-
-        // Reciprocal Rank Fusion (RRF) with k=60
-        let k = 60.0;
-        let mut rrf_scores: HashMap<String, (f64, String, String, String)> = HashMap::new();
-        // Track which result sets each ID appeared in
-        let mut fts_ids: std::collections::HashSet<String> = std::collections::HashSet::new();
-        let mut sem_ids: std::collections::HashSet<String> = std::collections::HashSet::new();
-
-        for (rank, result) in fts_results.iter().enumerate() {
-            let rrf_score = 1.0 / (k + rank as f64 + 1.0);
-            fts_ids.insert(result.id.clone());
-            rrf_scores
-                .entry(result.id.clone())
-                .and_modify(|(score, _, _, _)| *score += rrf_score)
-                .or_insert((
-                    rrf_score,
-                    result.title.clone(),
-                    result.snippet.clone(),
-                    "fulltext".to_string(),
-                ));
-        }
-
-        for (rank, result) in sem_results.iter().enumerate() {
-            let rrf_score = 1.0 / (k + rank as f64 + 1.0);
-            sem_ids.insert(result.id.clone());
-            rrf_scores
-                .entry(result.id.clone())
-                .and_modify(|(score, _, _, match_type)| {
-                    *score += rrf_score;
-                    if match_type == "fulltext" {
-                        *match_type = "both".to_string();
-                    }
-                })
-                .or_insert((
-                    rrf_score,
-                    result.title.clone(),
-                    result.snippet.clone(),
-                    "semantic".to_string(),
-                ));
-        }
+        text: &str,
+        filter: &NoteFilter,
+        aggregation: ScoreAggregation,
+        min_similarity: f64,
+    ) -> Result<Vec<ScoredNote>, SunderError> {
+        let embedding_service = self.embedding.get()?;
+        let embedding = embedding_service.embed_query(text)?;
+        let blob = embedding_to_blob(&embedding);
+        let conn = self.db.get_read_conn()?;
+        let matches = passage::match_notes(
+            &conn,
+            &blob,
+            embedding_service.model_version(),
+            RANKING_DEPTH,
+            Some(filter),
+            None,
+            aggregation,
+        )?;
 
-        let mut combined: Vec<SearchResult> = rrf_scores
+        Ok(matches
             .into_iter()
-            .map(|(id, (score, title, snippet, match_type))| SearchResult {
-                id,
-                title,
-                snippet,
-                score,
-                match_type,
+            .filter(|m| m.score >= min_similarity)
+            .map(|m| ScoredNote {
+                snippet: String::new(),
+                snippet_highlights: Vec::new(),
+                matches: vec![TextRange {
+                    start: m.passage.start,
+                    end: m.passage.end,
+                }],
+                id: m.id,
+                title: m.title,
+                score: m.score,
+                passage: Some(m.passage),
             })
-            .collect();
+            .collect())
+    }
+}
 
-        combined.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
-        combined.truncate(limit as usize);
+/// Full-text and semantic rankings fused as `fusion` says, best first, ties
+/// broken by ID so pages are stable.
+fn fuse(
+    fts_ranking: Vec<(String, f64)>,
+    sem_results: Vec<ScoredNote>,
+    fusion: &FusionOptions,
+) -> Vec<RankedNote> {
+    let scores: Vec<f64> = fts_ranking.iter().map(|(_, score)| *score).collect();
+    let fts_signals = signal_scores(
+        &scores,
+        &fusion.normalize(&scores),
+        fusion.fulltext_weight,
+        1,
+    );
+    let scores: Vec<f64> = sem_results.iter().map(|r| r.score).collect();
+    let sem_signals = signal_scores(
+        &scores,
+        &fusion.normalize(&scores),
+        fusion.semantic_weight,
+        1,
+    );
 
-        Ok(combined)
+    let mut fused: HashMap<String, RankedNote> = HashMap::new();
+    for ((id, _), signal) in fts_ranking.into_iter().zip(fts_signals) {
+        fused.insert(
+            id.clone(),
+            RankedNote {
+                id,
+                score: signal.contribution,
+                fulltext: Some(signal),
+                semantic: None,
+                semantic_match: None,
+            },
+        );
+    }
+    for (note, signal) in sem_results.into_iter().zip(sem_signals) {
+        let ranked = fused.entry(note.id.clone()).or_insert_with(|| RankedNote {
+            id: note.id.clone(),
+            score: 0.0,
+            fulltext: None,
+            semantic: None,
+            semantic_match: None,
+        });
+        ranked.score += signal.contribution;
+        ranked.semantic = Some(signal);
+        ranked.semantic_match = Some(note);
     }
-}
 
-/// Sanitize FTS5 query: escape special characters, wrap words in quotes.
-fn sanitize_fts_query(query: &str) -> String {
-    query
-        .split_whitespace()
-        .filter(|word| {
-            let upper = word.to_uppercase();
-            !matches!(
-                upper.as_str(),
-                "OR" | "AND" | "NOT" | "NEAR"
-            ) && !word.contains('*')
-                && !word.contains(':')
-        })
-        .map(|word| {
-            let escaped = word.replace('"', "");
-            format!("\"{escaped}\"")
-        })
-        .collect::<Vec<_>>()
-        .join(" ")
+    let mut ranked: Vec<RankedNote> = fused.into_values().collect();
+    ranked.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    ranked
 }
 
-fn make_snippet(content: &str) -> String {
-    let stripped: String = content
-        .chars()
-        .take(250)
-        .collect::<String>()
-        .lines()
-        .map(|line| {
-            line.trim()
-                .trim_start_matches('#')
-                .trim()
-                .replace("**", "")
-                .replace('*', "")
-        })
-        .collect::<Vec<_>>()
-        .join(" ");
+fn take_window<T>(items: Vec<T>, window: &Range<usize>) -> Vec<T> {
+    items
+        .into_iter()
+        .skip(window.start)
+        .take(window.len())
+        .collect()
+}
 
-    if stripped.len() > 200 {
-        format!("{}...", &stripped[..200])
-    } else {
-        stripped
+/// Strip FTS5 hit marks from `marked`, returning the text and where the hits
+/// are in it. With `collapse`, runs of whitespace become single spaces and the
+/// ends are trimmed, for a one-line snippet.
+fn split_hits(marked: &str, collapse: bool) -> (String, Vec<TextRange>) {
+    let mut text = String::with_capacity(marked.len());
+    let mut hits = Vec::new();
+    let mut len = 0u32;
+    let mut start = None;
+    let mut pending_space = false;
+    for c in marked.chars() {
+        if collapse && c.is_whitespace() {
+            pending_space = true;
+            continue;
+        }
+        if pending_space && !text.is_empty() && c != HIT_CLOSE {
+            text.push(' ');
+            len += 1;
+        }
+        pending_space = pending_space && c == HIT_CLOSE;
+        match c {
+            HIT_OPEN => start = Some(len),
+            HIT_CLOSE => {
+                if let Some(start) = start.take() {
+                    hits.push(TextRange { start, end: len });
+                }
+            }
+            c => {
+                text.push(c);
+                len += 1;
+            }
+        }
     }
+    (text, hits)
+}
+
+/// Map character ranges in `rendered.text` to character ranges in the
+/// markdown it was rendered from.
+fn source_ranges(ranges: &[TextRange], rendered: &PlainText, markdown: &str) -> Vec<TextRange> {
+    let plain_bytes: Vec<usize> = rendered
+        .text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(rendered.text.len()))
+        .collect();
+    let source_char = |byte: usize| {
+        let mut byte = byte.min(markdown.len());
+        while !markdown.is_char_boundary(byte) {
+            byte -= 1;
+        }
+        markdown[..byte].chars().count() as u32
+    };
+    ranges
+        .iter()
+        .filter_map(|range| {
+            let start = *plain_bytes.get(range.start as usize)?;
+            let end = *plain_bytes.get(range.end as usize)?;
+            Some(TextRange {
+                start: source_char(rendered.source_offset(start)),
+                end: source_char(rendered.source_end(end)),
+            })
+        })
+        .collect()
 }
@@ -1,5 +1,10 @@
 use crate::db::DatabaseManager;
 use crate::error::SunderError;
+use crate::services::chunking::ChunkingPolicy;
+use crate::services::embedding::MAX_EMBEDDING_SESSIONS;
+use crate::services::plaintext::{CodeBlockPolicy, LinkPolicy};
+use crate::services::search::FusionMethod;
+use rusqlite::{Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
@@ -9,6 +14,24 @@ pub struct Settings {
     pub similarity_threshold: f64,
     pub debounce_ms: u32,
     pub theme: String,
+    pub trash_retention_days: u32,
+    pub revision_max_per_note: u32,
+    pub revision_max_age_days: u32,
+    pub chunking_policy: ChunkingPolicy,
+    pub plain_text_code_blocks: CodeBlockPolicy,
+    pub plain_text_links: LinkPolicy,
+    pub fts_plain_text: bool,
+    /// Model sessions bulk indexing runs in parallel; takes effect on restart.
+    pub embedding_sessions: u32,
+    /// Directory of a model loaded in place of the bundled one.
+    pub model_directory: Option<String>,
+    /// How hybrid search combines full-text and semantic rankings.
+    pub search_fusion: FusionMethod,
+    pub search_rrf_k: f64,
+    pub search_fulltext_weight: f64,
+    pub search_semantic_weight: f64,
+    /// Semantic matches less similar than this are left out of search results.
+    pub search_min_similarity: f64,
 }
 
 #[derive(Debug, Deserialize)]
@@ -16,6 +39,35 @@ pub struct SettingsPatch {
     pub similarity_threshold: Option<f64>,
     pub debounce_ms: Option<u32>,
     pub theme: Option<String>,
+    pub trash_retention_days: Option<u32>,
+    pub revision_max_per_note: Option<u32>,
+    pub revision_max_age_days: Option<u32>,
+    pub chunking_policy: Option<ChunkingPolicy>,
+    pub plain_text_code_blocks: Option<CodeBlockPolicy>,
+    pub plain_text_links: Option<LinkPolicy>,
+    pub fts_plain_text: Option<bool>,
+    pub embedding_sessions: Option<u32>,
+    pub search_fusion: Option<FusionMethod>,
+    pub search_rrf_k: Option<f64>,
+    pub search_fulltext_weight: Option<f64>,
+    pub search_semantic_weight: Option<f64>,
+    pub search_min_similarity: Option<f64>,
+}
+
+impl SettingsPatch {
+    /// Whether the patch changes how note text is chunked or cleaned for embedding.
+    pub fn affects_embeddings(&self) -> bool {
+        self.chunking_policy.is_some()
+            || self.plain_text_code_blocks.is_some()
+            || self.plain_text_links.is_some()
+    }
+
+    /// Whether the patch changes the text the full-text index holds.
+    pub fn affects_fts(&self) -> bool {
+        self.fts_plain_text.is_some()
+            || self.plain_text_code_blocks.is_some()
+            || self.plain_text_links.is_some()
+    }
 }
 
 pub struct SettingsService {
@@ -31,22 +83,15 @@ impl SettingsService {
         let conn = self.db.get_read_conn()?;
 
         let get_value = |key: &str, default: &str| -> String {
-            conn.query_row(
-                "SELECT value FROM settings WHERE key = ?1",
-                [key],
-                |row| row.get::<_, String>(0),
-            )
-            .unwrap_or_else(|_| default.to_string())
+            setting(&conn, key)
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| default.to_string())
         };
 
-        let watch_directory = {
-            let v = get_value("watch_directory", "");
-            if v.is_empty() {
-                None
-            } else {
-                Some(v)
-            }
-        };
+        let optional = |key: &str| Some(get_value(key, "")).filter(|v| !v.is_empty());
+
+        let watch_directory = optional("watch_directory");
 
         let similarity_threshold: f64 = get_value("similarity_threshold", "0.65")
             .parse()
@@ -58,11 +103,71 @@ impl SettingsService {
 
         let theme = get_value("theme", "dark");
 
+        let trash_retention_days: u32 = get_value("trash_retention_days", "30")
+            .parse()
+            .unwrap_or(30);
+
+        let revision_max_per_note: u32 = get_value("revision_max_per_note", "50")
+            .parse()
+            .unwrap_or(50);
+
+        let revision_max_age_days: u32 = get_value("revision_max_age_days", "90")
+            .parse()
+            .unwrap_or(90);
+
+        let chunking_policy =
+            ChunkingPolicy::parse(&get_value("chunking_policy", "markdown")).unwrap_or_default();
+
+        let plain_text_code_blocks =
+            CodeBlockPolicy::parse(&get_value("plain_text_code_blocks", "drop")).unwrap_or_default();
+
+        let plain_text_links =
+            LinkPolicy::parse(&get_value("plain_text_links", "text")).unwrap_or_default();
+
+        let fts_plain_text = get_value("fts_plain_text", "false") == "true";
+
+        let embedding_sessions: u32 = get_value("embedding_sessions", "1")
+            .parse()
+            .unwrap_or(1);
+
+        let model_directory = optional("model_directory");
+
+        let search_fusion =
+            FusionMethod::parse(&get_value("search_fusion", "rrf")).unwrap_or_default();
+
+        let search_rrf_k: f64 = get_value("search_rrf_k", "60").parse().unwrap_or(60.0);
+
+        let search_fulltext_weight: f64 = get_value("search_fulltext_weight", "1")
+            .parse()
+            .unwrap_or(1.0);
+
+        let search_semantic_weight: f64 = get_value("search_semantic_weight", "1")
+            .parse()
+            .unwrap_or(1.0);
+
+        let search_min_similarity: f64 = get_value("search_min_similarity", "0.2")
+            .parse()
+            .unwrap_or(0.2);
+
         Ok(Settings {
             watch_directory,
             similarity_threshold,
             debounce_ms,
             theme,
+            trash_retention_days,
+            revision_max_per_note,
+            revision_max_age_days,
+            chunking_policy,
+            plain_text_code_blocks,
+            plain_text_links,
+            fts_plain_text,
+            embedding_sessions,
+            model_directory,
+            search_fusion,
+            search_rrf_k,
+            search_fulltext_weight,
+            search_semantic_weight,
+            search_min_similarity,
         })
     }
 
@@ -105,6 +210,166 @@ impl SettingsService {
             )?;
         }
 
+        if let Some(days) = patch.trash_retention_days {
+            // 0 keeps trashed notes until the trash is emptied manually
+            if days > 3650 {
+                return Err(SunderError::InvalidValue(
+                    "trash_retention_days must be between 0 and 3650".to_string(),
+                ));
+            }
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('trash_retention_days', ?1)",
+                [days.to_string()],
+            )?;
+        }
+
+        // For both revision limits, 0 means unlimited
+        if let Some(max) = patch.revision_max_per_note {
+            if max > 10_000 {
+                return Err(SunderError::InvalidValue(
+                    "revision_max_per_note must be between 0 and 10000".to_string(),
+                ));
+            }
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('revision_max_per_note', ?1)",
+                [max.to_string()],
+            )?;
+        }
+
+        if let Some(days) = patch.revision_max_age_days {
+            if days > 3650 {
+                return Err(SunderError::InvalidValue(
+                    "revision_max_age_days must be between 0 and 3650".to_string(),
+                ));
+            }
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('revision_max_age_days', ?1)",
+                [days.to_string()],
+            )?;
+        }
+
+        if let Some(policy) = patch.chunking_policy {
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('chunking_policy', ?1)",
+                [policy.as_str()],
+            )?;
+        }
+
+        if let Some(policy) = patch.plain_text_code_blocks {
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('plain_text_code_blocks', ?1)",
+                [policy.as_str()],
+            )?;
+        }
+
+        if let Some(policy) = patch.plain_text_links {
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('plain_text_links', ?1)",
+                [policy.as_str()],
+            )?;
+        }
+
+        if let Some(enabled) = patch.fts_plain_text {
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('fts_plain_text', ?1)",
+                [enabled.to_string()],
+            )?;
+        }
+
+        if let Some(sessions) = patch.embedding_sessions {
+            if !(1..=MAX_EMBEDDING_SESSIONS as u32).contains(&sessions) {
+                return Err(SunderError::InvalidValue(
+                    "embedding_sessions must be between 1 and 16".to_string(),
+                ));
+            }
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('embedding_sessions', ?1)",
+                [sessions.to_string()],
+            )?;
+        }
+
+        if let Some(method) = patch.search_fusion {
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('search_fusion', ?1)",
+                [method.as_str()],
+            )?;
+        }
+
+        if let Some(k) = patch.search_rrf_k {
+            if !(1.0..=1000.0).contains(&k) {
+                return Err(SunderError::InvalidValue(
+                    "search_rrf_k must be between 1 and 1000".to_string(),
+                ));
+            }
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('search_rrf_k', ?1)",
+                [k.to_string()],
+            )?;
+        }
+
+        for (key, weight) in [
+            ("search_fulltext_weight", patch.search_fulltext_weight),
+            ("search_semantic_weight", patch.search_semantic_weight),
+        ] {
+            let Some(weight) = weight else { continue };
+            if !(0.0..=10.0).contains(&weight) {
+                return Err(SunderError::InvalidValue(format!(
+                    "{key} must be between 0.0 and 10.0"
+                )));
+            }
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+                [key.to_string(), weight.to_string()],
+            )?;
+        }
+
+        if let Some(similarity) = patch.search_min_similarity {
+            if !(-1.0..=1.0).contains(&similarity) {
+                return Err(SunderError::InvalidValue(
+                    "search_min_similarity must be between -1.0 and 1.0".to_string(),
+                ));
+            }
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('search_min_similarity', ?1)",
+                [similarity.to_string()],
+            )?;
+        }
+
         Ok(())
     }
+
+    /// Persist (or clear) the active vault directory.
+    pub fn set_watch_directory(&self, directory: Option<&str>) -> Result<(), SunderError> {
+        let conn = self.db.get_write_conn()?;
+        match directory {
+            Some(dir) => conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('watch_directory', ?1)",
+                [dir],
+            )?,
+            None => conn.execute("DELETE FROM settings WHERE key = 'watch_directory'", [])?,
+        };
+        Ok(())
+    }
+
+    /// Persist (or clear) the directory of a user-chosen embedding model.
+    pub fn set_model_directory(&self, directory: Option<&str>) -> Result<(), SunderError> {
+        let conn = self.db.get_write_conn()?;
+        match directory {
+            Some(dir) => conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('model_directory', ?1)",
+                [dir],
+            )?,
+            None => conn.execute("DELETE FROM settings WHERE key = 'model_directory'", [])?,
+        };
+        Ok(())
+    }
+}
+
+/// The stored value of a setting, or `None` if it was never set.
+pub(crate) fn setting(conn: &Connection, key: &str) -> Result<Option<String>, SunderError> {
+    Ok(conn
+        .query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| {
+            row.get(0)
+        })
+        .optional()?)
 }
@@ -0,0 +1,356 @@
+use crate::db::DatabaseManager;
+use crate::error::SunderError;
+use crate::services::markdown;
+use crate::services::note::NoteService;
+use crate::services::revision::RevisionSource;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+#[derive(Debug, Serialize)]
+pub struct TagInfo {
+    pub name: String,
+    /// Parent in the tag hierarchy, e.g. `project` for `project/alpha`.
+    pub parent: Option<String>,
+    /// Notes tagged with exactly this tag.
+    pub count: u32,
+    /// Notes tagged with this tag or any tag nested below it.
+    pub total_count: u32,
+}
+
+pub struct TagService {
+    db: Arc<DatabaseManager>,
+}
+
+impl TagService {
+    pub fn new(db: Arc<DatabaseManager>) -> Self {
+        Self { db }
+    }
+
+    /// All tags with note counts. Parents of nested tags are included even when
+    /// no note carries them directly, so the list always forms a complete tree.
+    pub fn list_tags(&self) -> Result<Vec<TagInfo>, SunderError> {
+        let conn = self.db.get_read_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT t.name, nt.note_id FROM tags t JOIN note_tags nt ON nt.tag_id = t.id",
+        )?;
+        let pairs: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Keyed by lowercase name so counts merge case-insensitively
+        let mut direct: BTreeMap<String, (String, HashSet<String>)> = BTreeMap::new();
+        let mut subtree: BTreeMap<String, (String, HashSet<String>)> = BTreeMap::new();
+
+        for (name, note_id) in &pairs {
+            direct
+                .entry(name.to_lowercase())
+                .or_insert_with(|| (name.clone(), HashSet::new()))
+                .1
+                .insert(note_id.clone());
+
+            for ancestor in ancestors_and_self(name) {
+                subtree
+                    .entry(ancestor.to_lowercase())
+                    .or_insert_with(|| (ancestor.to_string(), HashSet::new()))
+                    .1
+                    .insert(note_id.clone());
+            }
+        }
+
+        Ok(subtree
+            .into_iter()
+            .map(|(key, (name, notes))| TagInfo {
+                parent: name.rsplit_once('/').map(|(parent, _)| parent.to_string()),
+                count: direct.get(&key).map_or(0, |(_, d)| d.len() as u32),
+                total_count: notes.len() as u32,
+                name,
+            })
+            .collect())
+    }
+
+    /// Rename a tag (and every tag nested below it) across all notes. Renaming
+    /// onto an existing tag merges the two. Notes are rewritten, including their
+    /// files, so the change survives the next import; if any can't be, none
+    /// are. Returns the ids of the notes changed.
+    pub fn rename_tag(&self, from: &str, to: &str) -> Result<Vec<String>, SunderError> {
+        let from = normalize_tag(from);
+        let to = normalize_tag(to);
+        if !is_valid_tag(&from) || !is_valid_tag(&to) {
+            return Err(SunderError::ValidationError(format!(
+                "Invalid tag name: {}",
+                if is_valid_tag(&from) { &to } else { &from }
+            )));
+        }
+        if from == to {
+            return Ok(Vec::new());
+        }
+
+        let note_ids: Vec<String> = {
+            let conn = self.db.get_read_conn()?;
+            let mut stmt = conn.prepare(&format!(
+                "SELECT DISTINCT nt.note_id FROM note_tags nt WHERE {}",
+                tag_filter_clause("nt.note_id", 1)
+            ))?;
+            let ids = stmt
+                .query_map([&from], |row| row.get(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            ids
+        };
+        if note_ids.is_empty() {
+            return Err(SunderError::NotFound(format!("Tag not found: {from}")));
+        }
+
+        // Every rewrite is worked out before anything is written
+        let note_service = NoteService::new(Arc::clone(&self.db));
+        let mut updates = Vec::new();
+        for id in note_ids {
+            let note = note_service.get_note(&id)?;
+            let content = rewrite_inline_tags(&note.content, &from, &to);
+            let metadata = note
+                .metadata
+                .as_ref()
+                .map(|m| rewrite_front_matter_tags(m, &from, &to));
+
+            if content == note.content && metadata == note.metadata {
+                continue;
+            }
+            updates.push((note, content, metadata));
+        }
+        let changed: Vec<String> = note_service
+            .update_notes_and_metadata(updates, RevisionSource::Edit)?
+            .into_iter()
+            .map(|note| note.id)
+            .collect();
+
+        tracing::info!("Renamed tag #{from} to #{to} in {} note(s)", changed.len());
+        Ok(changed)
+    }
+}
+
+/// SQL condition matching notes tagged with the tag bound at `?{param}` or any
+/// tag nested below it. `note_id_expr` is the note id column to filter.
+pub fn tag_filter_clause(note_id_expr: &str, param: usize) -> String {
+    format!(
+        "{note_id_expr} IN (
+            SELECT nt.note_id FROM note_tags nt JOIN tags t ON t.id = nt.tag_id
+            WHERE t.name = ?{param}
+               OR substr(t.name, 1, length(?{param}) + 1) = ?{param} || '/' COLLATE NOCASE
+        )"
+    )
+}
+
+/// Replace a note's tags with those found in its front matter and body.
+pub fn sync_note_tags(
+    conn: &Connection,
+    note_id: &str,
+    metadata: Option<&serde_json::Value>,
+    content: &str,
+) -> Result<(), SunderError> {
+    conn.execute("DELETE FROM note_tags WHERE note_id = ?1", [note_id])?;
+
+    let mut seen = HashSet::new();
+    let front_matter = metadata.map(front_matter_tags).unwrap_or_default();
+    let inline = inline_tag_spans(content)
+        .into_iter()
+        .map(|(start, end)| normalize_tag(&content[start..end]));
+
+    let tagged = front_matter
+        .into_iter()
+        .map(|tag| (tag, "front_matter"))
+        .chain(inline.map(|tag| (tag, "inline")));
+
+    for (tag, source) in tagged {
+        if !is_valid_tag(&tag) || !seen.insert(tag.to_lowercase()) {
+            continue;
+        }
+        conn.execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", [&tag])?;
+        conn.execute(
+            "INSERT OR IGNORE INTO note_tags (note_id, tag_id, source)
+             SELECT ?1, id, ?2 FROM tags WHERE name = ?3",
+            rusqlite::params![note_id, source, tag],
+        )?;
+    }
+
+    conn.execute(
+        "DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM note_tags)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Tags declared in front matter `tags:` (or `tag:`), as a list or a
+/// comma/space separated string.
+fn front_matter_tags(metadata: &serde_json::Value) -> Vec<String> {
+    let Some(value) = metadata.get("tags").or_else(|| metadata.get("tag")) else {
+        return Vec::new();
+    };
+
+    let raw: Vec<String> = match value {
+        serde_json::Value::Array(items) => items
+            .iter()
+            .filter_map(|item| match item {
+                serde_json::Value::String(s) => Some(s.clone()),
+                serde_json::Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+            .collect(),
+        serde_json::Value::String(s) => split_tag_string(s).map(str::to_string).collect(),
+        _ => Vec::new(),
+    };
+
+    raw.iter().map(|t| normalize_tag(t)).collect()
+}
+
+fn split_tag_string(s: &str) -> impl Iterator<Item = &str> {
+    s.split([',', ' ']).filter(|t| !t.trim().is_empty())
+}
+
+/// Byte ranges of inline `#tag` tokens (without the `#`). Code blocks, inline
+/// code spans, heading lines and link destinations (`[setup](#setup)`) are
+/// skipped.
+pub fn inline_tag_spans(content: &str) -> Vec<(usize, usize)> {
+    let code = markdown::code_ranges(content);
+    let destinations: Vec<_> = markdown::inline_links(content)
+        .into_iter()
+        .map(|link| link.range)
+        .collect();
+    let mut spans = Vec::new();
+    let mut line_start = 0;
+
+    for line in content.split_inclusive('\n') {
+        let offset = line_start;
+        line_start += line.len();
+        if is_heading(line.trim_start()) {
+            continue;
+        }
+
+        let mut prev: Option<char> = None;
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if c == '#'
+                && !markdown::in_ranges(&code, offset + i)
+                && !markdown::in_ranges(&destinations, offset + i)
+                && prev.is_none_or(|p| p.is_whitespace() || "([{,;\"'".contains(p))
+            {
+                let start = i + 1;
+                let mut end = start;
+                while let Some(&(j, next)) = chars.peek() {
+                    if !is_tag_char(next) {
+                        break;
+                    }
+                    end = j + next.len_utf8();
+                    chars.next();
+                }
+                let tag = line[start..end].trim_end_matches('/');
+                if is_valid_tag(tag) {
+                    spans.push((offset + start, offset + start + tag.len()));
+                }
+                prev = line[..end].chars().next_back();
+                continue;
+            }
+            prev = Some(c);
+        }
+    }
+
+    spans
+}
+
+/// ATX heading: 1-6 `#` followed by a space or end of line.
+fn is_heading(trimmed_line: &str) -> bool {
+    let hashes = trimmed_line.chars().take_while(|&c| c == '#').count();
+    (1..=6).contains(&hashes)
+        && trimmed_line[hashes..]
+            .chars()
+            .next()
+            .is_none_or(|c| c == ' ' || c == '\t' || c == '\n' || c == '\r')
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '/'
+}
+
+/// Tags need at least one non-digit character so `#1` or `#2024` are not tags.
+fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.chars().all(is_tag_char)
+        && tag.chars().any(|c| !c.is_ascii_digit() && c != '/')
+        && !tag.split('/').any(str::is_empty)
+}
+
+/// Strip surrounding whitespace, a leading `#` and stray `/` from a tag name.
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().trim_start_matches('#').trim_matches('/').to_string()
+}
+
+/// `tag` itself followed by each of its parents, e.g. `a/b/c`, `a/b`, `a`.
+fn ancestors_and_self(tag: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(tag), |t| t.rsplit_once('/').map(|(parent, _)| parent))
+}
+
+/// The new name for `tag` when renaming `from` to `to`, if it is affected.
+fn renamed(tag: &str, from: &str, to: &str) -> Option<String> {
+    // Compared component by component: lowercasing can change a component's
+    // byte length, so `from.len()` isn't where the rest of `tag` starts
+    let depth = from.split('/').count();
+    let components: Vec<&str> = tag.splitn(depth + 1, '/').collect();
+    if components.len() < depth
+        || !components
+            .iter()
+            .zip(from.split('/'))
+            .all(|(a, b)| a.to_lowercase() == b.to_lowercase())
+    {
+        return None;
+    }
+    let matched = components[..depth].iter().map(|c| c.len()).sum::<usize>() + depth - 1;
+    Some(format!("{to}{}", &tag[matched..]))
+}
+
+fn rewrite_inline_tags(content: &str, from: &str, to: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut last = 0;
+    for (start, end) in inline_tag_spans(content) {
+        if let Some(new_tag) = renamed(&content[start..end], from, to) {
+            out.push_str(&content[last..start]);
+            out.push_str(&new_tag);
+            last = end;
+        }
+    }
+    out.push_str(&content[last..]);
+    out
+}
+
+fn rewrite_front_matter_tags(metadata: &serde_json::Value, from: &str, to: &str) -> serde_json::Value {
+    let mut metadata = metadata.clone();
+    let key = if metadata.get("tags").is_some() { "tags" } else { "tag" };
+    let Some(value) = metadata.get_mut(key) else {
+        return metadata;
+    };
+
+    let rename = |t: &str| {
+        let hash = if t.starts_with('#') { "#" } else { "" };
+        renamed(&normalize_tag(t), from, to)
+            .map(|new_tag| format!("{hash}{new_tag}"))
+            .unwrap_or_else(|| t.to_string())
+    };
+
+    match value {
+        serde_json::Value::Array(items) => {
+            for item in items.iter_mut() {
+                if let serde_json::Value::String(s) = item {
+                    *s = rename(s);
+                }
+            }
+        }
+        serde_json::Value::String(s) => {
+            let separator = if s.contains(',') { ", " } else { " " };
+            *s = split_tag_string(s)
+                .map(|t| rename(t.trim()))
+                .collect::<Vec<_>>()
+                .join(separator);
+        }
+        _ => {}
+    }
+    metadata
+}
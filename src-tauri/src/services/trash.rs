@@ -0,0 +1,389 @@
+use crate::db::DatabaseManager;
+use crate::error::SunderError;
+use crate::services::embedding;
+use crate::services::note::{metadata_to_sql, sync_derived, Note, NoteService};
+use crate::services::settings::SettingsService;
+use crate::services::vault;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug, Serialize)]
+pub struct TrashedNote {
+    pub id: String,
+    pub title: String,
+    pub file_path: Option<String>,
+    pub word_count: u32,
+    pub deleted_at: String,
+}
+
+/// A trashed note row, including the data needed to restore it.
+struct DeletedNote {
+    id: String,
+    title: String,
+    content: String,
+    file_path: Option<String>,
+    trash_path: Option<String>,
+    word_count: u32,
+    created_at: String,
+    updated_at: String,
+    metadata: Option<serde_json::Value>,
+    embedding: Option<Vec<u8>>,
+    model_version: Option<String>,
+    content_hash: Option<String>,
+}
+
+pub struct TrashService {
+    db: Arc<DatabaseManager>,
+}
+
+impl TrashService {
+    pub fn new(db: Arc<DatabaseManager>) -> Self {
+        Self { db }
+    }
+
+    /// Move a note to the trash. Its file (if still on disk) is moved into the
+    /// trash directory and its row and embedding are kept in `deleted_notes`,
+    /// its passage chunks in `deleted_note_chunks`.
+    pub fn trash_note(&self, id: &str) -> Result<(), SunderError> {
+        let note = NoteService::new(Arc::clone(&self.db)).get_note(id)?;
+
+        let moved = match &note.file_path {
+            Some(path) if Path::new(path).exists() => {
+                let from = PathBuf::from(path);
+                let to = self.trash_dir_for(&from)?.join(format!("{}.md", note.id));
+                vault::move_file(&from, &to)?;
+                Some((from, to))
+            }
+            _ => None,
+        };
+
+        let result = self.record_deletion(&note, moved.as_ref().map(|(_, to)| to.as_path()));
+        if result.is_err() {
+            // Put the file back so the note and its file stay together
+            if let Some((from, to)) = &moved {
+                let _ = vault::move_file(to, from);
+            }
+        }
+        result
+    }
+
+    fn record_deletion(&self, note: &Note, trash_path: Option<&Path>) -> Result<(), SunderError> {
+        let now = chrono::Utc::now().to_rfc3339();
+        let trash_path = trash_path.map(|p| p.to_string_lossy().to_string());
+
+        let mut conn = self.db.get_write_conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO deleted_notes
+                (id, title, content, file_path, trash_path, word_count, created_at, updated_at,
+                 deleted_at, metadata, embedding, model_version, content_hash)
+             SELECT n.id, n.title, n.content, n.file_path, ?2, n.word_count, n.created_at,
+                    n.updated_at, ?3, n.metadata, e.vector, e.model_version, e.content_hash
+             FROM notes n
+             LEFT JOIN embeddings e ON e.note_id = n.id
+             WHERE n.id = ?1",
+            rusqlite::params![note.id, trash_path, now],
+        )?;
+        tx.execute("DELETE FROM deleted_note_chunks WHERE note_id = ?1", [&note.id])?;
+        tx.execute(
+            "INSERT INTO deleted_note_chunks
+                (note_id, chunk_index, start_offset, end_offset, vector, model_version, chunking)
+             SELECT note_id, chunk_index, start_offset, end_offset, vector, model_version, chunking
+             FROM note_chunks WHERE note_id = ?1",
+            [&note.id],
+        )?;
+        tx.execute("DELETE FROM vec_embeddings WHERE note_id = ?1", [&note.id])?;
+        embedding::delete_chunks(&tx, &note.id)?;
+        tx.execute(
+            "DELETE FROM similarity_cache WHERE note_id_a = ?1 OR note_id_b = ?1",
+            [&note.id],
+        )?;
+        tx.execute("DELETE FROM notes WHERE id = ?1", [&note.id])?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    pub fn list_trash(&self) -> Result<Vec<TrashedNote>, SunderError> {
+        let conn = self.db.get_read_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, word_count, deleted_at
+             FROM deleted_notes ORDER BY deleted_at DESC",
+        )?;
+        let notes = stmt
+            .query_map([], |row| {
+                Ok(TrashedNote {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    file_path: row.get(2)?,
+                    word_count: row.get(3)?,
+                    deleted_at: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(notes)
+    }
+
+    /// Restore a trashed note with its original id, embedding and passage
+    /// chunks, unless they came from another model. The file goes back to its
+    /// original path, or a free name next to it if that is taken.
+    pub fn restore_note(&self, id: &str) -> Result<Note, SunderError> {
+        let deleted = self.get_deleted(id)?;
+        let target = self.restore_target(&deleted)?;
+        let trashed = deleted
+            .trash_path
+            .as_deref()
+            .map(PathBuf::from)
+            .filter(|trashed| trashed.exists());
+        if let (Some(target), Some(trashed)) = (&target, &trashed) {
+            vault::move_file(trashed, target)?;
+        }
+
+        let result = self.record_restore(deleted, target.as_deref());
+        if result.is_err() {
+            // Take the file out of the vault again so it isn't imported as a new note
+            if let Some(target) = &target {
+                match &trashed {
+                    Some(trashed) => {
+                        let _ = vault::move_file(target, trashed);
+                    }
+                    None => {
+                        let _ = std::fs::remove_file(target);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Where a restored note's file goes: its original path, or a free name
+    /// next to it if that is taken. `None` for notes without a file.
+    fn restore_target(&self, deleted: &DeletedNote) -> Result<Option<PathBuf>, SunderError> {
+        let Some(original) = &deleted.file_path else {
+            return Ok(None);
+        };
+        let note_service = NoteService::new(Arc::clone(&self.db));
+        let original = PathBuf::from(original);
+        let target = if original.exists()
+            || note_service
+                .get_note_by_file_path(&original.to_string_lossy())?
+                .is_some()
+        {
+            let dir = original.parent().unwrap_or(Path::new("."));
+            vault::unique_note_path(dir, &deleted.title, |candidate| {
+                Ok(note_service
+                    .get_note_by_file_path(&candidate.to_string_lossy())?
+                    .is_some())
+            })?
+        } else {
+            original
+        };
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(Some(target))
+    }
+
+    /// Write the restored note's file at `target` (the trashed file, if any,
+    /// already moved there) and put the note back in the database.
+    fn record_restore(
+        &self,
+        deleted: DeletedNote,
+        target: Option<&Path>,
+    ) -> Result<Note, SunderError> {
+        let mut file_hash = None;
+        let mut metadata = deleted.metadata.clone();
+        let file_path = match target {
+            Some(target) => {
+                let written = vault::write_note_file(
+                    target,
+                    &deleted.title,
+                    &deleted.content,
+                    deleted.metadata.as_ref(),
+                )?;
+                file_hash = Some(written.hash);
+                metadata = written.metadata;
+                Some(target.to_string_lossy().to_string())
+            }
+            None => None,
+        };
+
+        let mut conn = self.db.get_write_conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO notes (id, title, content, file_path, word_count, created_at, updated_at, file_hash, metadata)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            rusqlite::params![
+                deleted.id,
+                deleted.title,
+                deleted.content,
+                file_path,
+                deleted.word_count,
+                deleted.created_at,
+                deleted.updated_at,
+                file_hash,
+                metadata_to_sql(&metadata),
+            ],
+        )?;
+        // Vectors from a model that has since been replaced cannot go back in
+        let current_model = embedding::active_model(&tx)?;
+        if let Some(blob) = deleted
+            .embedding
+            .as_ref()
+            .filter(|_| deleted.model_version.is_some() && deleted.model_version == current_model)
+        {
+            let now = chrono::Utc::now().to_rfc3339();
+            tx.execute(
+                "INSERT OR REPLACE INTO embeddings
+                    (note_id, vector, model_version, updated_at, content_hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                rusqlite::params![deleted.id, blob, deleted.model_version, now, deleted.content_hash],
+            )?;
+            tx.execute(
+                "INSERT INTO vec_embeddings (note_id, embedding) VALUES (?1, ?2)",
+                rusqlite::params![deleted.id, blob],
+            )?;
+            tx.execute(
+                "INSERT INTO note_chunks
+                    (note_id, chunk_index, start_offset, end_offset, vector, model_version, chunking)
+                 SELECT note_id, chunk_index, start_offset, end_offset, vector, model_version, chunking
+                 FROM deleted_note_chunks
+                 WHERE note_id = ?1 AND model_version = ?2
+                 ORDER BY chunk_index",
+                rusqlite::params![deleted.id, deleted.model_version],
+            )?;
+            tx.execute(
+                "INSERT INTO vec_chunks (chunk_id, embedding)
+                 SELECT id, vector FROM note_chunks WHERE note_id = ?1",
+                [&deleted.id],
+            )?;
+        }
+        let note = Note {
+            id: deleted.id,
+            title: deleted.title,
+            content: deleted.content,
+            file_path,
+            word_count: deleted.word_count,
+            created_at: deleted.created_at,
+            updated_at: deleted.updated_at,
+            metadata,
+            file_hash,
+        };
+        sync_derived(&tx, &note)?;
+        tx.execute("DELETE FROM deleted_notes WHERE id = ?1", [&note.id])?;
+        tx.commit()?;
+
+        Ok(note)
+    }
+
+    /// Permanently delete everything in the trash. Returns the number of notes removed.
+    pub fn empty_trash(&self) -> Result<u32, SunderError> {
+        self.purge_where("1 = 1", rusqlite::params![])
+    }
+
+    /// Permanently delete notes trashed longer ago than the configured retention.
+    pub fn purge_expired(&self) -> Result<u32, SunderError> {
+        let retention_days = SettingsService::new(Arc::clone(&self.db))
+            .get_settings()?
+            .trash_retention_days;
+        if retention_days == 0 {
+            return Ok(0);
+        }
+
+        let cutoff = (chrono::Utc::now() - chrono::Duration::days(retention_days as i64)).to_rfc3339();
+        let purged = self.purge_where("deleted_at < ?1", rusqlite::params![cutoff])?;
+        if purged > 0 {
+            tracing::info!("Purged {purged} note(s) from trash older than {retention_days} days");
+        }
+        Ok(purged)
+    }
+
+    fn purge_where(&self, condition: &str, params: &[&dyn rusqlite::ToSql]) -> Result<u32, SunderError> {
+        let mut conn = self.db.get_write_conn()?;
+        let tx = conn.transaction()?;
+
+        let trash_paths: Vec<Option<String>> = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT trash_path FROM deleted_notes WHERE {condition}"
+            ))?;
+            let paths = stmt
+                .query_map(params, |row| row.get(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            paths
+        };
+
+        tx.execute(
+            &format!(
+                "DELETE FROM note_revisions
+                 WHERE note_id IN (SELECT id FROM deleted_notes WHERE {condition})"
+            ),
+            params,
+        )?;
+        let purged = tx.execute(
+            &format!("DELETE FROM deleted_notes WHERE {condition}"),
+            params,
+        )?;
+        tx.commit()?;
+
+        // Files go only once the rows are gone, so a failed purge loses nothing
+        for path in trash_paths.iter().flatten() {
+            if let Err(e) = std::fs::remove_file(path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("Failed to remove trashed file {path}: {e}");
+                }
+            }
+        }
+        Ok(purged as u32)
+    }
+
+    fn get_deleted(&self, id: &str) -> Result<DeletedNote, SunderError> {
+        let conn = self.db.get_read_conn()?;
+        conn.query_row(
+            "SELECT id, title, content, file_path, trash_path, word_count, created_at, updated_at,
+                    metadata, embedding, model_version, content_hash
+             FROM deleted_notes WHERE id = ?1",
+            [id],
+            |row| {
+                Ok(DeletedNote {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    content: row.get(2)?,
+                    file_path: row.get(3)?,
+                    trash_path: row.get(4)?,
+                    word_count: row.get(5)?,
+                    created_at: row.get(6)?,
+                    updated_at: row.get(7)?,
+                    metadata: row
+                        .get::<_, Option<String>>(8)?
+                        .and_then(|json| serde_json::from_str(&json).ok()),
+                    embedding: row.get(9)?,
+                    model_version: row.get(10)?,
+                    content_hash: row.get(11)?,
+                })
+            },
+        )
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => {
+                SunderError::NotFound(format!("Trashed note not found: {id}"))
+            }
+            _ => SunderError::from(e),
+        })
+    }
+
+    /// Trash directory for a file: `.sunder/trash` inside the vault when the file
+    /// lives in it, otherwise a `trash` directory next to the database.
+    fn trash_dir_for(&self, file: &Path) -> Result<PathBuf, SunderError> {
+        let dir = match vault::active_vault_dir(&self.db)? {
+            Some(vault_dir) if file.starts_with(&vault_dir) => vault_dir.join(".sunder").join("trash"),
+            _ => self
+                .db
+                .db_path()
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join("trash"),
+        };
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+}
@@ -0,0 +1,244 @@
+use crate::db::DatabaseManager;
+use crate::error::SunderError;
+use crate::services::front_matter::Document;
+use crate::services::settings::SettingsService;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+const MAX_SLUG_CHARS: usize = 80;
+
+/// The watched vault directory, if one is configured and still present.
+pub fn active_vault_dir(db: &Arc<DatabaseManager>) -> Result<Option<PathBuf>, SunderError> {
+    let settings = SettingsService::new(Arc::clone(db)).get_settings()?;
+    Ok(settings
+        .watch_directory
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_dir()))
+}
+
+/// Hash of a file's full contents, used to recognise files we wrote ourselves.
+pub fn file_hash(contents: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(contents.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Result of writing a note's markdown file.
+pub struct WrittenFile {
+    /// Hash of the full file contents.
+    pub hash: String,
+    /// Front matter as written, so the database matches the file exactly.
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// Write a note back to its markdown file, keeping the existing front matter.
+///
+/// The front matter on disk is kept byte-for-byte unless `metadata` differs
+/// from it, in which case it is re-serialized from `metadata`. `None` means
+/// "leave whatever is on disk". The title is only written into the front matter
+/// when the file would not otherwise yield it on the next import.
+pub fn write_note_file(
+    path: &Path,
+    title: &str,
+    body: &str,
+    metadata: Option<&serde_json::Value>,
+) -> Result<WrittenFile, SunderError> {
+    let mut doc = match std::fs::read_to_string(path) {
+        Ok(existing) => Document::parse(&existing),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Document::parse(""),
+        Err(e) => {
+            return Err(SunderError::IoError(format!(
+                "Failed to read {}: {e}",
+                path.display()
+            )))
+        }
+    };
+
+    if let Some(metadata) = metadata {
+        if doc.metadata().as_ref() != Some(metadata) {
+            doc.set_metadata(metadata).map_err(|e| {
+                SunderError::Internal(format!("Failed to serialize front matter: {e}"))
+            })?;
+        }
+    }
+    if doc.title_or_file_stem(path) != title {
+        doc.set_title(title);
+    }
+    doc.body = body.to_string();
+
+    let rendered = doc.render();
+    write_atomic(path, &rendered)?;
+    Ok(WrittenFile {
+        hash: file_hash(&rendered),
+        metadata: doc.metadata(),
+    })
+}
+
+/// Create the markdown file for a note made inside the app, with generated
+/// front matter.
+pub fn write_new_note_file(
+    path: &Path,
+    title: &str,
+    created_at: &str,
+    body: &str,
+) -> Result<WrittenFile, SunderError> {
+    let metadata = serde_json::json!({
+        "title": title,
+        "created": created_at,
+    });
+    write_note_file(path, title, body, Some(&metadata))
+}
+
+/// Pick a path for a new note in `dir`, named after the slugified title.
+/// Appends `-2`, `-3`, ... until the name is free on disk and not `taken`.
+pub fn unique_note_path<F>(dir: &Path, title: &str, taken: F) -> Result<PathBuf, SunderError>
+where
+    F: Fn(&Path) -> Result<bool, SunderError>,
+{
+    unique_path_for_stem(dir, &slugify(title), taken)
+}
+
+/// Pick a free `{stem}.md` path in `dir`, appending `-2`, `-3`, ... as needed.
+pub fn unique_path_for_stem<F>(dir: &Path, stem: &str, taken: F) -> Result<PathBuf, SunderError>
+where
+    F: Fn(&Path) -> Result<bool, SunderError>,
+{
+    for n in 1u32.. {
+        let file_name = if n == 1 {
+            format!("{stem}.md")
+        } else {
+            format!("{stem}-{n}.md")
+        };
+        let candidate = dir.join(file_name);
+        if !candidate.exists() && !taken(&candidate)? {
+            return Ok(candidate);
+        }
+    }
+    unreachable!("exhausted note filename candidates")
+}
+
+/// Lowercase, hyphen-separated filename stem. Non-ASCII letters and digits are
+/// kept so titles in other scripts still produce readable names.
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+    for c in title.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    let slug: String = slug.trim_end_matches('-').chars().take(MAX_SLUG_CHARS).collect();
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "untitled".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// A title made safe to use as a file name as-is, for notes whose title comes
+/// from their file name rather than a slug.
+pub fn title_file_stem(title: &str) -> String {
+    let stem: String = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '-',
+            c if c.is_control() => '-',
+            c => c,
+        })
+        .collect();
+    let stem = stem.trim().trim_start_matches('.').trim();
+    if stem.is_empty() {
+        "Untitled".to_string()
+    } else {
+        stem.to_string()
+    }
+}
+
+/// Resolve `.` and `..` components without touching the filesystem.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Path of `to` relative to the directory `from_dir`, with `/` separators as
+/// used in markdown links.
+pub fn relative_link_path(from_dir: &Path, to: &Path) -> String {
+    let relative = pathdiff::diff_paths(to, from_dir).unwrap_or_else(|| to.to_path_buf());
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Write a file by writing a hidden temp file in the same directory and renaming
+/// it over the target, so readers (and the file watcher) never see a partial file.
+pub fn write_atomic(path: &Path, contents: &str) -> Result<(), SunderError> {
+    let tmp_path = temp_path_for(path)?;
+
+    let result = (|| -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+    })();
+
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(SunderError::IoError(format!(
+            "Failed to write {}: {e}",
+            path.display()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Move a file, falling back to copy-and-delete when a rename is not possible
+/// (e.g. across filesystems).
+pub fn move_file(from: &Path, to: &Path) -> Result<(), SunderError> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    std::fs::copy(from, to).map_err(|e| {
+        SunderError::IoError(format!(
+            "Failed to move {} to {}: {e}",
+            from.display(),
+            to.display()
+        ))
+    })?;
+    std::fs::remove_file(from)?;
+    Ok(())
+}
+
+/// Temp file next to `path`. Hidden and without a markdown extension so the
+/// file watcher ignores it.
+fn temp_path_for(path: &Path) -> Result<PathBuf, SunderError> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| SunderError::ValidationError(format!("Invalid file path: {}", path.display())))?;
+    Ok(path.with_file_name(format!(
+        ".{}.sunder-tmp",
+        file_name.to_string_lossy()
+    )))
+}
+
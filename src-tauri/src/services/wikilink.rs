@@ -0,0 +1,302 @@
+use crate::db::DatabaseManager;
+use crate::error::SunderError;
+use crate::services::markdown;
+use crate::services::note::{Note, NoteService};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use std::sync::Arc;
+
+const CONTEXT_CHARS: usize = 160;
+
+/// A `[[target#heading|alias]]` link found in a note's content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wikilink {
+    pub target: String,
+    pub heading: Option<String>,
+    pub alias: Option<String>,
+    /// Byte range of the whole link in the content, including brackets and any `!`.
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NoteLink {
+    pub source_id: String,
+    pub source_title: String,
+    /// `None` while the link does not resolve to any note.
+    pub target_id: Option<String>,
+    pub target_title: Option<String>,
+    /// Link target as written, e.g. `Note Title` in `[[Note Title#Section|shown]]`.
+    pub target_text: String,
+    pub heading: Option<String>,
+    pub alias: Option<String>,
+    /// 1-based line of the link in the source note.
+    pub line: u32,
+    /// The line containing the link.
+    pub context: String,
+}
+
+pub struct WikilinkService {
+    db: Arc<DatabaseManager>,
+}
+
+impl WikilinkService {
+    pub fn new(db: Arc<DatabaseManager>) -> Self {
+        Self { db }
+    }
+
+    /// Links from other notes that resolve to this note.
+    pub fn get_backlinks(&self, note_id: &str) -> Result<Vec<NoteLink>, SunderError> {
+        NoteService::new(Arc::clone(&self.db)).get_note(note_id)?;
+        self.query_links(
+            "l.target_id = ?1 AND l.source_id != ?1",
+            "s.updated_at DESC, l.start_offset",
+            note_id,
+        )
+    }
+
+    /// Links written in this note, resolved or not, in document order.
+    pub fn get_outgoing_links(&self, note_id: &str) -> Result<Vec<NoteLink>, SunderError> {
+        NoteService::new(Arc::clone(&self.db)).get_note(note_id)?;
+        self.query_links("l.source_id = ?1", "l.start_offset", note_id)
+    }
+
+    fn query_links(
+        &self,
+        condition: &str,
+        order: &str,
+        note_id: &str,
+    ) -> Result<Vec<NoteLink>, SunderError> {
+        let conn = self.db.get_read_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT l.source_id, s.title, s.content, l.target_id, t.title, l.target_text,
+                    l.heading, l.alias, l.start_offset
+             FROM note_links l
+             JOIN notes s ON s.id = l.source_id
+             LEFT JOIN notes t ON t.id = l.target_id
+             WHERE {condition}
+             ORDER BY {order}"
+        ))?;
+        let links = stmt
+            .query_map([note_id], |row| {
+                let content: String = row.get(2)?;
+                let start: usize = row.get(8)?;
+                Ok(NoteLink {
+                    source_id: row.get(0)?,
+                    source_title: row.get(1)?,
+                    target_id: row.get(3)?,
+                    target_title: row.get(4)?,
+                    target_text: row.get(5)?,
+                    heading: row.get(6)?,
+                    alias: row.get(7)?,
+                    line: markdown::line_number(&content, start),
+                    context: markdown::line_context(&content, start, CONTEXT_CHARS),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(links)
+    }
+}
+
+/// All wikilinks in `content`, skipping code blocks and inline code. Embeds
+/// (`![[...]]`) count as links. Links with an empty target (`[[#Heading]]`) are ignored.
+pub fn parse_wikilinks(content: &str) -> Vec<Wikilink> {
+    let code = markdown::code_ranges(content);
+    let mut links = Vec::new();
+    let mut search_from = 0;
+
+    while let Some(found) = content[search_from..].find("[[") {
+        let open = search_from + found;
+        search_from = open + 2;
+        if markdown::in_ranges(&code, open) {
+            continue;
+        }
+
+        let rest = &content[open + 2..];
+        let Some(close) = rest.find("]]") else {
+            break;
+        };
+        let inner = &rest[..close];
+        if inner.contains(['\n', '[', ']']) {
+            continue;
+        }
+
+        // `\|` is how a pipe is escaped inside tables
+        let (target_part, alias) = match inner.split_once('|') {
+            Some((target, alias)) => (target.trim_end_matches('\\'), Some(alias.trim())),
+            None => (inner, None),
+        };
+        let (target, heading) = match target_part.split_once('#') {
+            Some((target, heading)) => (target.trim(), Some(heading.trim())),
+            None => (target_part.trim(), None),
+        };
+        if target.is_empty() {
+            continue;
+        }
+
+        let start = if content[..open].ends_with('!') { open - 1 } else { open };
+        let end = open + 2 + close + 2;
+        links.push(Wikilink {
+            target: target.to_string(),
+            heading: heading.filter(|h| !h.is_empty()).map(str::to_string),
+            alias: alias.filter(|a| !a.is_empty()).map(str::to_string),
+            start,
+            end,
+        });
+        search_from = end;
+    }
+
+    links
+}
+
+/// Aliases declared in front matter `aliases:` (or `alias:`), as a list or a
+/// comma separated string.
+pub fn front_matter_aliases(metadata: Option<&serde_json::Value>) -> Vec<String> {
+    let Some(value) = metadata.and_then(|m| m.get("aliases").or_else(|| m.get("alias"))) else {
+        return Vec::new();
+    };
+
+    let aliases: Vec<String> = match value {
+        serde_json::Value::Array(items) => items
+            .iter()
+            .filter_map(|item| item.as_str().map(str::to_string))
+            .collect(),
+        serde_json::Value::String(s) => s.split(',').map(str::to_string).collect(),
+        _ => Vec::new(),
+    };
+
+    aliases
+        .into_iter()
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty())
+        .collect()
+}
+
+/// Replace a note's aliases with those in its front matter.
+pub fn sync_note_aliases(
+    conn: &Connection,
+    note_id: &str,
+    metadata: Option<&serde_json::Value>,
+) -> Result<(), SunderError> {
+    conn.execute("DELETE FROM note_aliases WHERE note_id = ?1", [note_id])?;
+    for alias in front_matter_aliases(metadata) {
+        conn.execute(
+            "INSERT OR IGNORE INTO note_aliases (note_id, alias) VALUES (?1, ?2)",
+            rusqlite::params![note_id, alias],
+        )?;
+    }
+    Ok(())
+}
+
+/// Replace a note's outgoing links with those in its content, then re-resolve
+/// links elsewhere that point at this note or could now resolve to it.
+/// Expects the note's aliases to be synced already.
+pub fn sync_note_links(conn: &Connection, note: &Note) -> Result<(), SunderError> {
+    conn.execute("DELETE FROM note_links WHERE source_id = ?1", [&note.id])?;
+    for link in parse_wikilinks(&note.content) {
+        let target_id = resolve_target(conn, &link.target)?;
+        conn.execute(
+            "INSERT INTO note_links
+                (source_id, target_id, target_text, heading, alias, start_offset, end_offset)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            rusqlite::params![
+                note.id,
+                target_id,
+                link.target,
+                link.heading,
+                link.alias,
+                link.start,
+                link.end,
+            ],
+        )?;
+    }
+
+    let file_path = note.file_path.as_deref().map(|p| p.replace('\\', "/"));
+    let mut stmt = conn.prepare(
+        "SELECT id, target_text, target_id FROM note_links
+         WHERE target_id = ?1
+            OR (target_id IS NULL AND (
+                   target_text = ?2 COLLATE NOCASE
+                OR target_text COLLATE NOCASE IN (SELECT alias FROM note_aliases WHERE note_id = ?1)
+                OR (?3 IS NOT NULL AND substr('/' || ?3, -(length(target_text) + 4))
+                        = '/' || target_text || '.md' COLLATE NOCASE)
+                OR (?3 IS NOT NULL AND substr('/' || ?3, -(length(target_text) + 1))
+                        = '/' || target_text COLLATE NOCASE)
+            ))",
+    )?;
+    let candidates: Vec<(i64, String, Option<String>)> = stmt
+        .query_map(rusqlite::params![note.id, note.title, file_path], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut changed = 0;
+    for (link_id, target_text, current) in candidates {
+        let target_id = resolve_target(conn, &target_text)?;
+        if target_id != current {
+            conn.execute(
+                "UPDATE note_links SET target_id = ?1 WHERE id = ?2",
+                rusqlite::params![target_id, link_id],
+            )?;
+            changed += 1;
+        }
+    }
+    if changed > 0 {
+        tracing::debug!("Re-resolved {changed} link(s) for note {}", note.id);
+    }
+
+    Ok(())
+}
+
+/// Find the note a link target refers to: by title, then alias, then file path
+/// (relative to any folder, with or without `.md`). Matching ignores ASCII case;
+/// the oldest note wins when several match.
+pub fn resolve_target(conn: &Connection, target: &str) -> Result<Option<String>, SunderError> {
+    let target = target.trim();
+    if target.is_empty() {
+        return Ok(None);
+    }
+
+    let by_title = conn
+        .query_row(
+            "SELECT id FROM notes WHERE title = ?1 COLLATE NOCASE
+             ORDER BY created_at, id LIMIT 1",
+            [target],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if by_title.is_some() {
+        return Ok(by_title);
+    }
+
+    let by_alias = conn
+        .query_row(
+            "SELECT a.note_id FROM note_aliases a JOIN notes n ON n.id = a.note_id
+             WHERE a.alias = ?1
+             ORDER BY n.created_at, n.id LIMIT 1",
+            [target],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if by_alias.is_some() {
+        return Ok(by_alias);
+    }
+
+    let path = target.trim_start_matches("./").trim_start_matches('/');
+    let path = if path.to_lowercase().ends_with(".md") {
+        path.to_string()
+    } else {
+        format!("{path}.md")
+    };
+    let by_path = conn
+        .query_row(
+            "SELECT id FROM notes
+             WHERE file_path IS NOT NULL
+               AND substr('/' || replace(file_path, '\\', '/'), -(length(?1) + 1)) = '/' || ?1 COLLATE NOCASE
+             ORDER BY length(file_path), created_at LIMIT 1",
+            [path],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(by_path)
+}
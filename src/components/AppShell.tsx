@@ -4,11 +4,14 @@ import { EditorView } from "./EditorView";
 import { LatentLinksPanel } from "./LatentLinksPanel";
 import { GraphCanvas } from "./GraphCanvas";
 import { SettingsView } from "./SettingsView";
+import type { TextRange } from "../types";
 
 // --- State ---
 
 interface AppState {
   activeNoteId: string | null;
+  /** Search hits to jump to in the active note. */
+  activeMatches: TextRange[];
   sidebarOpen: boolean;
   graphVisible: boolean;
   settingsOpen: boolean;
@@ -17,7 +20,7 @@ interface AppState {
 }
 
 type AppAction =
-  | { type: "SET_ACTIVE_NOTE"; id: string | null }
+  | { type: "SET_ACTIVE_NOTE"; id: string | null; matches?: TextRange[] }
   | { type: "TOGGLE_SIDEBAR" }
   | { type: "TOGGLE_GRAPH" }
   | { type: "TOGGLE_SETTINGS" }
@@ -26,6 +29,7 @@ type AppAction =
 
 const initialState: AppState = {
   activeNoteId: null,
+  activeMatches: [],
   sidebarOpen: true,
   graphVisible: false,
   settingsOpen: false,
@@ -36,7 +40,13 @@ const initialState: AppState = {
 function appReducer(state: AppState, action: AppAction): AppState {
   switch (action.type) {
     case "SET_ACTIVE_NOTE":
-      return { ...state, activeNoteId: action.id, editorContent: "", settingsOpen: false };
+      return {
+        ...state,
+        activeNoteId: action.id,
+        activeMatches: action.matches ?? [],
+        editorContent: "",
+        settingsOpen: false,
+      };
     case "TOGGLE_SIDEBAR":
       return { ...state, sidebarOpen: !state.sidebarOpen };
     case "TOGGLE_GRAPH":
@@ -3,8 +3,15 @@ import { useAppState } from "./AppShell";
 import { useNote } from "../hooks/useNote";
 import { useAutoSave } from "../hooks/useAutoSave";
 import { ipc } from "../types";
-import { EditorState } from "@codemirror/state";
-import { EditorView as CMEditorView, keymap, placeholder } from "@codemirror/view";
+import type { TextRange } from "../types";
+import { EditorState, StateEffect, StateField } from "@codemirror/state";
+import {
+  EditorView as CMEditorView,
+  Decoration,
+  type DecorationSet,
+  keymap,
+  placeholder,
+} from "@codemirror/view";
 import { markdown } from "@codemirror/lang-markdown";
 import { defaultKeymap, history, historyKeymap } from "@codemirror/commands";
 import { syntaxHighlighting, HighlightStyle, bracketMatching } from "@codemirror/language";
@@ -55,6 +62,10 @@ const sunderTheme = CMEditorView.theme({
   ".cm-line": {
     padding: "0",
   },
+  ".cm-searchHit": {
+    backgroundColor: "var(--color-amber-soft)",
+    borderRadius: "2px",
+  },
   "&.cm-focused": {
     outline: "none",
   },
@@ -80,6 +91,34 @@ const sunderHighlight = HighlightStyle.define([
   { tag: tags.processingInstruction, color: "var(--color-teal)" },
 ]);
 
+// --- Search hits ---
+
+const setSearchHits = StateEffect.define<{ from: number; to: number }[]>();
+const searchHitMark = Decoration.mark({ class: "cm-searchHit" });
+
+// Marks the hits of the search a note was opened from, until the first edit
+const searchHits = StateField.define<DecorationSet>({
+  create: () => Decoration.none,
+  update(hits, tr) {
+    for (const effect of tr.effects) {
+      if (effect.is(setSearchHits)) {
+        return Decoration.set(effect.value.map((r) => searchHitMark.range(r.from, r.to)), true);
+      }
+    }
+    return tr.docChanged ? Decoration.none : hits;
+  },
+  provide: (field) => CMEditorView.decorations.from(field),
+});
+
+/** Convert a character (code point) offset, as the backend counts, to a UTF-16 one. */
+function utf16Offset(text: string, offset: number): number {
+  let index = 0;
+  for (let i = 0; i < offset && index < text.length; i++) {
+    index += (text.codePointAt(index) ?? 0) > 0xffff ? 2 : 1;
+  }
+  return index;
+}
+
 // --- Component ---
 
 interface EditorViewProps {
@@ -87,7 +126,10 @@ interface EditorViewProps {
 }
 
 export function EditorView({ noteId }: EditorViewProps) {
-  const { dispatch } = useAppState();
+  const {
+    state: { activeMatches },
+    dispatch,
+  } = useAppState();
   const { note, isLoading, error } = useNote(noteId === "new" ? null : noteId);
   const containerRef = useRef<HTMLDivElement>(null);
   const cmViewRef = useRef<CMEditorView | null>(null);
@@ -134,6 +176,7 @@ export function EditorView({ noteId }: EditorViewProps) {
         CMEditorView.lineWrapping,
         placeholder("Start writing..."),
         keymap.of([...defaultKeymap, ...historyKeymap]),
+        searchHits,
         updateListener,
       ],
     });
@@ -189,6 +232,22 @@ export function EditorView({ noteId }: EditorViewProps) {
     }
   }, [noteId, note]);
 
+  // Mark the search hits the note was opened from and jump to the first
+  const loadedNoteId = note?.id;
+  useEffect(() => {
+    const view = cmViewRef.current;
+    if (!view || loadedNoteId !== noteId) return;
+    const doc = view.state.doc.toString();
+    const hits = activeMatches
+      .map((m) => ({ from: utf16Offset(doc, m.start), to: utf16Offset(doc, m.end) }))
+      .filter((h) => h.to > h.from);
+    view.dispatch({
+      effects: setSearchHits.of(hits),
+      selection: hits.length > 0 ? { anchor: hits[0].from } : undefined,
+      scrollIntoView: hits.length > 0,
+    });
+  }, [loadedNoteId, noteId, activeMatches]);
+
   const handleDelete = useCallback(async () => {
     if (!noteId || noteId === "new") return;
     try {
@@ -1,7 +1,7 @@
 import { useState, useEffect, useCallback } from "react";
 import { useAppState } from "./AppShell";
 import { ipc, events } from "../types";
-import type { Settings, IndexingProgressPayload } from "../types";
+import type { Settings, IndexingProgressPayload, EmbeddingStatus } from "../types";
 import { open } from "@tauri-apps/plugin-dialog";
 
 export function SettingsView() {
@@ -13,7 +13,10 @@ export function SettingsView() {
   const [debounceMs, setDebounceMs] = useState(1000);
   const [indexingProgress, setIndexingProgress] = useState<IndexingProgressPayload | null>(null);
   const [isReindexing, setIsReindexing] = useState(false);
+  const [isPaused, setIsPaused] = useState(false);
   const [scanResult, setScanResult] = useState<string | null>(null);
+  const [embeddingStatus, setEmbeddingStatus] = useState<EmbeddingStatus | null>(null);
+  const [isLoadingModel, setIsLoadingModel] = useState(false);
 
   // Load settings
   useEffect(() => {
@@ -63,6 +66,29 @@ export function SettingsView() {
     }
   }, []);
 
+  // Semantic features are off until an embedding model loads
+  useEffect(() => {
+    ipc.getEmbeddingStatus().then(setEmbeddingStatus).catch(() => {});
+    const unlisten = events.onEmbeddingStatus(setEmbeddingStatus);
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  const handlePickModel = useCallback(async () => {
+    try {
+      const selected = await open({ directory: true, multiple: false });
+      if (selected && typeof selected === "string") {
+        setIsLoadingModel(true);
+        await ipc.loadEmbeddingModel(selected);
+      }
+    } catch {
+      // Load errors are reported through the embedding status
+    } finally {
+      setIsLoadingModel(false);
+    }
+  }, []);
+
   const handleStopWatching = useCallback(async () => {
     try {
       await ipc.stopWatching();
@@ -108,6 +134,39 @@ export function SettingsView() {
     [dispatch],
   );
 
+  // Pick up a reindex left paused or unfinished by the last session
+  useEffect(() => {
+    ipc
+      .getIndexQueue()
+      .then((status) => {
+        setIsPaused(status.paused);
+        if (status.pending > 0) setIsReindexing(true);
+      })
+      .catch(() => {});
+    const unlisten = events.onIndexQueue((status) => setIsPaused(status.paused));
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  const handlePauseToggle = useCallback(async () => {
+    try {
+      await (isPaused ? ipc.resumeReindex() : ipc.pauseReindex());
+    } catch {
+      // Error handled
+    }
+  }, [isPaused]);
+
+  const handleCancelReindex = useCallback(async () => {
+    try {
+      await ipc.cancelReindex();
+      setIsReindexing(false);
+      setIndexingProgress(null);
+    } catch {
+      // Error handled
+    }
+  }, []);
+
   const handleReindex = useCallback(async () => {
     setIsReindexing(true);
     setIndexingProgress(null);
@@ -188,6 +247,48 @@ export function SettingsView() {
           )}
         </Section>
 
+        {/* Embedding Model */}
+        <Section
+          title="Embedding Model"
+          description="Powers semantic search, latent links and the graph. Without a model, search is full-text only."
+        >
+          <div className="flex items-center gap-2">
+            <div
+              className="flex min-w-0 flex-1 items-center rounded-lg px-3 py-2"
+              style={{
+                background: "var(--color-surface-2)",
+                border: "1px solid var(--color-border-subtle)",
+              }}
+            >
+              <span
+                className="truncate text-[12px] font-mono"
+                style={{
+                  color: embeddingStatus?.available ? "var(--color-ink-secondary)" : "var(--color-ink-ghost)",
+                }}
+              >
+                {embeddingStatus?.model_version ?? "No model loaded"}
+              </span>
+            </div>
+            <button
+              onClick={handlePickModel}
+              disabled={isLoadingModel}
+              className="focus-ring shrink-0 rounded-lg px-3 py-2 text-[12px] font-500 transition-colors"
+              style={{
+                background: "var(--color-surface-3)",
+                color: "var(--color-ink-secondary)",
+                border: "1px solid var(--color-border-subtle)",
+              }}
+            >
+              {isLoadingModel ? "Loading..." : "Load Model"}
+            </button>
+          </div>
+          {embeddingStatus?.error && (
+            <p className="mt-2 text-[11px] font-500" style={{ color: "var(--color-rose)" }}>
+              {embeddingStatus.error}
+            </p>
+          )}
+        </Section>
+
         {/* Similarity Threshold */}
         <Section
           title="Similarity Threshold"
@@ -285,8 +386,26 @@ export function SettingsView() {
               boxShadow: isReindexing ? "none" : "0 2px 8px oklch(0.72 0.12 70 / 0.2)",
             }}
           >
-            {isReindexing ? "Reindexing..." : "Reindex All"}
+            {isReindexing ? (isPaused ? "Paused" : "Reindexing...") : "Reindex All"}
           </button>
+          {isReindexing && (
+            <span className="ml-2 inline-flex gap-2">
+              <button
+                onClick={handlePauseToggle}
+                className="focus-ring rounded-lg px-3 py-2.5 text-[12px] font-500 transition-all"
+                style={{ background: "var(--color-surface-2)", color: "var(--color-ink-secondary)" }}
+              >
+                {isPaused ? "Resume" : "Pause"}
+              </button>
+              <button
+                onClick={handleCancelReindex}
+                className="focus-ring rounded-lg px-3 py-2.5 text-[12px] font-500 transition-all"
+                style={{ background: "var(--color-surface-2)", color: "var(--color-ink-secondary)" }}
+              >
+                Cancel
+              </button>
+            </span>
+          )}
 
           {/* Progress bar */}
           {indexingProgress && (
@@ -297,6 +416,11 @@ export function SettingsView() {
                 </span>
                 <span className="font-mono tabular-nums" style={{ color: "var(--color-amber)" }}>
                   {indexingProgress.processed}/{indexingProgress.total}
+                  {indexingProgress.notes_per_second !== null &&
+                    ` · ${indexingProgress.notes_per_second.toFixed(1)}/s`}
+                  {indexingProgress.eta_seconds !== null &&
+                    indexingProgress.eta_seconds > 0 &&
+                    ` · ${formatEta(indexingProgress.eta_seconds)} left`}
                 </span>
               </div>
               <div
@@ -377,3 +501,10 @@ function Section({
     </div>
   );
 }
+
+function formatEta(seconds: number): string {
+  if (seconds < 60) return `${seconds}s`;
+  const minutes = Math.round(seconds / 60);
+  if (minutes < 60) return `${minutes}m`;
+  return `${Math.floor(minutes / 60)}h ${minutes % 60}m`;
+}
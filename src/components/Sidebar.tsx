@@ -3,6 +3,7 @@ import { useAppState } from "./AppShell";
 import { useNoteList } from "../hooks/useNoteList";
 import { useSearch } from "../hooks/useSearch";
 import { ipc, events } from "../types";
+import type { TextRange } from "../types";
 
 export function Sidebar() {
   const { state, dispatch } = useAppState();
@@ -12,7 +13,14 @@ export function Sidebar() {
   const listRef = useRef<HTMLDivElement>(null);
 
   const { notes, total, isLoading, refresh } = useNoteList();
-  const { results: searchResults, isSearching } = useSearch(searchQuery);
+  const {
+    results: searchResults,
+    total: searchTotal,
+    isSearching,
+    error: searchError,
+    hasMore: hasMoreResults,
+    loadMore: loadMoreResults,
+  } = useSearch(searchQuery);
 
   const isOpen = state.sidebarOpen;
   const isSearchActive = searchQuery.trim().length > 0;
@@ -33,6 +41,8 @@ export function Sidebar() {
         id: r.id,
         title: r.title,
         snippet: r.snippet,
+        highlights: r.snippet_highlights,
+        matches: r.matches,
         updated_at: "",
         matchType: r.match_type,
       }))
@@ -40,13 +50,15 @@ export function Sidebar() {
         id: n.id,
         title: n.title,
         snippet: n.snippet,
+        highlights: [] as TextRange[],
+        matches: [] as TextRange[],
         updated_at: n.updated_at,
         matchType: undefined as string | undefined,
       }));
 
   const selectNote = useCallback(
-    (id: string) => {
-      dispatch({ type: "SET_ACTIVE_NOTE", id });
+    (id: string, matches: TextRange[] = []) => {
+      dispatch({ type: "SET_ACTIVE_NOTE", id, matches });
     },
     [dispatch],
   );
@@ -72,7 +84,7 @@ export function Sidebar() {
         setFocusedIndex((i) => Math.max(i - 1, 0));
       } else if (e.key === "Enter" && focusedIndex >= 0) {
         e.preventDefault();
-        selectNote(displayItems[focusedIndex].id);
+        selectNote(displayItems[focusedIndex].id, displayItems[focusedIndex].matches);
       } else if (e.key === "Escape") {
         setSearchQuery("");
         setFocusedIndex(-1);
@@ -199,7 +211,7 @@ export function Sidebar() {
                 {isSearchActive
                   ? isSearching
                     ? "Searching..."
-                    : "No matching notes"
+                    : (searchError ?? "No matching notes")
                   : "No notes yet"}
               </p>
             </div>
@@ -214,7 +226,7 @@ export function Sidebar() {
                   data-note-item
                   role="option"
                   aria-selected={isActive}
-                  onClick={() => selectNote(item.id)}
+                  onClick={() => selectNote(item.id, item.matches)}
                   className={`focus-ring group relative mb-0.5 flex w-full flex-col rounded-lg px-2.5 py-2 text-left transition-all duration-150 ${
                     isFocused && !isActive ? "ring-1 ring-[var(--color-violet)] ring-opacity-30" : ""
                   }`}
@@ -278,7 +290,7 @@ export function Sidebar() {
                       className="truncate text-[11px] font-300"
                       style={{ color: "var(--color-ink-ghost)", flex: 1 }}
                     >
-                      {item.snippet}
+                      {highlightSnippet(item.snippet, item.highlights)}
                     </span>
                     {item.updated_at && (
                       <span
@@ -302,11 +314,43 @@ export function Sidebar() {
         >
           <span className="text-[10px] font-400 tabular-nums" style={{ color: "var(--color-ink-ghost)" }}>
             {isSearchActive
-              ? `${displayItems.length} result${displayItems.length !== 1 ? "s" : ""}`
+              ? hasMoreResults
+                ? `${displayItems.length} of ${searchTotal} results`
+                : `${displayItems.length} result${displayItems.length !== 1 ? "s" : ""}`
               : `${total} note${total !== 1 ? "s" : ""}`}
           </span>
+          {isSearchActive && hasMoreResults && (
+            <button
+              onClick={loadMoreResults}
+              disabled={isSearching}
+              className="focus-ring ml-auto rounded px-1.5 text-[10px] font-500 transition-colors hover:bg-[var(--color-surface-3)]"
+              style={{ color: "var(--color-ink-tertiary)" }}
+            >
+              Load more
+            </button>
+          )}
         </div>
       </div>
     </nav>
   );
 }
+
+/** Split a snippet into plain and highlighted runs; ranges count code points, like the backend. */
+function highlightSnippet(snippet: string, highlights: TextRange[]) {
+  if (highlights.length === 0) return snippet;
+  const chars = Array.from(snippet);
+  const parts: React.ReactNode[] = [];
+  let pos = 0;
+  highlights.forEach((h, i) => {
+    if (h.start < pos) return;
+    parts.push(chars.slice(pos, h.start).join(""));
+    parts.push(
+      <mark key={i} style={{ background: "transparent", color: "var(--color-amber)" }}>
+        {chars.slice(h.start, h.end).join("")}
+      </mark>,
+    );
+    pos = h.end;
+  });
+  parts.push(chars.slice(pos).join(""));
+  return parts;
+}
@@ -1,22 +1,41 @@
-import { useState, useEffect, useRef } from "react";
-import type { SearchResult } from "../types";
+import { useState, useEffect, useRef, useCallback } from "react";
+import type { SearchResult, SunderError } from "../types";
 import { ipc } from "../types";
 
 interface UseSearchReturn {
   results: SearchResult[];
+  total: number;
   isSearching: boolean;
   error: string | null;
+  hasMore: boolean;
+  loadMore: () => void;
 }
 
-export function useSearch(query: string): UseSearchReturn {
+function errorMessage(e: unknown): string {
+  // Malformed queries come back as an InvalidQuery error with a message
+  return e instanceof Error
+    ? e.message
+    : typeof e === "object" && e !== null && "message" in e
+      ? String((e as SunderError).message)
+      : String(e);
+}
+
+export function useSearch(query: string, limit: number = 20): UseSearchReturn {
   const [results, setResults] = useState<SearchResult[]>([]);
+  const [total, setTotal] = useState(0);
+  const [nextCursor, setNextCursor] = useState<string | null>(null);
   const [isSearching, setIsSearching] = useState(false);
   const [error, setError] = useState<string | null>(null);
   const timeoutRef = useRef<ReturnType<typeof setTimeout> | null>(null);
+  // Bumped on every query change, so responses for an earlier query are dropped
+  const generationRef = useRef(0);
 
   useEffect(() => {
+    const generation = ++generationRef.current;
     if (!query.trim()) {
       setResults([]);
+      setTotal(0);
+      setNextCursor(null);
       setIsSearching(false);
       setError(null);
       return;
@@ -28,22 +47,46 @@ export function useSearch(query: string): UseSearchReturn {
 
     timeoutRef.current = setTimeout(async () => {
       try {
-        const response = await ipc.searchNotes(query);
-        setResults(response);
+        const page = await ipc.searchNotes(query, undefined, limit);
+        if (generation !== generationRef.current) return;
+        setResults(page.results);
+        setTotal(page.total);
+        setNextCursor(page.next_cursor);
         setError(null);
       } catch (e: unknown) {
-        const msg = e instanceof Error ? e.message : String(e);
-        setError(msg);
+        if (generation !== generationRef.current) return;
+        setError(errorMessage(e));
         setResults([]);
+        setTotal(0);
+        setNextCursor(null);
       } finally {
-        setIsSearching(false);
+        if (generation === generationRef.current) setIsSearching(false);
       }
     }, 200);
 
     return () => {
       if (timeoutRef.current) clearTimeout(timeoutRef.current);
     };
-  }, [query]);
+  }, [query, limit]);
+
+  // Pages follow the cursor so they stay consistent with the first
+  const loadMore = useCallback(async () => {
+    if (!nextCursor || isSearching) return;
+    const generation = generationRef.current;
+    setIsSearching(true);
+    try {
+      const page = await ipc.searchNotes(query, undefined, limit, { cursor: nextCursor });
+      if (generation !== generationRef.current) return;
+      setResults((prev) => [...prev, ...page.results]);
+      setTotal(page.total);
+      setNextCursor(page.next_cursor);
+    } catch (e: unknown) {
+      if (generation !== generationRef.current) return;
+      setError(errorMessage(e));
+    } finally {
+      if (generation === generationRef.current) setIsSearching(false);
+    }
+  }, [query, limit, nextCursor, isSearching]);
 
-  return { results, isSearching, error };
+  return { results, total, isSearching, error, hasMore: nextCursor !== null, loadMore };
 }
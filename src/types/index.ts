@@ -11,6 +11,7 @@ export interface Note {
   word_count: number;
   created_at: string;
   updated_at: string;
+  metadata: Record<string, unknown> | null;
 }
 
 export interface NoteListItem {
@@ -25,12 +26,147 @@ export interface NoteList {
   total: number;
 }
 
+export interface FolderNode {
+  name: string;
+  /** Relative to the vault root with `/` separators; empty for the root. */
+  path: string;
+  count: number;
+  total_count: number;
+  has_children: boolean;
+  /** `null` until loaded with another `getNoteTree(path)` call. */
+  children: FolderNode[] | null;
+}
+
+export interface TrashedNote {
+  id: string;
+  title: string;
+  file_path: string | null;
+  word_count: number;
+  deleted_at: string;
+}
+
+export interface RevisionSummary {
+  id: number;
+  note_id: string;
+  title: string;
+  source: "create" | "edit" | "import" | "restore";
+  word_count: number;
+  created_at: string;
+}
+
+export interface DiffLine {
+  op: "equal" | "insert" | "delete";
+  old_line: number | null;
+  new_line: number | null;
+  text: string;
+}
+
+export interface RevisionDiff {
+  from_revision_id: number;
+  to_revision_id: number | null;
+  insertions: number;
+  deletions: number;
+  lines: DiffLine[];
+}
+
+export interface TagInfo {
+  name: string;
+  parent: string | null;
+  count: number;
+  total_count: number;
+}
+
+export interface NoteLink {
+  source_id: string;
+  source_title: string;
+  target_id: string | null;
+  target_title: string | null;
+  target_text: string;
+  heading: string | null;
+  alias: string | null;
+  line: number;
+  context: string;
+}
+
+export interface UnlinkedMention {
+  source_id: string;
+  source_title: string;
+  matched_text: string;
+  /** Byte offsets into the source note's content; pass back unchanged to linkMention. */
+  start: number;
+  end: number;
+  line: number;
+  context: string;
+}
+
+export type ScoreAggregation = "max" | "top_k_mean";
+
+export interface Passage {
+  text: string;
+  start: number;
+  end: number;
+}
+
+/** Character (not UTF-16) offsets. */
+export interface TextRange {
+  start: number;
+  end: number;
+}
+
 export interface SearchResult {
   id: string;
   title: string;
   snippet: string;
+  /** Hits within `snippet`. */
+  snippet_highlights: TextRange[];
+  /** Hits within the note's content, or the best passage for semantic matches. */
+  matches: TextRange[];
   score: number;
   match_type: "fulltext" | "semantic" | "both";
+  passage: Passage | null;
+  fulltext_rank: number | null;
+  fulltext_score: number | null;
+  semantic_rank: number | null;
+  semantic_score: number | null;
+  /** Present when searching with `explain`. */
+  explanation: ScoreExplanation | null;
+}
+
+/** Where a page of search results starts. */
+export type PageStart = { offset: number } | { cursor: string };
+
+export interface SearchPage {
+  results: SearchResult[];
+  /** Results across all pages. */
+  total: number;
+  fulltext_total: number | null;
+  /** Approximate: counts only the notes with the nearest passages. */
+  semantic_total: number | null;
+  next_cursor: string | null;
+}
+
+export type FusionMethod = "rrf" | "min_max" | "z_score";
+
+export interface FusionOptions {
+  method: FusionMethod;
+  rrf_k: number;
+  fulltext_weight: number;
+  semantic_weight: number;
+}
+
+export interface SignalScore {
+  rank: number;
+  score: number;
+  normalized: number;
+  weight: number;
+  contribution: number;
+}
+
+export interface ScoreExplanation {
+  /** Null for single-mode searches. */
+  fusion: FusionOptions | null;
+  fulltext: SignalScore | null;
+  semantic: SignalScore | null;
 }
 
 export interface LatentLink {
@@ -38,6 +174,7 @@ export interface LatentLink {
   title: string;
   similarity: number;
   snippet: string;
+  passage: Passage;
 }
 
 export interface GraphNode {
@@ -59,17 +196,48 @@ export interface GraphData {
   edges: GraphEdge[];
 }
 
+export type ChunkingPolicy = "markdown" | "window";
+export type CodeBlockPolicy = "keep" | "drop";
+export type LinkPolicy = "text" | "text_and_url";
+
 export interface Settings {
   watch_directory: string | null;
   similarity_threshold: number;
   debounce_ms: number;
   theme: "dark" | "light";
+  trash_retention_days: number;
+  revision_max_per_note: number;
+  revision_max_age_days: number;
+  chunking_policy: ChunkingPolicy;
+  plain_text_code_blocks: CodeBlockPolicy;
+  plain_text_links: LinkPolicy;
+  fts_plain_text: boolean;
+  embedding_sessions: number;
+  model_directory: string | null;
+  search_fusion: FusionMethod;
+  search_rrf_k: number;
+  search_fulltext_weight: number;
+  search_semantic_weight: number;
+  search_min_similarity: number;
 }
 
 export interface SettingsPatch {
   similarity_threshold?: number;
   debounce_ms?: number;
   theme?: "dark" | "light";
+  trash_retention_days?: number;
+  revision_max_per_note?: number;
+  revision_max_age_days?: number;
+  chunking_policy?: ChunkingPolicy;
+  plain_text_code_blocks?: CodeBlockPolicy;
+  plain_text_links?: LinkPolicy;
+  fts_plain_text?: boolean;
+  embedding_sessions?: number;
+  search_fusion?: FusionMethod;
+  search_rrf_k?: number;
+  search_fulltext_weight?: number;
+  search_semantic_weight?: number;
+  search_min_similarity?: number;
 }
 
 // --- Error Types ---
@@ -93,6 +261,25 @@ export interface IndexingProgressPayload {
   processed: number;
   total: number;
   current_note_title: string;
+  notes_per_second: number | null;
+  eta_seconds: number | null;
+}
+
+export interface QueueStatus {
+  pending: number;
+  interactive: number;
+  processed: number;
+  paused: boolean;
+  notes_per_second: number | null;
+  eta_seconds: number | null;
+  current_note_title: string | null;
+}
+
+export interface EmbeddingStatus {
+  available: boolean;
+  model_version: string | null;
+  model_dir: string | null;
+  error: string | null;
 }
 
 export interface FileChangePayload {
@@ -112,16 +299,82 @@ export const ipc = {
   updateNote: (id: string, title?: string, content?: string) =>
     invoke<Note>("update_note", { id, title, content }),
 
-  deleteNote: (id: string) => invoke<void>("delete_note", { id }),
+  moveNote: (id: string, newTitle?: string, newPath?: string) =>
+    invoke<Note>("move_note", { id, newTitle, newPath }),
 
-  listNotes: (offset?: number, limit?: number, sortBy?: string) =>
-    invoke<NoteList>("list_notes", { offset, limit, sortBy }),
-
-  searchNotes: (query: string, mode?: "hybrid" | "fulltext" | "semantic", limit?: number) =>
-    invoke<SearchResult[]>("search_notes", { query, mode, limit }),
+  deleteNote: (id: string) => invoke<void>("delete_note", { id }),
 
-  getLatentLinks: (content: string, excludeNoteId?: string, threshold?: number, limit?: number) =>
-    invoke<LatentLink[]>("get_latent_links", { content, excludeNoteId, threshold, limit }),
+  listTrash: () => invoke<TrashedNote[]>("list_trash"),
+
+  restoreNote: (id: string) => invoke<Note>("restore_note", { id }),
+
+  emptyTrash: () => invoke<number>("empty_trash"),
+
+  listRevisions: (noteId: string) => invoke<RevisionSummary[]>("list_revisions", { noteId }),
+
+  diffRevisions: (noteId: string, fromRevisionId: number, toRevisionId?: number) =>
+    invoke<RevisionDiff>("diff_revisions", { noteId, fromRevisionId, toRevisionId }),
+
+  restoreRevision: (noteId: string, revisionId: number) =>
+    invoke<Note>("restore_revision", { noteId, revisionId }),
+
+  listNotes: (
+    offset?: number,
+    limit?: number,
+    sortBy?: string,
+    tag?: string,
+    folder?: string,
+    recursive?: boolean,
+  ) => invoke<NoteList>("list_notes", { offset, limit, sortBy, tag, folder, recursive }),
+
+  getNoteTree: (folder?: string, depth?: number) =>
+    invoke<FolderNode>("get_note_tree", { folder, depth }),
+
+  listTags: () => invoke<TagInfo[]>("list_tags"),
+
+  renameTag: (from: string, to: string) =>
+    invoke<number>("rename_tag", { from, to }),
+
+  getBacklinks: (noteId: string) =>
+    invoke<NoteLink[]>("get_backlinks", { noteId }),
+
+  getOutgoingLinks: (noteId: string) =>
+    invoke<NoteLink[]>("get_outgoing_links", { noteId }),
+
+  getUnlinkedMentions: (noteId: string) =>
+    invoke<UnlinkedMention[]>("get_unlinked_mentions", { noteId }),
+
+  linkMention: (sourceId: string, targetId: string, start: number, end: number) =>
+    invoke<Note>("link_mention", { sourceId, targetId, start, end }),
+
+  searchNotes: (
+    query: string,
+    mode?: "hybrid" | "fulltext" | "semantic",
+    limit?: number,
+    page?: PageStart,
+    tag?: string,
+    aggregation?: ScoreAggregation,
+    minSimilarity?: number,
+    explain?: boolean,
+  ) =>
+    invoke<SearchPage>("search_notes", {
+      query,
+      mode,
+      limit,
+      page,
+      tag,
+      aggregation,
+      minSimilarity,
+      explain,
+    }),
+
+  getLatentLinks: (
+    content: string,
+    excludeNoteId?: string,
+    threshold?: number,
+    limit?: number,
+    aggregation?: ScoreAggregation,
+  ) => invoke<LatentLink[]>("get_latent_links", { content, excludeNoteId, threshold, limit, aggregation }),
 
   getGraphData: (centerNoteId?: string, threshold?: number) =>
     invoke<GraphData>("get_graph_data", { centerNoteId, threshold }),
@@ -140,7 +393,20 @@ export const ipc = {
 
   updateSettings: (settings: SettingsPatch) => invoke<void>("update_settings", { settings }),
 
-  reindexAll: () => invoke<{ queued: boolean }>("reindex_all"),
+  reindexAll: (force?: boolean) => invoke<{ queued: boolean }>("reindex_all", { force }),
+
+  pauseReindex: () => invoke<void>("pause_reindex"),
+
+  resumeReindex: () => invoke<void>("resume_reindex"),
+
+  cancelReindex: () => invoke<number>("cancel_reindex"),
+
+  getIndexQueue: () => invoke<QueueStatus>("get_index_queue"),
+
+  getEmbeddingStatus: () => invoke<EmbeddingStatus>("get_embedding_status"),
+
+  loadEmbeddingModel: (directory: string) =>
+    invoke<EmbeddingStatus>("load_embedding_model", { directory }),
 
   logFrontendError: (level: string, message: string, context?: string) =>
     invoke<void>("log_frontend_error", { level, message, context }),
@@ -157,6 +423,12 @@ export const events = {
   ): Promise<UnlistenFn> =>
     listen<IndexingProgressPayload>("indexing-progress", (event) => callback(event.payload)),
 
+  onIndexQueue: (callback: (payload: QueueStatus) => void): Promise<UnlistenFn> =>
+    listen<QueueStatus>("index-queue", (event) => callback(event.payload)),
+
+  onEmbeddingStatus: (callback: (payload: EmbeddingStatus) => void): Promise<UnlistenFn> =>
+    listen<EmbeddingStatus>("embedding-status", (event) => callback(event.payload)),
+
   onFileChange: (callback: (payload: FileChangePayload) => void): Promise<UnlistenFn> =>
     listen<FileChangePayload>("file-change", (event) => callback(event.payload)),
 };